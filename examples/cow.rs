@@ -60,7 +60,7 @@ fn qcow_info(path: &str) {
     os_reader.seek(SeekFrom::Start(1048576 + 1024)).unwrap();
     let mut bytes = vec![0; 1024];
 
-    os_reader.read(&mut bytes).unwrap();
+    os_reader.read_exact(&mut bytes).unwrap();
 
     println!(
         "All root directory info for each partition. Total: {}",
@@ -71,12 +71,13 @@ fn qcow_info(path: &str) {
             continue;
         }
         let os_reader = calf.os_reader(&info).unwrap();
+        let part_reader = os_reader.partition_reader(&entry);
 
-        let test = BufReader::new(os_reader);
+        let test = BufReader::new(part_reader);
 
         println!("entry: {entry:?}");
 
-        let mut ext4_reader = Ext4Reader::new(test, 4096, entry.offset_start).unwrap();
+        let mut ext4_reader = Ext4Reader::new(test, 4096, 0).unwrap();
         println!("Superblock: {:?}", ext4_reader.superblock().unwrap());
         let root = ext4_reader.root().unwrap();
 
@@ -0,0 +1,37 @@
+use calf::{
+    calf::{CalfReader, CalfReaderAction, QcowInfo},
+    format::header::CalfHeader,
+    map::map_json,
+};
+use std::{env, fs::File, io::BufReader, path::Path};
+
+/// Print the guest allocation map. Output is the same as `qemu-img map --output=json`
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() == 2 {
+        let path = &args[1];
+        if Path::new(path).is_file() {
+            qcow_map(path);
+        } else {
+            println!("This is not a file")
+        }
+    } else {
+        println!("Require QCOW input file!!")
+    }
+}
+
+fn qcow_map(path: &str) {
+    let reader = File::open(path).unwrap();
+    let buf = BufReader::new(reader);
+    let mut calf = CalfReader { fs: buf };
+
+    let info = QcowInfo {
+        header: calf.header().unwrap(),
+        level1_table: calf.level1_entries().unwrap(),
+    };
+
+    let mut os_reader = calf.os_reader(&info).unwrap();
+    let entries = os_reader.allocation_map().unwrap();
+    print!("{}", map_json(&entries));
+}
@@ -0,0 +1,338 @@
+use crate::{
+    calf::QcowInfo,
+    error::CalfError,
+    format::{
+        cluster::{ClusterLocation, cluster_location, decode_cluster},
+        extensions::extension::Extensions,
+        header::Header,
+        level::Level,
+    },
+    reader::{missing_level1, seek_position},
+    utils::read::read_bytes_async,
+};
+use std::{
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncSeek, BufReader, ReadBuf};
+
+/// Async version of `CalfReader`. Requires the `tokio` feature
+pub struct AsyncCalfReader<T: AsyncRead + AsyncSeek + Unpin> {
+    pub fs: BufReader<T>,
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin + Send + 'static> AsyncCalfReader<T> {
+    /// Grab QCOW header info
+    pub async fn header(&mut self) -> Result<Header, CalfError> {
+        let size = 112;
+        let bytes = read_bytes_async(0, size, &mut self.fs).await?;
+        Header::grab_header(&bytes)
+    }
+
+    /// List extensions associated with the QCOW file
+    pub async fn extensions(&mut self) -> Result<Extensions, CalfError> {
+        let size = 512;
+        let offset = 112;
+        let bytes = read_bytes_async(offset, size, &mut self.fs).await?;
+        Extensions::grab_extensions(&bytes, offset)
+    }
+
+    /// List QCOW level one entries
+    pub async fn level1_entries(&mut self) -> Result<Vec<Level>, CalfError> {
+        let header = self.header().await?;
+        let bytes = read_bytes_async(
+            header.level_one_table_offset,
+            header.level_one_table_ref as u64,
+            &mut self.fs,
+        )
+        .await?;
+        Level::grab_levels(&bytes, header.level_one_table_offset)
+    }
+
+    /// Create an async reader that can read bytes from the guest OS. Takes ownership of the QCOW file
+    pub fn os_reader(self, info: Arc<QcowInfo>) -> AsyncOsReader<T> {
+        let cluster_bits = info.header.cluster_block_bits_count;
+        AsyncOsReader {
+            os_size: info.header.size,
+            qcow: info,
+            fs: Some(self.fs),
+            pending: None,
+            position: 0,
+            cluster_bits,
+            cluster_size: 1 << cluster_bits,
+            level2_cache: None,
+            cluster_key: None,
+            cluster_bytes: Vec::new(),
+        }
+    }
+}
+
+type LoadFuture<T> = Pin<Box<dyn Future<Output = LoadedCluster<T>> + Send>>;
+
+/// Level 2 table cached with the level 1 key it belongs to
+type Level2Cache = Option<(u64, Vec<Level>)>;
+
+/// The QCOW file is moved into the load future and handed back when the cluster is read
+struct LoadedCluster<T> {
+    fs: BufReader<T>,
+    cluster_key: u64,
+    level2_cache: Level2Cache,
+    bytes: io::Result<Vec<u8>>,
+}
+
+/// Async reader for the guest OS inside the QCOW file. Implements `AsyncRead` and `AsyncSeek`
+pub struct AsyncOsReader<T: AsyncRead + AsyncSeek + Unpin> {
+    qcow: Arc<QcowInfo>,
+    fs: Option<BufReader<T>>,
+    pending: Option<LoadFuture<T>>,
+    position: u64,
+    cluster_bits: u32,
+    cluster_size: u64,
+    os_size: u64,
+    level2_cache: Level2Cache,
+    cluster_key: Option<u64>,
+    cluster_bytes: Vec<u8>,
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin + Send + 'static> AsyncRead for AsyncOsReader<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let reader = self.get_mut();
+        loop {
+            if reader.position >= reader.os_size || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            let cluster_key = reader.position / reader.cluster_size;
+            if reader.cluster_key == Some(cluster_key) {
+                let position_in_cluster = reader.position % reader.cluster_size;
+                let cluster_bytes_remaining = u64::min(
+                    reader.cluster_size - position_in_cluster,
+                    reader.os_size - reader.position,
+                );
+                let read_len = u64::min(cluster_bytes_remaining, buf.remaining() as u64);
+                let read_start = position_in_cluster as usize;
+
+                buf.put_slice(&reader.cluster_bytes[read_start..read_start + read_len as usize]);
+                reader.position += read_len;
+                return Poll::Ready(Ok(()));
+            }
+
+            if reader.pending.is_none() {
+                let Some(fs) = reader.fs.take() else {
+                    return Poll::Ready(Err(io::Error::other(
+                        "QCOW file is not available for reading",
+                    )));
+                };
+                reader.pending = Some(Box::pin(load_cluster(
+                    fs,
+                    reader.qcow.clone(),
+                    cluster_key,
+                    reader.cluster_bits,
+                    reader.level2_cache.take(),
+                )));
+            }
+
+            if let Some(future) = reader.pending.as_mut() {
+                let loaded = match future.as_mut().poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => return Poll::Pending,
+                };
+                reader.pending = None;
+                reader.fs = Some(loaded.fs);
+                reader.level2_cache = loaded.level2_cache;
+                reader.cluster_bytes = loaded.bytes?;
+                reader.cluster_key = Some(loaded.cluster_key);
+                // A seek may have happened while loading. Loop again to check the cluster is still the one we need
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncSeek + Unpin + Send + 'static> AsyncSeek for AsyncOsReader<T> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let reader = self.get_mut();
+        reader.position = seek_position(reader.position, reader.os_size, position)?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// Load a guest cluster. Returns the QCOW file back when done
+async fn load_cluster<T: AsyncRead + AsyncSeek + Unpin>(
+    mut fs: BufReader<T>,
+    qcow: Arc<QcowInfo>,
+    cluster_key: u64,
+    cluster_bits: u32,
+    mut level2_cache: Level2Cache,
+) -> LoadedCluster<T> {
+    let bytes =
+        read_guest_cluster(&mut fs, &qcow, cluster_key, cluster_bits, &mut level2_cache).await;
+    LoadedCluster {
+        fs,
+        cluster_key,
+        level2_cache,
+        bytes,
+    }
+}
+
+/// Read a guest cluster using the same lookup and decode logic as the sync `OsReader`
+async fn read_guest_cluster<T: AsyncRead + AsyncSeek + Unpin>(
+    fs: &mut BufReader<T>,
+    qcow: &QcowInfo,
+    cluster_key: u64,
+    cluster_bits: u32,
+    level2_cache: &mut Level2Cache,
+) -> io::Result<Vec<u8>> {
+    let cluster_size = 1u64 << cluster_bits;
+    let size = 8;
+    let level2_entries = cluster_size / size;
+    let level1_key = cluster_key / level2_entries;
+    let level2_index = (cluster_key % level2_entries) as usize;
+
+    let level1 = qcow
+        .level1_table
+        .get(level1_key as usize)
+        .ok_or_else(|| missing_level1(cluster_key << cluster_bits))?;
+
+    if level1.offset != 0 && !matches!(level2_cache, Some((key, _)) if *key == level1_key) {
+        let bytes = read_bytes_async(level1.offset, cluster_size, fs).await?;
+        let table = Level::grab_levels(&bytes, level1.offset)?;
+        *level2_cache = Some((level1_key, table));
+    }
+
+    let level2 = level2_cache
+        .as_ref()
+        .and_then(|(_, table)| table.get(level2_index));
+    match cluster_location(level1, level2) {
+        ClusterLocation::Zeros => Ok(vec![0; cluster_size as usize]),
+        ClusterLocation::Host(offset) => {
+            let bytes = read_bytes_async(offset, cluster_size, fs).await?;
+            decode_cluster(
+                bytes,
+                &qcow.header.compression_method,
+                &level2.is_some_and(|entry| entry.is_compressed),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncCalfReader;
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        error::{CalfError, Offset},
+        format::header::CalfHeader,
+    };
+    use std::{io::Read, path::PathBuf, sync::Arc};
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+    #[tokio::test]
+    async fn test_async_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = tokio::fs::File::open(&test_location).await.unwrap();
+        let mut calf = AsyncCalfReader {
+            fs: BufReader::new(reader),
+        };
+        assert_eq!(calf.header().await.unwrap().version, 3);
+        assert!(calf.extensions().await.unwrap().features.is_empty());
+
+        let info = Arc::new(QcowInfo {
+            header: calf.header().await.unwrap(),
+            level1_table: calf.level1_entries().await.unwrap(),
+        });
+        let mut os_reader = calf.os_reader(info);
+
+        let mut bytes = Vec::new();
+        os_reader.read_to_end(&mut bytes).await.unwrap();
+
+        let sync_reader = std::fs::File::open(&test_location).unwrap();
+        let mut sync_calf = CalfReader {
+            fs: std::io::BufReader::new(sync_reader),
+        };
+        let sync_info = QcowInfo {
+            header: sync_calf.header().unwrap(),
+            level1_table: sync_calf.level1_entries().unwrap(),
+        };
+        let mut sync_os_reader = sync_calf.os_reader(&sync_info).unwrap();
+        let mut expected = Vec::new();
+        sync_os_reader.read_to_end(&mut expected).unwrap();
+
+        assert_eq!(bytes, expected);
+    }
+
+    #[tokio::test]
+    async fn test_async_seek() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = tokio::fs::File::open(&test_location).await.unwrap();
+        let mut calf = AsyncCalfReader {
+            fs: BufReader::new(reader),
+        };
+        let info = Arc::new(QcowInfo {
+            header: calf.header().await.unwrap(),
+            level1_table: calf.level1_entries().await.unwrap(),
+        });
+        let mut os_reader = calf.os_reader(info);
+
+        let status = os_reader.seek(std::io::SeekFrom::End(-20)).await.unwrap();
+        assert_eq!(status, 131052);
+
+        os_reader
+            .seek(std::io::SeekFrom::Start(65536 + 500))
+            .await
+            .unwrap();
+        let mut bytes = [0; 20];
+        os_reader.read_exact(&mut bytes).await.unwrap();
+        assert_eq!(bytes[..12], [4; 12]);
+        assert_eq!(bytes[12..], [0; 8]);
+    }
+
+    #[tokio::test]
+    async fn test_async_truncated() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/truncated.qcow");
+
+        let reader = tokio::fs::File::open(&test_location).await.unwrap();
+        let mut calf = AsyncCalfReader {
+            fs: BufReader::new(reader),
+        };
+        let info = Arc::new(QcowInfo {
+            header: calf.header().await.unwrap(),
+            level1_table: calf.level1_entries().await.unwrap(),
+        });
+        let mut os_reader = calf.os_reader(info);
+        let mut data = [0; 512];
+        os_reader.seek(std::io::SeekFrom::Start(512)).await.unwrap();
+        os_reader.read_exact(&mut data).await.unwrap();
+        assert_eq!(data, [2; 512]);
+
+        // Same error as the sync reader. Level 2 entry points past the end of the QCOW file
+        let err = os_reader.read_exact(&mut data).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(1048576));
+
+        // Level 2 table of the last clusters is cut off
+        os_reader
+            .seek(std::io::SeekFrom::Start(64 * 512))
+            .await
+            .unwrap();
+        let err = os_reader.read_exact(&mut data).await.unwrap_err();
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(2560));
+    }
+}
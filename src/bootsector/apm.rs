@@ -0,0 +1,267 @@
+use crate::{
+    bootsector::boot::{ApmPartition, BootInfo, BootType, BootWarning, Partition, PartitionType},
+    error::{CalfError, Offset, Structure},
+    reader::{OsReader, read_guest},
+    utils::strings::extract_utf8_string,
+};
+use log::warn;
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{be_u16, be_u32},
+};
+use std::io::{Read, Seek};
+
+/// Read the Apple Partition Map if the first block has an Apple driver descriptor.
+/// Returns None if there is no partition map. See: <https://en.wikipedia.org/wiki/Apple_Partition_Map>
+pub(crate) fn apm_info<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    descriptor: &[u8],
+) -> Option<BootInfo> {
+    let block_size = match parse_driver_descriptor(descriptor) {
+        Ok((_, result)) => result,
+        Err(_) => return None,
+    };
+
+    let first_entry = read_entry(reader, 1, block_size).ok()?;
+    // Every entry in the map includes the number of entries. Including the map itself
+    let map_entries = first_entry.0;
+    // Stop at a sane number of entries. The partition map partition is usually 63 blocks
+    let max_entries = 256;
+
+    let mut info = BootInfo {
+        boot_type: BootType::ApplePartitionMap,
+        partitions: Vec::new(),
+        sector_size: block_size,
+        gpt: None,
+        gpt_backup: None,
+        gpt_discrepancies: Vec::new(),
+        hybrid_mbr: Vec::new(),
+        warnings: Vec::new(),
+        disk_signature: None,
+        boot_code: None,
+    };
+    info.partitions
+        .push(apm_partition(first_entry.1, block_size));
+
+    for block in 2..=map_entries.min(max_entries) as u64 {
+        let entry = match read_entry(reader, block, block_size) {
+            Ok((_, result)) => result,
+            Err(err) => {
+                warn!("[calf] Could not read Apple partition map entry {block}: {err}");
+                info.warnings.push(BootWarning::ApmEntryUnreadable {
+                    offset: block * block_size,
+                    detail: err.to_string(),
+                });
+                break;
+            }
+        };
+        info.partitions.push(apm_partition(entry, block_size));
+    }
+
+    for part in &info.partitions {
+        if part.offset_start + part.partition_size > reader.os_size {
+            warn!(
+                "[calf] Apple partition at {} is larger than the guest disk",
+                part.offset_start
+            );
+            info.warnings.push(BootWarning::OutOfBounds {
+                offset: part.offset_start,
+                size: part.partition_size,
+            });
+        }
+    }
+
+    Some(info)
+}
+
+/// Read and parse a partition map entry at the provided block
+fn read_entry<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    block: u64,
+    block_size: u64,
+) -> Result<(u32, ApmPartition), CalfError> {
+    let offset = block * block_size;
+    let data = read_guest(reader, offset, block_size)?;
+    match parse_entry(&data) {
+        Ok((_, result)) => Ok(result),
+        Err(err) => Err(CalfError::parse(
+            Structure::ApmEntry,
+            Offset::Guest(offset),
+            &err,
+        )),
+    }
+}
+
+/// Parse the driver descriptor in block 0 and get the block size
+fn parse_driver_descriptor(data: &[u8]) -> nom::IResult<&[u8], u64> {
+    let (input, _) = tag(&b"ER"[..])(data)?;
+    let (input, block_size) = be_u16(input)?;
+    let (input, _block_count) = be_u32(input)?;
+
+    // Block size should always be set. Use the common size if not
+    let default_size = 512;
+    if block_size == 0 {
+        return Ok((input, default_size));
+    }
+    Ok((input, block_size as u64))
+}
+
+/// Parse a partition map entry. Returns the number of entries in the map and the partition
+fn parse_entry(data: &[u8]) -> nom::IResult<&[u8], (u32, ApmPartition)> {
+    let (input, _) = tag(&b"PM"[..])(data)?;
+    let (input, _padding) = be_u16(input)?;
+    let (input, map_entries) = be_u32(input)?;
+    let (input, start_block) = be_u32(input)?;
+    let (input, block_count) = be_u32(input)?;
+    let string_size: u8 = 32;
+    let (input, name) = take(string_size)(input)?;
+    let (input, partition_type) = take(string_size)(input)?;
+    let (input, data_start) = be_u32(input)?;
+    let (input, data_count) = be_u32(input)?;
+    let (input, status) = be_u32(input)?;
+
+    let entry = ApmPartition {
+        name: extract_cstring(name),
+        partition_type: extract_cstring(partition_type),
+        start_block,
+        block_count,
+        data_start,
+        data_count,
+        status,
+    };
+    Ok((input, (map_entries, entry)))
+}
+
+/// Strings are NULL terminated. Bytes after the NULL may be garbage
+fn extract_cstring(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(data.len());
+    extract_utf8_string(&data[..end])
+}
+
+/// Convert a partition map entry into a `Partition`
+fn apm_partition(entry: ApmPartition, block_size: u64) -> Partition {
+    // Bit 1 marks the partition as valid and bit 3 as bootable
+    let bootable = 0x8;
+
+    Partition {
+        partition_type: get_apm_partition_type(&entry.partition_type),
+        partition_type_value: 0,
+        type_name: entry.partition_type.clone(),
+        first_sector_offset: 0,
+        last_sector_offset: 0,
+        first_logical_offset: entry.start_block,
+        offset_start: entry.start_block as u64 * block_size,
+        sectors_in_partition: entry.block_count,
+        partition_size: entry.block_count as u64 * block_size,
+        bootable: entry.status & bootable != 0,
+        first_chs: None,
+        last_chs: None,
+        chs_mismatch: false,
+        gpt: None,
+        bsd: None,
+        apm: Some(entry),
+        children: Vec::new(),
+        filesystem: None,
+        bitlocker: None,
+    }
+}
+
+/// Determine the partition type from the partition map type string
+fn get_apm_partition_type(partition_type: &str) -> PartitionType {
+    match partition_type {
+        "Apple_partition_map" => PartitionType::ApplePartitionMap,
+        "Apple_HFS" | "Apple_HFSX" => PartitionType::HfsPlus,
+        "Apple_Boot" | "Apple_Bootstrap" => PartitionType::AppleBoot,
+        // Linux on PowerPC uses the A/UX type
+        "Apple_UNIX_SVR2" => PartitionType::Linux,
+        "Apple_Free" | "Apple_Void" => PartitionType::None,
+        value if value.starts_with("Apple_Driver") => PartitionType::AppleDriver,
+        _ => PartitionType::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_apm_partition_type, parse_driver_descriptor, parse_entry};
+    use crate::{
+        bootsector::boot::{BootType, PartitionType},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        reader::read_guest,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_parse_entry() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/apm/apm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let data = read_guest(&mut os_reader, 0, 512).unwrap();
+        let (_, block_size) = parse_driver_descriptor(&data).unwrap();
+        assert_eq!(block_size, 512);
+
+        let data = read_guest(&mut os_reader, 3 * 512, 512).unwrap();
+        let (_, (map_entries, entry)) = parse_entry(&data).unwrap();
+        assert_eq!(map_entries, 4);
+        assert_eq!(entry.name, "Macintosh HD");
+        assert_eq!(entry.partition_type, "Apple_HFS");
+        assert_eq!(entry.start_block, 120);
+        assert_eq!(entry.block_count, 1800);
+        assert_eq!(entry.data_count, 1800);
+        assert!(parse_entry(&[0; 512]).is_err());
+    }
+
+    #[test]
+    fn test_boot_info_apm() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/apm/apm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.boot_type, BootType::ApplePartitionMap);
+        assert_eq!(results.partitions.len(), 4);
+        assert_eq!(
+            results.partitions[0].partition_type,
+            PartitionType::ApplePartitionMap
+        );
+        assert_eq!(
+            results.partitions[1].partition_type,
+            PartitionType::AppleDriver
+        );
+        assert_eq!(results.partitions[2].partition_type, PartitionType::HfsPlus);
+        assert_eq!(results.partitions[2].offset_start, 120 * 512);
+        assert_eq!(results.partitions[2].partition_size, 1800 * 512);
+        assert_eq!(results.partitions[2].type_name, "Apple_HFS");
+        assert_eq!(
+            results.partitions[2].apm.as_ref().unwrap().name,
+            "Macintosh HD"
+        );
+        assert_eq!(results.partitions[3].partition_type, PartitionType::None);
+        assert!(results.warnings.is_empty());
+        assert_eq!(
+            get_apm_partition_type("Apple_Driver_ATA"),
+            PartitionType::AppleDriver
+        );
+    }
+}
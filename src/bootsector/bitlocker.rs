@@ -0,0 +1,333 @@
+use crate::{
+    bootsector::boot::{
+        BitLockerEncryption, BitLockerInfo, KeyProtector, Partition, ProtectionType,
+    },
+    error::{CalfError, Offset, Structure},
+    reader::{OsReader, read_guest},
+    utils::{guid::format_guid, strings::extract_utf16_string},
+};
+use log::warn;
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u16, le_u32, le_u64},
+};
+use std::io::{Read, Seek};
+
+/// Read the FVE metadata of an encrypted volume. The first metadata copy that parses is used
+pub(crate) fn bitlocker_info<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    part: &Partition,
+) -> Result<BitLockerInfo, CalfError> {
+    let header_size = 512;
+    let header = read_guest(reader, part.offset_start, header_size)?;
+    let offsets = metadata_offsets(&header);
+    if offsets.is_empty() {
+        return Err(CalfError::Parse {
+            structure: Structure::BitLockerMetadata,
+            offset: Offset::Guest(part.offset_start),
+            detail: String::from("volume header has no metadata offsets"),
+        });
+    }
+
+    let mut last_error = None;
+    for offset in offsets {
+        let metadata_offset = part.offset_start.checked_add(offset);
+        let Some(metadata_offset) = metadata_offset.filter(|_| offset < part.partition_size) else {
+            warn!("[calf] BitLocker metadata copy at {offset} is past the end of the volume");
+            last_error = Some(CalfError::Parse {
+                structure: Structure::BitLockerMetadata,
+                offset: Offset::Guest(part.offset_start),
+                detail: format!("metadata offset {offset} is past the end of the volume"),
+            });
+            continue;
+        };
+        match read_metadata(reader, metadata_offset) {
+            Ok(info) => return Ok(info),
+            Err(err) => {
+                warn!("[calf] Could not read BitLocker metadata copy at {offset}: {err}");
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(last_error.unwrap_or(CalfError::Parse {
+        structure: Structure::BitLockerMetadata,
+        offset: Offset::Guest(part.offset_start),
+        detail: String::from("no valid metadata copy"),
+    }))
+}
+
+/// Get the metadata offsets from the volume header. Vista only stores the cluster of the first copy
+fn metadata_offsets(header: &[u8]) -> Vec<u64> {
+    let get_u64 = |offset: usize| {
+        header
+            .get(offset..offset + 8)
+            .and_then(|value| value.try_into().ok())
+            .map(u64::from_le_bytes)
+            .unwrap_or_default()
+    };
+    let offsets = if has_bitlocker_guid(header, 160) {
+        vec![get_u64(176), get_u64(184), get_u64(192)]
+    } else if has_bitlocker_guid(header, 424) {
+        // BitLocker To Go volume header looks like FAT32
+        vec![get_u64(440), get_u64(448), get_u64(456)]
+    } else if header.get(3..11) == Some(b"-FVE-FS-") {
+        let bytes_per_sector = u64::from(u16::from_le_bytes([header[11], header[12]]));
+        let sectors_per_cluster = u64::from(header[13]);
+        let cluster = get_u64(56);
+        let offset = cluster
+            .checked_mul(bytes_per_sector)
+            .and_then(|value| value.checked_mul(sectors_per_cluster));
+        if offset.is_none() {
+            warn!("[calf] BitLocker metadata cluster {cluster} is too large");
+        }
+        offset.into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    offsets.into_iter().filter(|offset| *offset != 0).collect()
+}
+
+/// Windows 7 and newer and To Go volume headers have the BDE identifier GUID
+pub(crate) fn has_bitlocker_guid(header: &[u8], offset: usize) -> bool {
+    let bitlocker_guid = "4967D63B-2E29-4AD8-8399-F6A339E3D001";
+    header
+        .get(offset..offset + 16)
+        .and_then(|value| value.try_into().ok())
+        .is_some_and(|value| format_guid(value) == bitlocker_guid)
+}
+
+/// Read a metadata block header, the metadata header, and the metadata entries
+fn read_metadata<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    offset: u64,
+) -> Result<BitLockerInfo, CalfError> {
+    let headers_size = 112;
+    let data = read_guest(reader, offset, headers_size)?;
+    let (_, (mut info, metadata_size)) = match parse_headers(&data) {
+        Ok(result) => result,
+        Err(err) => {
+            return Err(CalfError::parse(
+                Structure::BitLockerMetadata,
+                Offset::Guest(offset),
+                &err,
+            ));
+        }
+    };
+
+    // Metadata size includes the 48 byte metadata header
+    let metadata_header_size = 48;
+    let max_size = 65536;
+    if !(metadata_header_size..=max_size).contains(&metadata_size) {
+        return Err(CalfError::Parse {
+            structure: Structure::BitLockerMetadata,
+            offset: Offset::Guest(offset),
+            detail: format!("unexpected metadata size {metadata_size}"),
+        });
+    }
+    let entries = read_guest(
+        reader,
+        offset + headers_size,
+        u64::from(metadata_size - metadata_header_size),
+    )?;
+    parse_entries(&entries, &mut info);
+    Ok(info)
+}
+
+/// Parse the 64 byte block header and the 48 byte metadata header that follows it
+fn parse_headers(data: &[u8]) -> nom::IResult<&[u8], (BitLockerInfo, u32)> {
+    let (input, _) = tag(&b"-FVE-FS-"[..])(data)?;
+    let (input, _size) = le_u16(input)?;
+    let (input, version) = le_u16(input)?;
+    let (input, _current_state) = le_u16(input)?;
+    let (input, _next_state) = le_u16(input)?;
+    let (input, encrypted_size) = le_u64(input)?;
+    let (input, _unknown) = le_u32(input)?;
+    let (input, _backup_sectors) = le_u32(input)?;
+    let (input, first) = le_u64(input)?;
+    let (input, second) = le_u64(input)?;
+    let (input, third) = le_u64(input)?;
+    let (input, _backup_offset) = le_u64(input)?;
+
+    let (input, metadata_size) = le_u32(input)?;
+    let (input, _version) = le_u32(input)?;
+    let (input, _header_size) = le_u32(input)?;
+    let (input, _copy_size) = le_u32(input)?;
+    let guid_size: u8 = 16;
+    let (input, guid) = take(guid_size)(input)?;
+    let (input, _nonce_counter) = le_u32(input)?;
+    let (input, method) = le_u16(input)?;
+    let (input, _unknown) = le_u16(input)?;
+    let (input, creation_time) = le_u64(input)?;
+
+    let encryption_method = match method {
+        0x0000 => BitLockerEncryption::None,
+        0x8000 => BitLockerEncryption::Aes128Diffuser,
+        0x8001 => BitLockerEncryption::Aes256Diffuser,
+        0x8002 => BitLockerEncryption::Aes128Cbc,
+        0x8003 => BitLockerEncryption::Aes256Cbc,
+        0x8004 => BitLockerEncryption::Aes128Xts,
+        0x8005 => BitLockerEncryption::Aes256Xts,
+        _ => BitLockerEncryption::Unknown,
+    };
+    let info = BitLockerInfo {
+        version,
+        volume_guid: format_guid(guid.try_into().unwrap_or(&[0; 16])),
+        encryption_method,
+        creation_time,
+        description: None,
+        encrypted_size,
+        metadata_offsets: vec![first, second, third],
+        key_protectors: Vec::new(),
+    };
+    Ok((input, (info, metadata_size)))
+}
+
+/// Get the key protectors and description from the metadata entries. Other entries are skipped
+fn parse_entries(data: &[u8], info: &mut BitLockerInfo) {
+    let entry_header_size = 8;
+    let mut input = data;
+    while input.len() >= entry_header_size {
+        let size = usize::from(u16::from_le_bytes([input[0], input[1]]));
+        if size < entry_header_size || size > input.len() {
+            break;
+        }
+        let entry_type = u16::from_le_bytes([input[2], input[3]]);
+        let value_type = u16::from_le_bytes([input[4], input[5]]);
+        let value = &input[entry_header_size..size];
+        input = &input[size..];
+
+        let vmk = (0x0002, 0x0008);
+        let description = (0x0007, 0x0002);
+        if (entry_type, value_type) == vmk {
+            match parse_key_protector(value) {
+                Ok((_, protector)) => info.key_protectors.push(protector),
+                Err(err) => warn!("[calf] Could not parse BitLocker key protector: {err:?}"),
+            }
+        } else if (entry_type, value_type) == description {
+            info.description = Some(extract_utf16_string(value));
+        }
+    }
+}
+
+/// Volume master key entry value. Protected key data follows as nested entries
+fn parse_key_protector(data: &[u8]) -> nom::IResult<&[u8], KeyProtector> {
+    let guid_size: u8 = 16;
+    let (input, id) = take(guid_size)(data)?;
+    let (input, modified) = le_u64(input)?;
+    let (input, _unknown) = le_u16(input)?;
+    let (input, protection) = le_u16(input)?;
+
+    let protection = match protection {
+        0x0000 => ProtectionType::ClearKey,
+        0x0100 => ProtectionType::Tpm,
+        0x0200 => ProtectionType::StartupKey,
+        0x0500 => ProtectionType::TpmAndPin,
+        0x0800 => ProtectionType::RecoveryPassword,
+        0x2000 => ProtectionType::Password,
+        _ => ProtectionType::Unknown,
+    };
+    let protector = KeyProtector {
+        id: format_guid(id.try_into().unwrap_or(&[0; 16])),
+        protection,
+        modified,
+    };
+    Ok((input, protector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bitlocker_info, metadata_offsets};
+    use crate::{
+        bootsector::boot::{BitLockerEncryption, FilesystemType, ProtectionType},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_bitlocker_info() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/bitlocker/bitlocker.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        // First metadata copy is corrupt. The second copy is used
+        let part = &results.partitions[0];
+        let bitlocker = part.bitlocker.as_ref().unwrap();
+        assert_eq!(bitlocker.version, 2);
+        assert_eq!(
+            bitlocker.volume_guid,
+            "6F1D9C2E-3B4A-4C5D-8E7F-901A2B3C4D5E"
+        );
+        assert_eq!(bitlocker.encryption_method, BitLockerEncryption::Aes128Xts);
+        assert_eq!(bitlocker.creation_time, 0x01d9a1b2c3d4e5f5);
+        assert_eq!(
+            bitlocker.description.as_ref().unwrap(),
+            "WIN-CALF C: 10/18/2025"
+        );
+        assert_eq!(bitlocker.encrypted_size, 0x100000);
+        assert_eq!(bitlocker.metadata_offsets, vec![0x20000, 0x80000, 0x100000]);
+        assert_eq!(bitlocker.key_protectors.len(), 2);
+        assert_eq!(
+            bitlocker.key_protectors[0].id,
+            "1C3A2B4D-5E6F-4A70-8B91-A2B3C4D5E6F7"
+        );
+        assert_eq!(
+            bitlocker.key_protectors[0].protection,
+            ProtectionType::RecoveryPassword
+        );
+        assert_eq!(bitlocker.key_protectors[1].protection, ProtectionType::Tpm);
+
+        let to_go = &results.partitions[1];
+        assert_eq!(
+            to_go.filesystem.as_ref().unwrap().fs_type,
+            FilesystemType::BitLocker
+        );
+        let bitlocker = to_go.bitlocker.as_ref().unwrap();
+        assert_eq!(
+            bitlocker.encryption_method,
+            BitLockerEncryption::Aes128Diffuser
+        );
+        assert_eq!(bitlocker.metadata_offsets, vec![0x10000, 0x40000, 0x80000]);
+    }
+
+    #[test]
+    fn test_bitlocker_offsets_out_of_range() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/bitlocker/bitlocker.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        let mut part = results.partitions[0].clone();
+        part.partition_size = 0x20000;
+        let err = bitlocker_info(&mut os_reader, &part).unwrap_err();
+        assert!(err.to_string().contains("past the end of the volume"));
+
+        let mut header = vec![0; 512];
+        header[3..11].copy_from_slice(b"-FVE-FS-");
+        header[11..13].copy_from_slice(&512u16.to_le_bytes());
+        header[13] = 8;
+        header[56..64].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(metadata_offsets(&header).is_empty());
+        header[56..64].copy_from_slice(&16u64.to_le_bytes());
+        assert_eq!(metadata_offsets(&header), vec![16 * 512 * 8]);
+    }
+}
@@ -1,28 +1,118 @@
 use crate::{
-    bootsector::mbr::{parse_extended, parse_mbr},
-    error::CalfError,
+    bootsector::{
+        apm::apm_info,
+        bsd::bsd_partitions,
+        gpt::{gpt_partition, gpt_tables, hybrid_entries},
+        mbr::{check_chs, has_boot_signature, parse_extended, parse_mbr},
+        probe::{probe_partition, probe_partitions},
+    },
+    error::{CalfError, Offset, Structure},
     reader::OsReader,
 };
-use log::error;
-use std::io::{Read, Seek, SeekFrom};
+use log::{error, warn};
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{Read, Seek, SeekFrom},
+};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BootInfo {
     pub boot_type: BootType,
     pub partitions: Vec<Partition>,
+    /// Logical sector size used for partition offsets
+    pub sector_size: u64,
+    /// GPT header the partitions came from. The backup is used if the primary is corrupt. Only set for GPT disks
+    pub gpt: Option<GptHeader>,
+    /// Backup GPT header at the last LBA of the guest disk
+    pub gpt_backup: Option<GptHeader>,
+    /// Partitions that do not match between the primary and backup GPT
+    pub gpt_discrepancies: Vec<GptDiscrepancy>,
+    /// MBR partitions next to the protective partition. Only set for hybrid MBRs
+    pub hybrid_mbr: Vec<HybridEntry>,
+    /// Problems found while walking the partition tables
+    pub warnings: Vec<BootWarning>,
+    /// 32 bit disk signature at offset 440. Windows uses it to map drive letters in the `MountedDevices` registry key.
+    /// Only set for MBR and GPT disks
+    pub disk_signature: Option<u32>,
+    /// First 440 bytes of the MBR. Only set for MBR and GPT disks
+    pub boot_code: Option<BootCode>,
+}
+
+/// MBR boot code fingerprint. Unknown boot code may be a bootkit
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BootCode {
+    pub sha256: String,
+    pub loader: BootLoader,
+}
+
+/// Boot loader identified by the strings and message table in the boot code
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BootLoader {
+    /// Boot code is all zeros. Common for data disks and GPT disks that only boot with UEFI
+    Empty,
+    /// GRUB2 `boot.img`
+    Grub2,
+    /// Windows 7 and newer, including Windows 10
+    Windows7,
+    Syslinux,
+    Unknown,
+}
+
+/// Partition table problem that did not stop parsing
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BootWarning {
+    /// Extended boot record points to an extended boot record that was already read
+    ExtendedLoop { offset: u64 },
+    /// Stopped following extended boot records after the limit
+    ExtendedChainLimit { limit: usize },
+    /// Extended boot record or partition is outside the extended partition or guest disk
+    OutOfBounds { offset: u64, size: u64 },
+    /// Extended boot record could not be read or parsed
+    ExtendedUnreadable { offset: u64, detail: String },
+    /// BSD disklabel checksum does not match. The partitions are still returned
+    BsdLabelChecksum { offset: u64 },
+    /// Apple partition map entry could not be read or parsed. Later entries are skipped
+    ApmEntryUnreadable { offset: u64, detail: String },
+    /// GPT entry LBAs are too large to be byte offsets. The entry is skipped
+    GptEntryOutOfRange { first_lba: u64, last_lba: u64 },
+    /// Primary GPT points to a backup header that is not at the last LBA of the disk
+    GptBackupLocation { backup_lba: u64, last_lba: u64 },
+    /// Primary and backup GPT headers have different disk GUIDs
+    GptDiskGuidMismatch { primary: String, backup: String },
+    /// Primary GPT could not be read or is corrupt. Partitions come from the backup GPT
+    GptPrimaryCorrupt { detail: String },
+    /// Backup GPT could not be read or is corrupt. Partitions come from the primary GPT
+    GptBackupUnreadable { detail: String },
+    /// MBR does not end with 0x55AA and no filesystem was found at offset 0
+    MissingBootSignature,
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootType {
     MasterBootRecord,
     GuidPartitionTable,
+    ApplePartitionMap,
+    /// Filesystem or volume written directly at offset 0 (superfloppy). Reported as a single whole disk partition
+    Unpartitioned,
+    /// No partition table or filesystem was found
+    None,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Partition {
     pub partition_type: PartitionType,
     pub partition_type_value: u8,
+    /// Human readable name of the MBR partition ID or GPT type GUID
+    pub type_name: String,
+    /// Raw 3 byte CHS value. See `first_chs`
     pub first_sector_offset: u32,
+    /// Raw 3 byte CHS value. See `last_chs`
     pub last_sector_offset: u32,
     pub first_logical_offset: u32,
     /**Offset to partition data */
@@ -30,12 +120,238 @@ pub struct Partition {
     pub sectors_in_partition: u32,
     pub partition_size: u64,
     pub bootable: bool,
+    /// Decoded CHS start address. Only set for MBR partitions
+    pub first_chs: Option<Chs>,
+    /// Decoded CHS end address. Only set for MBR partitions
+    pub last_chs: Option<Chs>,
+    /// CHS and LBA values do not agree for any disk geometry. Common for hand edited partition tables
+    pub chs_mismatch: bool,
+    /// Only set for GPT partitions
+    pub gpt: Option<GptPartition>,
+    /// Only set for partitions from a BSD disklabel
+    pub bsd: Option<BsdPartition>,
+    /// Only set for Apple Partition Map partitions
+    pub apm: Option<ApmPartition>,
+    /// Partitions nested inside this partition. Set for BSD slices with a disklabel
+    pub children: Vec<Partition>,
+    /// Filesystem found by probing the partition data. May not match the partition type
+    pub filesystem: Option<Filesystem>,
+    /// Only set for BDE encrypted volumes
+    pub bitlocker: Option<BitLockerInfo>,
+}
+
+/// Filesystem or volume signature found in the partition data
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Filesystem {
+    pub fs_type: FilesystemType,
+    pub label: Option<String>,
+    /// UUID, GUID, or volume serial number depending on the filesystem
+    pub uuid: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FilesystemType {
+    Ext2,
+    Ext3,
+    Ext4,
+    Xfs,
+    Btrfs,
+    Ntfs,
+    Fat12,
+    Fat16,
+    Fat32,
+    ExFat,
+    Swap,
+    LvmPv,
+    Luks1,
+    Luks2,
+    Zfs,
+    Iso9660,
+    BitLocker,
 }
 
-#[derive(PartialEq, Debug, Clone)]
+/// Cylinder, head, sector address from an MBR partition entry
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Chs {
+    pub cylinder: u16,
+    pub head: u8,
+    pub sector: u8,
+}
+
+/// GUID Partition Table header: <https://uefi.org/specs/UEFI/2.10/05_GUID_Partition_Table_Format.html>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: String,
+    pub entries_lba: u64,
+    pub entries_count: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GptPartition {
+    pub type_guid: String,
+    pub unique_guid: String,
+    pub first_lba: u64,
+    /// Last LBA is inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+/// BSD disklabel partition entry
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BsdPartition {
+    /// Partition letter. Usually `a` is root and `b` is swap
+    pub letter: char,
+    /// Raw offset in sectors. FreeBSD offsets may be relative to the slice
+    pub offset: u64,
+    /// Size in sectors
+    pub size: u64,
+    pub fstype: u8,
+    /// Filesystem fragment size. OpenBSD stores the high 16 bits of the offset and size here instead and uses 0
+    pub fsize: u32,
+    /// Filesystem blocks per fragment
+    pub frag: u8,
+    /// Filesystem cylinders per group
+    pub cpg: u16,
+}
+
+/// Apple Partition Map entry. Offsets are in blocks of the driver descriptor block size
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApmPartition {
+    pub name: String,
+    /// Type string such as `Apple_HFS`
+    pub partition_type: String,
+    pub start_block: u32,
+    pub block_count: u32,
+    /// First block of data relative to the start of the partition
+    pub data_start: u32,
+    pub data_count: u32,
+    pub status: u32,
+}
+
+/// Full volume encryption (FVE) metadata of Windows BDE volumes: <https://github.com/libyal/libbde/blob/main/documentation/BitLocker%20Drive%20Encryption%20(BDE)%20format.asciidoc>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BitLockerInfo {
+    /// 1 for Windows Vista. 2 for Windows 7 and newer
+    pub version: u16,
+    pub volume_guid: String,
+    pub encryption_method: BitLockerEncryption,
+    /// Windows FILETIME when encryption was enabled
+    pub creation_time: u64,
+    /// Computer name, drive letter, and date when encryption was enabled
+    pub description: Option<String>,
+    /// Bytes encrypted so far. Less than the volume size while encryption is in progress
+    pub encrypted_size: u64,
+    /// Offsets of the three FVE metadata copies from the start of the volume
+    pub metadata_offsets: Vec<u64>,
+    /// Volume master key protectors. Recovery password IDs are needed to look up recovery keys
+    pub key_protectors: Vec<KeyProtector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BitLockerEncryption {
+    None,
+    Aes128Diffuser,
+    Aes256Diffuser,
+    Aes128Cbc,
+    Aes256Cbc,
+    Aes128Xts,
+    Aes256Xts,
+    Unknown,
+}
+
+/// Volume master key protector
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyProtector {
+    /// Key protector ID. Windows shows the first group as the recovery key ID
+    pub id: String,
+    pub protection: ProtectionType,
+    /// Windows FILETIME
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProtectionType {
+    /// Key is stored unprotected. Used when protection is suspended
+    ClearKey,
+    Tpm,
+    StartupKey,
+    TpmAndPin,
+    RecoveryPassword,
+    Password,
+    Unknown,
+}
+
+/// MBR partition in a hybrid MBR and the GPT partition it overlaps
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HybridEntry {
+    pub status: HybridStatus,
+    pub mbr: Partition,
+    pub gpt: Option<GptPartition>,
+}
+
+/// How a hybrid MBR partition compares to the GPT
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HybridStatus {
+    /// Same first and last LBA as a GPT partition
+    Match,
+    /// Overlaps a GPT partition but the boundaries are different
+    BoundaryMismatch,
+    /// Not in the GPT. Hidden from GPT aware tools
+    MbrOnly,
+}
+
+/// Partition that is different in the primary and backup GPT. Matched by unique GUID
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GptDiscrepancy {
+    pub discrepancy_type: DiscrepancyType,
+    pub primary: Option<GptPartition>,
+    pub backup: Option<GptPartition>,
+}
+
+/// Changes in the primary GPT compared to the backup GPT
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiscrepancyType {
+    /// Partition is only in the primary GPT
+    Added,
+    /// Partition is only in the backup GPT
+    Removed,
+    /// First or last LBA is different
+    Resized,
+    /// Type GUID, attributes, or name is different
+    Modified,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PartitionType {
     Ntfs,
     Linux,
+    #[default]
     Unknown,
     Fat16,
     Fat32,
@@ -45,117 +361,380 @@ pub enum PartitionType {
     LinuxSwap,
     LinuxLvm,
     None,
+    EfiSystem,
+    BiosBoot,
+    MicrosoftReserved,
+    MicrosoftBasicData,
+    Fat12,
+    HiddenFat12,
+    HiddenFat16,
+    HiddenFat32,
+    HiddenNtfs,
+    WindowsRecovery,
+    WindowsDynamic,
+    LinuxRaid,
+    FreeBsd,
+    OpenBsd,
+    NetBsd,
+    Solaris,
+    HfsPlus,
+    AppleBoot,
+    VmwareVmfs,
+    Ufs,
+    BsdSwap,
+    ApplePartitionMap,
+    AppleDriver,
 }
 
-/// Get the bootsector info from the QCOW file
+/// Get the bootsector info from the QCOW file. The logical sector size is detected if not provided
 pub(crate) fn boot_info<'qcow, 'reader, T: std::io::Seek + std::io::Read>(
     reader: &mut OsReader<'qcow, 'reader, T>,
+    sector_size: Option<u64>,
+) -> Result<BootInfo, CalfError> {
+    let sector_size = match sector_size {
+        Some(result) => result,
+        None => detect_sector_size(reader),
+    };
+
+    let mut boot = partition_tables(reader, sector_size)?;
+    probe_partitions(reader, &mut boot.partitions);
+    Ok(boot)
+}
+
+/// Read the MBR, GPT, or Apple Partition Map and any nested partition tables
+fn partition_tables<T: std::io::Seek + std::io::Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    sector_size: u64,
 ) -> Result<BootInfo, CalfError> {
     if let Err(err) = reader.seek(SeekFrom::Start(0)) {
         error!("[calf] Could not seek to start for boot info: {err:?}");
-        return Err(CalfError::SeekFile);
+        return Err(CalfError::SeekFile {
+            offset: Offset::Guest(0),
+            source: err,
+        });
     }
 
-    // 512 seems to be the most common
-    let sector_size = 512;
-    let mut mbr_buff = vec![0; sector_size];
+    // The MBR and EBR structures are always 512 bytes. Even on 4K native disks
+    let mbr_size = 512;
+    let mut mbr_buff = vec![0; mbr_size];
     if let Err(err) = reader.read(&mut mbr_buff) {
-        error!("[calf] Could not read MBR first {sector_size} bytes: {err:?}");
-        return Err(CalfError::ReadFile);
+        error!("[calf] Could not read MBR first {mbr_size} bytes: {err:?}");
+        return Err(CalfError::ReadFile {
+            offset: Offset::Guest(0),
+            source: err,
+        });
     }
 
-    let mut boot = match parse_mbr(&mbr_buff) {
+    // Apple Partition Maps start with a driver descriptor instead of boot code
+    if let Some(mut info) = apm_info(reader, &mbr_buff) {
+        fit_to_disk(
+            &mut info.partitions,
+            reader.os_size,
+            info.sector_size,
+            &mut info.warnings,
+        );
+        return Ok(info);
+    }
+
+    // FAT, NTFS, and exFAT boot sectors also end with 0x55AA. Their boot code would be read as partition entries
+    let whole_disk = whole_disk_partition(reader.os_size, sector_size);
+    let filesystem = probe_partition(reader, &whole_disk);
+    let is_boot_sector = filesystem.as_ref().is_some_and(|fs| {
+        matches!(
+            fs.fs_type,
+            FilesystemType::Ntfs
+                | FilesystemType::Fat12
+                | FilesystemType::Fat16
+                | FilesystemType::Fat32
+                | FilesystemType::ExFat
+                | FilesystemType::BitLocker
+        )
+    });
+    if !has_boot_signature(&mbr_buff) || is_boot_sector {
+        return Ok(unpartitioned(whole_disk, filesystem.is_some(), sector_size));
+    }
+
+    let mut boot = match parse_mbr(&mbr_buff, sector_size) {
         Ok((_, result)) => result,
         Err(err) => {
-            error!("[calf] Could not parse MBR first {sector_size} bytes: {err:?}");
-            return Err(CalfError::ParseMbr);
+            error!("[calf] Could not parse MBR first {mbr_size} bytes: {err:?}");
+            return Err(CalfError::parse(Structure::Mbr, Offset::Guest(0), &err));
         }
     };
 
+    if boot.boot_type == BootType::GuidPartitionTable {
+        if let Some(tables) = gpt_tables(reader, sector_size) {
+            let mbr_partitions = std::mem::take(&mut boot.partitions);
+            for entry in tables.entries {
+                let (first_lba, last_lba) = (entry.first_lba, entry.last_lba);
+                let Some(part) = gpt_partition(entry, sector_size) else {
+                    warn!(
+                        "[calf] GPT partition at LBA {first_lba} to {last_lba} is too large. Skipping it"
+                    );
+                    boot.warnings.push(BootWarning::GptEntryOutOfRange {
+                        first_lba,
+                        last_lba,
+                    });
+                    continue;
+                };
+                boot.partitions.push(part);
+            }
+            fit_to_disk(
+                &mut boot.partitions,
+                reader.os_size,
+                sector_size,
+                &mut boot.warnings,
+            );
+            boot.gpt = Some(tables.header);
+            boot.gpt_backup = tables.backup;
+            boot.gpt_discrepancies = tables.discrepancies;
+            boot.warnings.extend(tables.warnings);
+            boot.hybrid_mbr = hybrid_entries(&mbr_partitions, &boot.partitions, sector_size);
+            return Ok(boot);
+        }
+        warn!("[calf] Could not read the primary or backup GPT. Only returning the MBR");
+    }
+
+    fit_to_disk(
+        &mut boot.partitions,
+        reader.os_size,
+        sector_size,
+        &mut boot.warnings,
+    );
+
+    let mut visited = HashSet::new();
     let mut extra_parts = Vec::new();
-    let mut root_offset;
     // Second partition should be the extended type. There is only one
     for part in &boot.partitions {
         if part.partition_type != PartitionType::Extended {
             continue;
         }
-        // All additional extended partitions are relative from the first extended partition
-        root_offset = part.offset_start;
+        let mut logical =
+            logical_partitions(reader, part, sector_size, &mut visited, &mut boot.warnings);
+        extra_parts.append(&mut logical);
+    }
+    boot.partitions.append(&mut extra_parts);
+
+    for part in &mut boot.partitions {
+        if matches!(
+            part.partition_type,
+            PartitionType::FreeBsd | PartitionType::OpenBsd | PartitionType::NetBsd
+        ) {
+            part.children = bsd_partitions(reader, part, sector_size, &mut boot.warnings);
+        }
+    }
+
+    for part in &mut boot.partitions {
+        part.chs_mismatch = check_chs(part, sector_size);
+        if part.chs_mismatch {
+            warn!(
+                "[calf] Partition at offset {} has CHS values that do not match the LBA",
+                part.offset_start
+            );
+        }
+    }
+
+    Ok(boot)
+}
+
+/// Entries that start past the end of the guest disk are junk and are dropped. Entries that only run past the end are cut at the end.
+/// Both get an `OutOfBounds` warning unless the table already reported one for the entry
+pub(crate) fn fit_to_disk(
+    partitions: &mut Vec<Partition>,
+    os_size: u64,
+    sector_size: u64,
+    warnings: &mut Vec<BootWarning>,
+) {
+    partitions.retain_mut(|part| {
+        if matches!(
+            part.partition_type,
+            PartitionType::None | PartitionType::Protective
+        ) || part.offset_start.saturating_add(part.partition_size) <= os_size
+        {
+            return true;
+        }
+        warn!(
+            "[calf] Partition at {} is outside the guest disk",
+            part.offset_start
+        );
+        let warning = BootWarning::OutOfBounds {
+            offset: part.offset_start,
+            size: part.partition_size,
+        };
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+        if part.offset_start >= os_size {
+            return false;
+        }
+        part.partition_size = os_size - part.offset_start;
+        part.sectors_in_partition =
+            u32::try_from(part.partition_size / sector_size.max(1)).unwrap_or(u32::MAX);
+        true
+    });
+}
+
+/// Disk without a partition table. A filesystem at offset 0 is reported as a partition covering the whole disk
+fn unpartitioned(whole_disk: Partition, has_filesystem: bool, sector_size: u64) -> BootInfo {
+    let mut boot = BootInfo {
+        boot_type: BootType::Unpartitioned,
+        partitions: vec![whole_disk],
+        sector_size,
+        gpt: None,
+        gpt_backup: None,
+        gpt_discrepancies: Vec::new(),
+        hybrid_mbr: Vec::new(),
+        warnings: Vec::new(),
+        disk_signature: None,
+        boot_code: None,
+    };
+    if !has_filesystem {
+        warn!(
+            "[calf] MBR does not have the 0x55AA signature and there is no filesystem at offset 0"
+        );
+        boot.boot_type = BootType::None;
+        boot.partitions.clear();
+        boot.warnings.push(BootWarning::MissingBootSignature);
+    }
+    boot
+}
+
+/// Partition covering the whole guest disk. Used for filesystems written directly at offset 0
+fn whole_disk_partition(os_size: u64, sector_size: u64) -> Partition {
+    let sectors = os_size / sector_size;
+    Partition {
+        partition_type: PartitionType::Unknown,
+        partition_type_value: 0,
+        type_name: String::from("Whole disk"),
+        first_sector_offset: 0,
+        last_sector_offset: 0,
+        first_logical_offset: 0,
+        offset_start: 0,
+        sectors_in_partition: u32::try_from(sectors).unwrap_or(u32::MAX),
+        partition_size: os_size,
+        bootable: false,
+        first_chs: None,
+        last_chs: None,
+        chs_mismatch: false,
+        gpt: None,
+        bsd: None,
+        apm: None,
+        children: Vec::new(),
+        filesystem: None,
+        bitlocker: None,
+    }
+}
+
+/// Walk the extended boot record chain of an extended partition. Stops on loops, long chains, and records outside the disk
+fn logical_partitions<T: std::io::Seek + std::io::Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    extended: &Partition,
+    sector_size: u64,
+    visited: &mut HashSet<u64>,
+    warnings: &mut Vec<BootWarning>,
+) -> Vec<Partition> {
+    // Linux supports at most 256 partitions per disk
+    let max_chain = 256;
+    let mbr_size = 512;
+    // All additional extended partitions are relative from the first extended partition
+    let root_offset = extended.offset_start;
+    let extended_end = root_offset.saturating_add(extended.partition_size);
 
-        if let Err(err) = reader.seek(SeekFrom::Start(part.offset_start)) {
-            error!("[calf] Could not seek to extended partition: {err:?}");
-            return Err(CalfError::ExtendedPartition);
+    let mut parts = Vec::new();
+    let mut pending = VecDeque::from([root_offset]);
+    while let Some(offset) = pending.pop_front() {
+        if !visited.insert(offset) {
+            warn!("[calf] Extended boot record at {offset} was already read. Stopping loop");
+            warnings.push(BootWarning::ExtendedLoop { offset });
+            continue;
+        }
+        if visited.len() > max_chain {
+            warn!("[calf] Reached extended boot record limit of {max_chain}");
+            warnings.push(BootWarning::ExtendedChainLimit { limit: max_chain });
+            break;
+        }
+        if offset < root_offset || offset + mbr_size > extended_end.min(reader.os_size) {
+            warn!("[calf] Extended boot record at {offset} is outside the extended partition");
+            warnings.push(BootWarning::OutOfBounds {
+                offset,
+                size: mbr_size,
+            });
+            continue;
         }
-        let mut mbr_buff = vec![0; sector_size];
-        if let Err(err) = reader.read(&mut mbr_buff) {
-            error!("[calf] Could not read extended partition {sector_size} bytes: {err:?}");
-            return Err(CalfError::ReadFile);
+
+        let mut mbr_buff = vec![0; mbr_size as usize];
+        if let Err(err) = reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_exact(&mut mbr_buff))
+        {
+            error!("[calf] Could not read extended partition {mbr_size} bytes: {err:?}");
+            warnings.push(BootWarning::ExtendedUnreadable {
+                offset,
+                detail: err.to_string(),
+            });
+            continue;
         }
 
         // We pass the root_offset to ensure any additional extended partition entries are properly setup to point to the absolute offset (root_offset + extended partition relative offset)
-        let (mut ext_boot, mut has_extended) =
-            match parse_extended(&mbr_buff, root_offset, part.offset_start) {
-                Ok((_, result)) => result,
-                Err(err) => {
-                    error!(
-                        "[calf] Could not parse extended partition {sector_size} bytes: {err:?}"
-                    );
-                    return Err(CalfError::ExtendedPartition);
-                }
-            };
-        extra_parts.append(&mut ext_boot.clone());
+        // We also need the current absolute offset of our current extended partition to ensure we can properly calculate the offsets for any non-extended partition types
+        let ext_boot = match parse_extended(&mbr_buff, root_offset, offset, sector_size) {
+            Ok((_, (result, _))) => result,
+            Err(err) => {
+                error!("[calf] Could not parse extended partition {mbr_size} bytes: {err:?}");
+                warnings.push(BootWarning::ExtendedUnreadable {
+                    offset,
+                    detail: CalfError::parse(
+                        Structure::ExtendedPartition,
+                        Offset::Guest(offset),
+                        &err,
+                    )
+                    .to_string(),
+                });
+                continue;
+            }
+        };
 
         // Extended partitions may have a list that points to more extended partitions. These additional "partitions" are not real partitions they are just extensions of the first extended partition (linked list)
-        while has_extended {
-            has_extended = false;
-            let mut next_ext = Vec::new();
-            for entry in &ext_boot {
-                if entry.partition_type != PartitionType::Extended {
-                    continue;
-                }
-
-                // Our next extended partition is always relative from the first extended partition found in the Master Boot Record (MBR)
-                if let Err(err) = reader.seek(SeekFrom::Start(entry.offset_start)) {
-                    error!("[calf] Could not seek to next extended partition: {err:?}");
-                    return Err(CalfError::ExtendedPartition);
-                }
-                let mut mbr_buff = vec![0; sector_size];
-                if let Err(err) = reader.read(&mut mbr_buff) {
-                    error!("[calf] Could not read next extended {sector_size} bytes: {err:?}");
-                    return Err(CalfError::ReadFile);
-                }
-
-                // We pass the root_offset to ensure any additional extended partition entries are properly setup to point to the absolute offset (root_offset + extended partition relative offset)
-                // We also need the current absolute offset of our current extended partition to ensure we can properly calculate the offsets for any non-extended partition types
-                let (mut ext_boot, more_extended) =
-                    match parse_extended(&mbr_buff, root_offset, entry.offset_start) {
-                        Ok((_, result)) => result,
-                        Err(err) => {
-                            error!("[calf] Could not parse MBR first {sector_size} bytes: {err:?}");
-                            return Err(CalfError::ExtendedPartition);
-                        }
-                    };
-                has_extended = more_extended;
-
-                if has_extended {
-                    next_ext.append(&mut ext_boot.clone());
-                }
-
-                extra_parts.append(&mut ext_boot);
-            }
+        // Links to the next extended boot record are checked when the record is read
+        let (mut logical, links): (Vec<Partition>, Vec<Partition>) = ext_boot
+            .into_iter()
+            .partition(|entry| entry.partition_type != PartitionType::Extended);
+        for link in &links {
+            pending.push_back(link.offset_start);
+        }
+        fit_to_disk(&mut logical, reader.os_size, sector_size, warnings);
+        parts.append(&mut logical);
+        parts.extend(links);
+    }
+
+    parts
+}
 
-            ext_boot = next_ext;
+/// Detect the logical sector size by looking for the GPT header at LBA 1. Defaults to 512 bytes
+fn detect_sector_size<T: std::io::Seek + std::io::Read>(reader: &mut OsReader<'_, '_, T>) -> u64 {
+    let sizes = [512, 4096];
+    let sig = b"EFI PART";
+    for size in sizes {
+        let mut buf = [0; 8];
+        if reader.seek(SeekFrom::Start(size)).is_err() || reader.read_exact(&mut buf).is_err() {
+            continue;
+        }
+        if &buf == sig {
+            return size;
         }
     }
-    boot.partitions.append(&mut extra_parts);
 
-    Ok(boot)
+    // 512 seems to be the most common
+    512
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        bootsector::boot::boot_info,
+        bootsector::boot::{
+            BootType, BootWarning, FilesystemType, PartitionType, boot_info, fit_to_disk,
+            whole_disk_partition,
+        },
         calf::{CalfReader, CalfReaderAction, QcowInfo},
         format::header::CalfHeader,
     };
@@ -175,7 +754,7 @@ mod tests {
             level1_table: calf.level1_entries().unwrap(),
         };
         let mut os_reader = calf.os_reader(&info).unwrap();
-        let results = boot_info(&mut os_reader).unwrap();
+        let results = boot_info(&mut os_reader, None).unwrap();
 
         assert_eq!(results.partitions.len(), 12);
     }
@@ -196,4 +775,174 @@ mod tests {
         };
         let _os_reader = calf.os_reader(&info).unwrap();
     }
+
+    #[test]
+    fn test_fit_to_disk() {
+        let disk_size = 2048 * 512;
+        let mut inside = whole_disk_partition(1024 * 512, 512);
+        inside.partition_type = PartitionType::Linux;
+        let mut overrun = inside.clone();
+        overrun.offset_start = 1024 * 512;
+        overrun.partition_size = u64::MAX;
+        let mut past_end = inside.clone();
+        past_end.offset_start = disk_size;
+        let mut protective = past_end.clone();
+        protective.partition_type = PartitionType::Protective;
+
+        let mut parts = vec![inside.clone(), overrun, past_end, protective];
+        let mut warnings = vec![BootWarning::OutOfBounds {
+            offset: disk_size,
+            size: 1024 * 512,
+        }];
+        fit_to_disk(&mut parts, disk_size, 512, &mut warnings);
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], inside);
+        assert_eq!(parts[1].partition_size, 1024 * 512);
+        assert_eq!(parts[1].sectors_in_partition, 1024);
+        assert_eq!(parts[2].partition_type, PartitionType::Protective);
+        // Warning the table already pushed is not repeated
+        assert_eq!(
+            warnings,
+            vec![
+                BootWarning::OutOfBounds {
+                    offset: disk_size,
+                    size: 1024 * 512,
+                },
+                BootWarning::OutOfBounds {
+                    offset: 1024 * 512,
+                    size: u64::MAX,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_boot_info_ebr_loop() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/ebr_loop.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = boot_info(&mut os_reader, None).unwrap();
+
+        // Logical partition running past the end of the disk ends at the end of the disk
+        let logical: Vec<(u64, u64)> = results.partitions[4..]
+            .iter()
+            .filter(|part| part.partition_type == PartitionType::Linux)
+            .map(|part| (part.offset_start, part.partition_size))
+            .collect();
+        assert_eq!(
+            logical,
+            vec![(528 * 512, 100 * 512), (728 * 512, (2048 - 728) * 512)]
+        );
+        assert_eq!(
+            results.warnings,
+            vec![
+                BootWarning::OutOfBounds {
+                    offset: 728 * 512,
+                    size: 5000 * 512
+                },
+                BootWarning::ExtendedLoop { offset: 512 * 512 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_boot_info_superfloppy() {
+        let fixtures = [
+            ("superfloppy_fat.qcow", Some(FilesystemType::Fat32)),
+            ("superfloppy_ext4.qcow", Some(FilesystemType::Ext4)),
+            ("blank.qcow", None),
+        ];
+        for (name, fs_type) in fixtures {
+            let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            test_location.push(format!("tests/test_data/mbr/{name}"));
+
+            let reader = File::open(test_location.to_str().unwrap()).unwrap();
+            let buf = BufReader::new(reader);
+
+            let mut calf = CalfReader { fs: buf };
+            let info = QcowInfo {
+                header: calf.header().unwrap(),
+                level1_table: calf.level1_entries().unwrap(),
+            };
+            let mut os_reader = calf.os_reader(&info).unwrap();
+            let results = boot_info(&mut os_reader, None).unwrap();
+
+            let Some(fs_type) = fs_type else {
+                assert_eq!(results.boot_type, BootType::None);
+                assert!(results.partitions.is_empty());
+                assert_eq!(results.warnings, vec![BootWarning::MissingBootSignature]);
+                continue;
+            };
+            assert_eq!(results.boot_type, BootType::Unpartitioned);
+            assert_eq!(results.partitions.len(), 1);
+            let part = &results.partitions[0];
+            assert_eq!(part.offset_start, 0);
+            assert_eq!(part.partition_size, 1048576);
+            assert_eq!(part.sectors_in_partition, 2048);
+            assert_eq!(part.filesystem.as_ref().unwrap().fs_type, fs_type);
+            assert!(results.warnings.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_boot_info_out_of_bounds() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/out_of_bounds.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = boot_info(&mut os_reader, None).unwrap();
+
+        // Partition starting past the end is dropped. The partition running past the end ends at the end of the disk
+        assert_eq!(results.boot_type, BootType::MasterBootRecord);
+        let linux: Vec<(u64, u64, u32)> = results
+            .partitions
+            .iter()
+            .filter(|part| part.partition_type == PartitionType::Linux)
+            .map(|part| {
+                (
+                    part.offset_start,
+                    part.partition_size,
+                    part.sectors_in_partition,
+                )
+            })
+            .collect();
+        assert_eq!(
+            linux,
+            vec![
+                (64 * 512, 512 * 512, 512),
+                (1024 * 512, os_reader.os_size - 1024 * 512, 1024)
+            ]
+        );
+        assert_eq!(
+            results.warnings,
+            vec![
+                BootWarning::OutOfBounds {
+                    offset: 4096 * 512,
+                    size: 512 * 512
+                },
+                BootWarning::OutOfBounds {
+                    offset: 1024 * 512,
+                    size: 4096 * 512
+                },
+            ]
+        );
+    }
 }
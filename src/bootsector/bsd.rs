@@ -0,0 +1,328 @@
+use crate::{
+    bootsector::boot::{BootWarning, BsdPartition, Partition, PartitionType, fit_to_disk},
+    error::{CalfError, Offset, Structure},
+    reader::{OsReader, read_guest},
+};
+use log::{error, warn};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u8, le_u16, le_u32},
+};
+use std::io::{Read, Seek};
+
+/// Read the BSD disklabel in the second sector of a FreeBSD, OpenBSD, or NetBSD slice.
+/// Returns an empty list if the slice has no disklabel
+pub(crate) fn bsd_partitions<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    slice: &Partition,
+    sector_size: u64,
+    warnings: &mut Vec<BootWarning>,
+) -> Vec<Partition> {
+    let label_offset = slice.offset_start + sector_size;
+    let label_size = 512;
+    let data = match read_guest(reader, label_offset, label_size) {
+        Ok(result) => result,
+        Err(err) => {
+            error!("[calf] Could not read BSD disklabel: {err}");
+            return Vec::new();
+        }
+    };
+
+    let openbsd = slice.partition_type == PartitionType::OpenBsd;
+    let entries = match parse_disklabel(&data, openbsd) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            warn!(
+                "[calf] {}",
+                CalfError::parse(Structure::BsdLabel, Offset::Guest(label_offset), &err)
+            );
+            return Vec::new();
+        }
+    };
+    if !verify_checksum(&data, entries.len()) {
+        warn!("[calf] BSD disklabel at {label_offset} has a bad checksum");
+        warnings.push(BootWarning::BsdLabelChecksum {
+            offset: label_offset,
+        });
+    }
+
+    // FreeBSD offsets are relative to the raw partition (c). OpenBSD and NetBSD offsets are from the start of the disk
+    let raw_partition = 2;
+    let slice_lba = slice.offset_start / sector_size;
+    let base_lba = match slice.partition_type {
+        PartitionType::FreeBsd => entries.get(raw_partition).map_or(0, |raw| raw.offset),
+        _ => 0,
+    };
+    let slice_end = slice.offset_start + slice.partition_size;
+
+    let mut parts = Vec::new();
+    for entry in entries {
+        // Unused entries are skipped. This includes the raw partition that covers the whole slice or disk
+        if entry.fstype == 0 || entry.size == 0 {
+            continue;
+        }
+        let lba = match slice.partition_type {
+            PartitionType::FreeBsd => slice_lba + entry.offset.saturating_sub(base_lba),
+            _ => entry.offset,
+        };
+        let part = bsd_partition(entry, lba, sector_size);
+        if part.offset_start < slice.offset_start
+            || part.offset_start.saturating_add(part.partition_size) > slice_end
+        {
+            warn!(
+                "[calf] BSD partition at {} is outside of its slice",
+                part.offset_start
+            );
+            warnings.push(BootWarning::OutOfBounds {
+                offset: part.offset_start,
+                size: part.partition_size,
+            });
+        }
+        parts.push(part);
+    }
+
+    fit_to_disk(&mut parts, reader.os_size, sector_size, warnings);
+    parts
+}
+
+/// Parse the disklabel partition entries: <https://man.freebsd.org/cgi/man.cgi?query=disklabel&sektion=5>.
+/// OpenBSD entries have 16 more bits of offset and size where the other BSDs have the fragment size
+fn parse_disklabel(data: &[u8], openbsd: bool) -> nom::IResult<&[u8], Vec<BsdPartition>> {
+    let magic = [0x57, 0x45, 0x56, 0x82];
+    let (input, _) = tag(&magic[..])(data)?;
+    // Drive type, geometry, and timing values we do not need
+    let unused: u8 = 128;
+    let (input, _) = take(unused)(input)?;
+    let (input, _) = tag(&magic[..])(input)?;
+    let (input, _checksum) = le_u16(input)?;
+    let (input, partitions) = le_u16(input)?;
+    let (input, _boot_size) = le_u32(input)?;
+    let (mut input, _superblock_size) = le_u32(input)?;
+
+    // Only 16 entries fit in the label sector
+    let max_partitions = 16;
+    let letters = "abcdefghijklmnop";
+    let mut entries = Vec::new();
+    for letter in letters
+        .chars()
+        .take(partitions.min(max_partitions) as usize)
+    {
+        let (remaining, size) = le_u32(input)?;
+        let (remaining, offset) = le_u32(remaining)?;
+        let (remaining, fsize) = le_u32(remaining)?;
+        let (remaining, fstype) = le_u8(remaining)?;
+        let (remaining, frag) = le_u8(remaining)?;
+        let (remaining, cpg) = le_u16(remaining)?;
+        input = remaining;
+
+        let mut offset = u64::from(offset);
+        let mut size = u64::from(size);
+        let mut fsize = fsize;
+        if openbsd {
+            // p_offseth and p_sizeh
+            offset |= u64::from(fsize & 0xffff) << 32;
+            size |= u64::from(fsize >> 16) << 32;
+            fsize = 0;
+        }
+
+        entries.push(BsdPartition {
+            letter,
+            offset,
+            size,
+            fstype,
+            fsize,
+            frag,
+            cpg,
+        });
+    }
+
+    Ok((input, entries))
+}
+
+/// XOR of every 16-bit word in the label, including the checksum, is zero
+fn verify_checksum(data: &[u8], partitions: usize) -> bool {
+    let header_size = 148;
+    let entry_size = 16;
+    let end = (header_size + partitions * entry_size).min(data.len());
+
+    let mut checksum = 0;
+    for word in data[..end].chunks_exact(2) {
+        checksum ^= u16::from_le_bytes([word[0], word[1]]);
+    }
+    checksum == 0
+}
+
+/// Convert a disklabel entry into a `Partition`
+fn bsd_partition(entry: BsdPartition, lba: u64, sector_size: u64) -> Partition {
+    Partition {
+        partition_type: get_bsd_partition_type(entry.fstype),
+        partition_type_value: entry.fstype,
+        type_name: get_bsd_partition_name(entry.fstype).to_string(),
+        first_sector_offset: 0,
+        last_sector_offset: 0,
+        first_logical_offset: u32::try_from(entry.offset).unwrap_or(u32::MAX),
+        offset_start: lba.saturating_mul(sector_size),
+        sectors_in_partition: u32::try_from(entry.size).unwrap_or(u32::MAX),
+        partition_size: entry.size.saturating_mul(sector_size),
+        bootable: false,
+        first_chs: None,
+        last_chs: None,
+        chs_mismatch: false,
+        gpt: None,
+        bsd: Some(entry),
+        apm: None,
+        children: Vec::new(),
+        filesystem: None,
+        bitlocker: None,
+    }
+}
+
+/// Determine the partition type from the disklabel fstype
+fn get_bsd_partition_type(fstype: u8) -> PartitionType {
+    match fstype {
+        0 => PartitionType::None,
+        1 => PartitionType::BsdSwap,
+        7 => PartitionType::Ufs,
+        17 => PartitionType::Linux,
+        _ => PartitionType::Unknown,
+    }
+}
+
+/// Human readable name for the disklabel fstype. The BSDs only agree on the first few values
+fn get_bsd_partition_name(fstype: u8) -> &'static str {
+    match fstype {
+        0 => "Unused",
+        1 => "Swap",
+        2 => "Version 6",
+        3 => "Version 7",
+        4 => "System V",
+        5 => "4.1BSD",
+        6 => "Eighth Edition",
+        7 => "4.2BSD (UFS)",
+        8 => "MS-DOS",
+        9 => "4.4BSD LFS",
+        10 => "Other",
+        11 => "HPFS",
+        12 => "ISO 9660",
+        13 => "Boot",
+        17 => "Linux ext2",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_bsd_partition_name, parse_disklabel, verify_checksum};
+    use crate::{
+        bootsector::boot::{BootWarning, PartitionType},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        reader::read_guest,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_parse_disklabel() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/bsd.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let mut data = read_guest(&mut os_reader, 64 * 512, 512).unwrap();
+
+        let (_, entries) = parse_disklabel(&data, false).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].letter, 'a');
+        assert_eq!(entries[0].offset, 79);
+        assert_eq!(entries[0].size, 600);
+        assert_eq!(entries[0].fstype, 7);
+        assert_eq!(entries[0].fsize, 2048);
+        assert_eq!(entries[1].fstype, 1);
+        assert!(verify_checksum(&data, entries.len()));
+
+        data[148] = 1;
+        assert!(!verify_checksum(&data, entries.len()));
+        data[0] = 0;
+        assert!(parse_disklabel(&data, false).is_err());
+        assert_eq!(get_bsd_partition_name(99), "Unknown");
+    }
+
+    #[test]
+    fn test_parse_disklabel_openbsd() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/bsd.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let mut data = read_guest(&mut os_reader, 1064 * 512, 512).unwrap();
+
+        // p_offseth and p_sizeh of partition a
+        data[156..158].copy_from_slice(&1u16.to_le_bytes());
+        data[158..160].copy_from_slice(&2u16.to_le_bytes());
+        let (_, entries) = parse_disklabel(&data, true).unwrap();
+        assert_eq!(entries[0].offset, (1 << 32) + 1079);
+        assert_eq!(entries[0].size, (2 << 32) + 900);
+        assert_eq!(entries[0].fsize, 0);
+
+        let (_, entries) = parse_disklabel(&data, false).unwrap();
+        assert_eq!(entries[0].offset, 1079);
+        assert_eq!(entries[0].fsize, 0x20001);
+    }
+
+    #[test]
+    fn test_boot_info_bsd() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/bsd.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        let freebsd = &results.partitions[0];
+        assert_eq!(freebsd.partition_type, PartitionType::FreeBsd);
+        assert_eq!(freebsd.children.len(), 2);
+        assert_eq!(freebsd.children[0].partition_type, PartitionType::Ufs);
+        assert_eq!(freebsd.children[0].offset_start, 79 * 512);
+        assert_eq!(freebsd.children[0].partition_size, 600 * 512);
+        assert_eq!(freebsd.children[1].partition_type, PartitionType::BsdSwap);
+        assert_eq!(freebsd.children[1].bsd.as_ref().unwrap().letter, 'b');
+
+        let openbsd = &results.partitions[1];
+        assert_eq!(openbsd.partition_type, PartitionType::OpenBsd);
+        assert_eq!(openbsd.children.len(), 2);
+        assert_eq!(openbsd.children[0].offset_start, 1079 * 512);
+        assert_eq!(openbsd.children[1].type_name, "4.2BSD (UFS)");
+        assert_eq!(openbsd.children[1].bsd.as_ref().unwrap().letter, 'd');
+        // Partition d runs past the end of the disk and ends at the end of the disk
+        assert_eq!(openbsd.children[1].partition_size, (2048 - 1979) * 512);
+
+        assert_eq!(
+            results.warnings,
+            vec![BootWarning::OutOfBounds {
+                offset: 1979 * 512,
+                size: 100 * 512
+            }]
+        );
+    }
+}
@@ -0,0 +1,889 @@
+use crate::{
+    bootsector::boot::{
+        BootWarning, DiscrepancyType, GptDiscrepancy, GptHeader, GptPartition, HybridEntry,
+        HybridStatus, Partition, PartitionType,
+    },
+    error::{CalfError, Offset, Structure},
+    reader::{OsReader, read_guest},
+    utils::{guid::format_guid, strings::extract_utf16_string},
+};
+use log::{error, warn};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u32, le_u64},
+};
+use std::io::{Read, Seek};
+
+/// The GPT that partitions are read from
+pub(crate) struct GptTables {
+    pub(crate) header: GptHeader,
+    pub(crate) entries: Vec<GptPartition>,
+    pub(crate) backup: Option<GptHeader>,
+    pub(crate) discrepancies: Vec<GptDiscrepancy>,
+    /// Differences between the primary and backup headers
+    pub(crate) warnings: Vec<BootWarning>,
+}
+
+/// Read the primary and backup GPT. Falls back to the backup if the primary is corrupt.
+/// Returns None if neither can be read
+pub(crate) fn gpt_tables<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    sector_size: u64,
+) -> Option<GptTables> {
+    let primary_lba = 1;
+    let primary = read_gpt(reader, primary_lba, sector_size);
+
+    // Backup is always at the last LBA of the disk
+    let last_lba = (reader.os_size / sector_size).checked_sub(1)?;
+    let backup = read_gpt(reader, last_lba, sector_size);
+
+    match (primary, backup) {
+        (Ok((header, entries)), Ok((backup_header, backup_entries))) => {
+            let mut warnings = Vec::new();
+            if header.backup_lba != last_lba {
+                warn!(
+                    "[calf] Primary GPT points to backup at LBA {}. Backup found at last LBA {last_lba}",
+                    header.backup_lba
+                );
+                warnings.push(BootWarning::GptBackupLocation {
+                    backup_lba: header.backup_lba,
+                    last_lba,
+                });
+            }
+            if header.disk_guid != backup_header.disk_guid {
+                warn!(
+                    "[calf] Primary GPT disk GUID {} does not match backup {}",
+                    header.disk_guid, backup_header.disk_guid
+                );
+                warnings.push(BootWarning::GptDiskGuidMismatch {
+                    primary: header.disk_guid.clone(),
+                    backup: backup_header.disk_guid.clone(),
+                });
+            }
+            let discrepancies = compare_entries(&entries, &backup_entries);
+            Some(GptTables {
+                header,
+                entries,
+                backup: Some(backup_header),
+                discrepancies,
+                warnings,
+            })
+        }
+        (Ok((header, entries)), Err(err)) => {
+            warn!("[calf] Could not read backup GPT: {err}");
+            Some(GptTables {
+                header,
+                entries,
+                backup: None,
+                discrepancies: Vec::new(),
+                warnings: vec![BootWarning::GptBackupUnreadable {
+                    detail: err.to_string(),
+                }],
+            })
+        }
+        (Err(err), Ok((backup_header, entries))) => {
+            warn!("[calf] Primary GPT is corrupt. Using the backup GPT: {err}");
+            Some(GptTables {
+                header: backup_header.clone(),
+                entries,
+                backup: Some(backup_header),
+                discrepancies: Vec::new(),
+                warnings: vec![BootWarning::GptPrimaryCorrupt {
+                    detail: err.to_string(),
+                }],
+            })
+        }
+        (Err(primary_err), Err(backup_err)) => {
+            error!("[calf] Could not read primary GPT {primary_err} or backup GPT {backup_err}");
+            None
+        }
+    }
+}
+
+/// Compare the primary GPT entries to the backup entries. Partitions are matched by unique GUID
+fn compare_entries(primary: &[GptPartition], backup: &[GptPartition]) -> Vec<GptDiscrepancy> {
+    let mut discrepancies = Vec::new();
+    for entry in primary {
+        let Some(backup_entry) = backup
+            .iter()
+            .find(|value| value.unique_guid == entry.unique_guid)
+        else {
+            discrepancies.push(GptDiscrepancy {
+                discrepancy_type: DiscrepancyType::Added,
+                primary: Some(entry.clone()),
+                backup: None,
+            });
+            continue;
+        };
+
+        let discrepancy_type = if entry.first_lba != backup_entry.first_lba
+            || entry.last_lba != backup_entry.last_lba
+        {
+            DiscrepancyType::Resized
+        } else if entry != backup_entry {
+            DiscrepancyType::Modified
+        } else {
+            continue;
+        };
+        discrepancies.push(GptDiscrepancy {
+            discrepancy_type,
+            primary: Some(entry.clone()),
+            backup: Some(backup_entry.clone()),
+        });
+    }
+
+    for entry in backup {
+        if primary
+            .iter()
+            .any(|value| value.unique_guid == entry.unique_guid)
+        {
+            continue;
+        }
+        discrepancies.push(GptDiscrepancy {
+            discrepancy_type: DiscrepancyType::Removed,
+            primary: None,
+            backup: Some(entry.clone()),
+        });
+    }
+
+    discrepancies
+}
+
+/// Compare MBR partitions to the GPT partitions. Returns nothing unless the MBR has partitions besides the protective one.
+/// Hybrid MBRs can hide a partition from MBR or GPT aware tools
+pub(crate) fn hybrid_entries(
+    mbr: &[Partition],
+    gpt: &[Partition],
+    sector_size: u64,
+) -> Vec<HybridEntry> {
+    let mut entries = Vec::new();
+    for part in mbr {
+        if matches!(
+            part.partition_type,
+            PartitionType::Protective | PartitionType::None
+        ) {
+            continue;
+        }
+        let first_lba = part.offset_start / sector_size;
+        let last_lba = (first_lba + part.sectors_in_partition as u64).saturating_sub(1);
+
+        let mut status = HybridStatus::MbrOnly;
+        let mut matched = None;
+        for gpt_part in gpt {
+            let Some(entry) = &gpt_part.gpt else {
+                continue;
+            };
+            if entry.first_lba == first_lba && entry.last_lba == last_lba {
+                status = HybridStatus::Match;
+                matched = Some(entry.clone());
+                break;
+            }
+            if matched.is_none() && entry.first_lba <= last_lba && first_lba <= entry.last_lba {
+                status = HybridStatus::BoundaryMismatch;
+                matched = Some(entry.clone());
+            }
+        }
+
+        if status != HybridStatus::Match {
+            warn!(
+                "[calf] Hybrid MBR partition at LBA {first_lba} to {last_lba} does not match the GPT: {status:?}"
+            );
+        }
+        entries.push(HybridEntry {
+            status,
+            mbr: part.clone(),
+            gpt: matched,
+        });
+    }
+
+    entries
+}
+
+/// Read and verify the GPT header at the provided LBA and its partition entries
+pub(crate) fn read_gpt<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    lba: u64,
+    sector_size: u64,
+) -> Result<(GptHeader, Vec<GptPartition>), CalfError> {
+    let Some(header_offset) = lba.checked_mul(sector_size) else {
+        return Err(CalfError::Parse {
+            structure: Structure::GptHeader,
+            offset: Offset::Guest(0),
+            detail: format!("header LBA {lba} is too large"),
+        });
+    };
+    let header_bytes = read_guest(reader, header_offset, sector_size)?;
+    let header = match parse_gpt_header(&header_bytes) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            error!("[calf] Could not parse GPT header at LBA {lba}: {err:?}");
+            return Err(CalfError::parse(
+                Structure::GptHeader,
+                Offset::Guest(header_offset),
+                &err,
+            ));
+        }
+    };
+
+    let min_header = 92;
+    if header.header_size < min_header || header.header_size as u64 > sector_size {
+        error!("[calf] Bad GPT header size {}", header.header_size);
+        return Err(CalfError::Parse {
+            structure: Structure::GptHeader,
+            offset: Offset::Guest(header_offset),
+            detail: format!("bad header size {}", header.header_size),
+        });
+    }
+    let crc = header_crc32(&header_bytes[..header.header_size as usize]);
+    if crc != header.header_crc32 {
+        error!(
+            "[calf] GPT header CRC32 mismatch. Expected {:#x}, got {crc:#x}",
+            header.header_crc32
+        );
+        return Err(CalfError::Parse {
+            structure: Structure::GptHeader,
+            offset: Offset::Guest(header_offset),
+            detail: format!(
+                "header CRC32 mismatch. Expected {:#x}, got {crc:#x}",
+                header.header_crc32
+            ),
+        });
+    }
+
+    // A header copied to another location is not valid there. Ex: the primary copied over the backup
+    if header.current_lba != lba {
+        error!(
+            "[calf] GPT header at LBA {lba} says it is at LBA {}",
+            header.current_lba
+        );
+        return Err(CalfError::Parse {
+            structure: Structure::GptHeader,
+            offset: Offset::Guest(header_offset),
+            detail: format!("header at LBA {lba} has current LBA {}", header.current_lba),
+        });
+    }
+
+    // Spec requires entries to be 128 bytes or larger. Also limit the array size to avoid huge allocations
+    let min_entry = 128;
+    let max_entries_size = 4 * 1024 * 1024;
+    let entries_size = header.entries_count as u64 * header.entry_size as u64;
+    let Some(entries_offset) = header.entries_lba.checked_mul(sector_size) else {
+        error!("[calf] GPT entries LBA {} is too large", header.entries_lba);
+        return Err(CalfError::Parse {
+            structure: Structure::GptHeader,
+            offset: Offset::Guest(header_offset),
+            detail: format!("entries LBA {} is too large", header.entries_lba),
+        });
+    };
+    if header.entry_size < min_entry
+        || header.entry_size % 8 != 0
+        || entries_size > max_entries_size
+    {
+        error!(
+            "[calf] Bad GPT entries. Count {} size {}",
+            header.entries_count, header.entry_size
+        );
+        return Err(CalfError::Parse {
+            structure: Structure::GptEntries,
+            offset: Offset::Guest(entries_offset),
+            detail: format!(
+                "bad entry count {} or size {}",
+                header.entries_count, header.entry_size
+            ),
+        });
+    }
+
+    let entries_bytes = read_guest(reader, entries_offset, entries_size)?;
+    let crc = crc32fast::hash(&entries_bytes);
+    if crc != header.entries_crc32 {
+        error!(
+            "[calf] GPT entries CRC32 mismatch. Expected {:#x}, got {crc:#x}",
+            header.entries_crc32
+        );
+        return Err(CalfError::Parse {
+            structure: Structure::GptEntries,
+            offset: Offset::Guest(entries_offset),
+            detail: format!(
+                "entries CRC32 mismatch. Expected {:#x}, got {crc:#x}",
+                header.entries_crc32
+            ),
+        });
+    }
+
+    let entries = match parse_gpt_entries(&entries_bytes, header.entry_size) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            error!("[calf] Could not parse GPT entries: {err:?}");
+            return Err(CalfError::parse(
+                Structure::GptEntries,
+                Offset::Guest(entries_offset),
+                &err,
+            ));
+        }
+    };
+
+    Ok((header, entries))
+}
+
+/// CRC32 of the header is calculated with the CRC field set to zero
+fn header_crc32(data: &[u8]) -> u32 {
+    let crc_offset = 16;
+    let crc_size = 4;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data[..crc_offset]);
+    hasher.update(&[0; 4]);
+    hasher.update(&data[crc_offset + crc_size..]);
+    hasher.finalize()
+}
+
+/// Parse the GPT header. Starts with the `EFI PART` signature
+pub(crate) fn parse_gpt_header(data: &[u8]) -> nom::IResult<&[u8], GptHeader> {
+    let (input, _sig) = tag(&b"EFI PART"[..])(data)?;
+    let (input, revision) = le_u32(input)?;
+    let (input, header_size) = le_u32(input)?;
+    let (input, header_crc32) = le_u32(input)?;
+    let (input, _reserved) = le_u32(input)?;
+    let (input, current_lba) = le_u64(input)?;
+    let (input, backup_lba) = le_u64(input)?;
+    let (input, first_usable_lba) = le_u64(input)?;
+    let (input, last_usable_lba) = le_u64(input)?;
+    let (input, disk_guid) = take_guid(input)?;
+    let (input, entries_lba) = le_u64(input)?;
+    let (input, entries_count) = le_u32(input)?;
+    let (input, entry_size) = le_u32(input)?;
+    let (input, entries_crc32) = le_u32(input)?;
+
+    let header = GptHeader {
+        revision,
+        header_size,
+        header_crc32,
+        current_lba,
+        backup_lba,
+        first_usable_lba,
+        last_usable_lba,
+        disk_guid,
+        entries_lba,
+        entries_count,
+        entry_size,
+        entries_crc32,
+    };
+    Ok((input, header))
+}
+
+/// Parse the GPT partition entry array. Unused entries are skipped
+pub(crate) fn parse_gpt_entries(
+    data: &[u8],
+    entry_size: u32,
+) -> nom::IResult<&[u8], Vec<GptPartition>> {
+    let mut input = data;
+    let mut entries = Vec::new();
+    while input.len() >= entry_size as usize {
+        let (remaining, entry) = take(entry_size)(input)?;
+        input = remaining;
+
+        // Unused entries have a type GUID of all zeros
+        if entry[..16].iter().all(|value| *value == 0) {
+            continue;
+        }
+
+        let (entry, type_guid) = take_guid(entry)?;
+        let (entry, unique_guid) = take_guid(entry)?;
+        let (entry, first_lba) = le_u64(entry)?;
+        let (entry, last_lba) = le_u64(entry)?;
+        let (entry, attributes) = le_u64(entry)?;
+        let name_size: u8 = 72;
+        let (_, name) = take(name_size)(entry)?;
+
+        entries.push(GptPartition {
+            type_guid,
+            unique_guid,
+            first_lba,
+            last_lba,
+            attributes,
+            name: extract_utf16_string(name),
+        });
+    }
+
+    Ok((input, entries))
+}
+
+/// Grab a 16 byte GUID
+fn take_guid(data: &[u8]) -> nom::IResult<&[u8], String> {
+    let guid_size: u8 = 16;
+    let (input, guid) = take(guid_size)(data)?;
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(guid);
+    Ok((input, format_guid(&bytes)))
+}
+
+/// Convert a GPT entry into a `Partition`. Returns None if the LBAs are too large to be byte offsets
+pub(crate) fn gpt_partition(entry: GptPartition, sector_size: u64) -> Option<Partition> {
+    if entry.last_lba < entry.first_lba {
+        warn!(
+            "[calf] GPT partition {} ends before it starts. First LBA {} last LBA {}",
+            entry.unique_guid, entry.first_lba, entry.last_lba
+        );
+    }
+    let sectors = entry
+        .last_lba
+        .checked_add(1)?
+        .saturating_sub(entry.first_lba);
+    let offset_start = entry.first_lba.checked_mul(sector_size)?;
+    let partition_size = sectors.checked_mul(sector_size)?;
+    offset_start.checked_add(partition_size)?;
+    // Bit 2 is the legacy BIOS bootable flag
+    let legacy_bootable = 0x4;
+
+    Some(Partition {
+        partition_type: get_gpt_partition_type(&entry.type_guid),
+        partition_type_value: 0,
+        type_name: get_gpt_partition_name(&entry.type_guid).to_string(),
+        first_sector_offset: 0,
+        last_sector_offset: 0,
+        first_logical_offset: u32::try_from(entry.first_lba).unwrap_or(u32::MAX),
+        offset_start,
+        sectors_in_partition: u32::try_from(sectors).unwrap_or(u32::MAX),
+        partition_size,
+        bootable: entry.attributes & legacy_bootable != 0,
+        first_chs: None,
+        last_chs: None,
+        chs_mismatch: false,
+        gpt: Some(entry),
+        bsd: None,
+        apm: None,
+        children: Vec::new(),
+        filesystem: None,
+        bitlocker: None,
+    })
+}
+
+/// Determine the partition type from the GPT type GUID. Only a few are supported right now
+/// There are a lot: <https://en.wikipedia.org/wiki/GUID_Partition_Table#Partition_type_GUIDs>
+fn get_gpt_partition_type(guid: &str) -> PartitionType {
+    match guid {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => PartitionType::EfiSystem,
+        "21686148-6449-6E6F-744E-656564454649" => PartitionType::BiosBoot,
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => PartitionType::MicrosoftReserved,
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => PartitionType::MicrosoftBasicData,
+        // Linux filesystem, x86-64 root, and home
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        | "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709"
+        | "933AC7E1-2EB4-4F13-B844-0E14E2AEF915" => PartitionType::Linux,
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => PartitionType::LinuxSwap,
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => PartitionType::LinuxLvm,
+        "A19D880F-05FC-4D3B-A006-743F0F84911E" => PartitionType::LinuxRaid,
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => PartitionType::WindowsRecovery,
+        "AF9B60A0-1431-4F62-BC68-3311714A69AD" => PartitionType::WindowsDynamic,
+        "516E7CB4-6ECF-11D6-8FF8-00022D09712B" => PartitionType::FreeBsd,
+        "6A85CF4D-1DD2-11B2-99A6-080020736631" => PartitionType::Solaris,
+        "48465300-0000-11AA-AA11-00306543ECAC" => PartitionType::HfsPlus,
+        "426F6F74-0000-11AA-AA11-00306543ECAC" => PartitionType::AppleBoot,
+        "AA31E02A-400F-11DB-9590-000C2911D1B8" => PartitionType::VmwareVmfs,
+        "00000000-0000-0000-0000-000000000000" => PartitionType::None,
+        _ => PartitionType::Unknown,
+    }
+}
+
+/// Human readable name for the GPT type GUID
+fn get_gpt_partition_name(guid: &str) -> &'static str {
+    match guid {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System Partition",
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => "Microsoft reserved",
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data",
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem",
+        "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709" => "Linux root (x86-64)",
+        "933AC7E1-2EB4-4F13-B844-0E14E2AEF915" => "Linux home",
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM",
+        "A19D880F-05FC-4D3B-A006-743F0F84911E" => "Linux RAID",
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => "Windows recovery environment",
+        "AF9B60A0-1431-4F62-BC68-3311714A69AD" => "Windows LDM data",
+        "516E7CB4-6ECF-11D6-8FF8-00022D09712B" => "FreeBSD data",
+        "6A85CF4D-1DD2-11B2-99A6-080020736631" => "Solaris root",
+        "48465300-0000-11AA-AA11-00306543ECAC" => "Apple HFS+",
+        "426F6F74-0000-11AA-AA11-00306543ECAC" => "Apple boot",
+        "AA31E02A-400F-11DB-9590-000C2911D1B8" => "VMware VMFS",
+        "00000000-0000-0000-0000-000000000000" => "Unused",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compare_entries, get_gpt_partition_name, get_gpt_partition_type, gpt_partition,
+        header_crc32, parse_gpt_header, read_gpt,
+    };
+    use crate::{
+        bootsector::boot::{BootType, BootWarning, DiscrepancyType, HybridStatus, PartitionType},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        reader::read_guest,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_read_gpt() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let (header, entries) = read_gpt(&mut os_reader, 1, 512).unwrap();
+
+        assert_eq!(header.revision, 0x10000);
+        assert_eq!(header.current_lba, 1);
+        assert_eq!(header.backup_lba, 4095);
+        assert_eq!(header.disk_guid, "5B3E9A10-0B2C-4F0E-9B7A-3C1D2E4F6A8B");
+        assert_eq!(header.entries_count, 128);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "EFI System");
+        assert_eq!(entries[0].type_guid, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(
+            entries[1].unique_guid,
+            "1F2E3D4C-5B6A-4978-8695-A4B3C2D1E0F9"
+        );
+        assert_eq!(entries[1].first_lba, 1064);
+        assert_eq!(entries[1].last_lba, 4055);
+    }
+
+    #[test]
+    fn test_boot_info_gpt() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.boot_type, BootType::GuidPartitionTable);
+        assert_eq!(results.partitions.len(), 2);
+        assert_eq!(
+            results.partitions[0].partition_type,
+            PartitionType::EfiSystem
+        );
+        assert_eq!(results.partitions[0].offset_start, 20480);
+        assert_eq!(results.partitions[0].partition_size, 524288);
+        assert_eq!(results.partitions[1].partition_type, PartitionType::Linux);
+        assert!(results.partitions[1].bootable);
+        assert_eq!(results.gpt.unwrap().last_usable_lba, 4062);
+    }
+
+    #[test]
+    fn test_header_crc32() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let mut data = read_guest(&mut os_reader, 512, 512).unwrap();
+        let (_, header) = parse_gpt_header(&data).unwrap();
+        assert_eq!(header_crc32(&data[..92]), header.header_crc32);
+
+        data[40] = 1;
+        assert_ne!(header_crc32(&data[..92]), header.header_crc32);
+    }
+
+    #[test]
+    fn test_gpt_partition() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let (_, mut entries) = read_gpt(&mut os_reader, 1, 512).unwrap();
+
+        let entry = entries.remove(1);
+        let part = gpt_partition(entry.clone(), 4096).unwrap();
+        assert_eq!(part.offset_start, 1064 * 4096);
+        assert_eq!(part.sectors_in_partition, 2992);
+        assert_eq!(part.type_name, "Linux filesystem");
+        assert_eq!(get_gpt_partition_type("bad"), PartitionType::Unknown);
+        assert_eq!(get_gpt_partition_name("bad"), "Unknown");
+
+        let mut overflow = entry.clone();
+        overflow.last_lba = u64::MAX;
+        assert!(gpt_partition(overflow, 512).is_none());
+        let mut overflow = entry;
+        overflow.first_lba = u64::MAX / 512 + 1;
+        overflow.last_lba = u64::MAX - 1;
+        assert!(gpt_partition(overflow, 512).is_none());
+    }
+
+    #[test]
+    fn test_backup_fallback() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_bad_primary.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        assert!(read_gpt(&mut os_reader, 1, 512).is_err());
+
+        let results = os_reader.get_boot_info().unwrap();
+        assert_eq!(results.partitions.len(), 2);
+        assert_eq!(results.partitions[1].offset_start, 1064 * 512);
+        let header = results.gpt.unwrap();
+        assert_eq!(header.current_lba, 4095);
+        assert_eq!(header.entries_lba, 4063);
+        assert!(results.gpt_discrepancies.is_empty());
+        assert!(matches!(
+            &results.warnings[..],
+            [BootWarning::GptPrimaryCorrupt { detail }] if detail.contains("CRC32")
+        ));
+    }
+
+    #[test]
+    fn test_copied_primary() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_copied_primary.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let err = read_gpt(&mut os_reader, 4095, 512).unwrap_err();
+        assert!(err.to_string().contains("current LBA 1"));
+
+        let results = os_reader.get_boot_info().unwrap();
+        assert_eq!(results.partitions.len(), 2);
+        assert_eq!(results.gpt.unwrap().current_lba, 1);
+        assert!(results.gpt_backup.is_none());
+        assert!(matches!(
+            &results.warnings[..],
+            [BootWarning::GptBackupUnreadable { detail }] if detail.contains("current LBA 1")
+        ));
+    }
+
+    #[test]
+    fn test_gpt_discrepancies() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_tampered.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.partitions.len(), 3);
+        assert_eq!(results.gpt.unwrap().current_lba, 1);
+        assert_eq!(results.gpt_backup.unwrap().current_lba, 4095);
+        assert_eq!(results.gpt_discrepancies.len(), 2);
+
+        let resized = &results.gpt_discrepancies[0];
+        assert_eq!(resized.discrepancy_type, DiscrepancyType::Resized);
+        assert_eq!(resized.primary.as_ref().unwrap().last_lba, 3063);
+        assert_eq!(resized.backup.as_ref().unwrap().last_lba, 4055);
+
+        let added = &results.gpt_discrepancies[1];
+        assert_eq!(added.discrepancy_type, DiscrepancyType::Added);
+        assert_eq!(added.primary.as_ref().unwrap().name, "hidden");
+        assert!(added.backup.is_none());
+    }
+
+    #[test]
+    fn test_gpt_header_mismatch() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_header_mismatch.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.partitions.len(), 2);
+        assert!(results.gpt_discrepancies.is_empty());
+        assert_eq!(
+            results.warnings,
+            vec![
+                BootWarning::GptBackupLocation {
+                    backup_lba: 3996,
+                    last_lba: 4095,
+                },
+                BootWarning::GptDiskGuidMismatch {
+                    primary: String::from("5B3E9A10-0B2C-4F0E-9B7A-3C1D2E4F6A8B"),
+                    backup: String::from("11111111-2222-4333-8444-555555555555"),
+                },
+            ]
+        );
+
+        // Matching headers have no warnings
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        assert!(os_reader.get_boot_info().unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_compare_entries() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let (_, entries) = read_gpt(&mut os_reader, 1, 512).unwrap();
+        assert!(compare_entries(&entries, &entries).is_empty());
+
+        let mut modified = entries.clone();
+        modified[0].name = String::from("changed");
+        modified.remove(1);
+        let results = compare_entries(&modified, &entries);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].discrepancy_type, DiscrepancyType::Modified);
+        assert_eq!(results[1].discrepancy_type, DiscrepancyType::Removed);
+    }
+
+    #[test]
+    fn test_boot_info_4kn() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_4kn.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.sector_size, 4096);
+        assert_eq!(results.boot_type, BootType::GuidPartitionTable);
+        assert_eq!(results.partitions.len(), 2);
+        assert_eq!(results.partitions[0].offset_start, 6 * 4096);
+        assert_eq!(results.partitions[1].offset_start, 262 * 4096);
+        assert_eq!(results.partitions[1].partition_size, 756 * 4096);
+        assert!(results.gpt_discrepancies.is_empty());
+        assert_eq!(results.gpt_backup.unwrap().current_lba, 1023);
+
+        // Guest disk is 4096 bytes per sector. Forcing 512 only finds the protective MBR
+        let results = os_reader.get_boot_info_sector_size(512).unwrap();
+        assert_eq!(results.sector_size, 512);
+        assert_eq!(results.partitions.len(), 4);
+        assert!(results.gpt.is_none());
+    }
+
+    #[test]
+    fn test_hybrid_mbr() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt_hybrid.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        assert_eq!(results.boot_type, BootType::GuidPartitionTable);
+        assert_eq!(results.partitions.len(), 2);
+        assert_eq!(results.hybrid_mbr.len(), 3);
+
+        let efi = &results.hybrid_mbr[0];
+        assert_eq!(efi.status, HybridStatus::Match);
+        assert_eq!(efi.mbr.partition_type, PartitionType::EfiSystem);
+        assert_eq!(efi.gpt.as_ref().unwrap().name, "EFI System");
+
+        let root = &results.hybrid_mbr[1];
+        assert_eq!(root.status, HybridStatus::BoundaryMismatch);
+        assert_eq!(root.gpt.as_ref().unwrap().name, "root");
+        assert_eq!(root.mbr.sectors_in_partition, 2000);
+
+        let hidden = &results.hybrid_mbr[2];
+        assert_eq!(hidden.status, HybridStatus::MbrOnly);
+        assert_eq!(hidden.mbr.partition_type, PartitionType::Fat32);
+        assert!(hidden.gpt.is_none());
+    }
+
+    #[test]
+    fn test_no_hybrid_mbr() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/gpt/gpt.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+        assert!(results.hybrid_mbr.is_empty());
+    }
+}
@@ -1,21 +1,33 @@
-use crate::bootsector::boot::{BootInfo, BootType, Partition, PartitionType};
+use crate::bootsector::boot::{
+    BootCode, BootInfo, BootLoader, BootType, Chs, Partition, PartitionType,
+};
 use log::warn;
 use nom::{
-    bytes::complete::take,
+    bytes::complete::{tag, take},
     number::complete::{le_u8, le_u16, le_u32},
 };
+use sha2::{Digest, Sha256};
 
-/// Parse the Master Boot Record (MBR) partition. We must be able to parse this in order to parse the rest of the filesystem
-pub(crate) fn parse_mbr(data: &[u8]) -> nom::IResult<&[u8], BootInfo> {
+/// Parse the Master Boot Record (MBR) partition. We must be able to parse this in order to parse the rest of the filesystem.
+/// Partition LBAs are converted to offsets using the logical sector size
+pub(crate) fn parse_mbr(data: &[u8], sector_size: u64) -> nom::IResult<&[u8], BootInfo> {
     let boot_binary_code: u16 = 440;
-    let (input, _binary) = take(boot_binary_code)(data)?;
+    let (input, binary) = take(boot_binary_code)(data)?;
 
-    let (input, _disk_id) = le_u32(input)?;
+    let (input, disk_signature) = le_u32(input)?;
     let (mut input, _reserved) = le_u16(input)?;
 
     let mut info = BootInfo {
         boot_type: BootType::MasterBootRecord,
         partitions: Vec::new(),
+        sector_size,
+        gpt: None,
+        gpt_backup: None,
+        gpt_discrepancies: Vec::new(),
+        hybrid_mbr: Vec::new(),
+        warnings: Vec::new(),
+        disk_signature: Some(disk_signature),
+        boot_code: Some(boot_code(binary)),
     };
 
     let partition_size: u8 = 16;
@@ -27,7 +39,7 @@ pub(crate) fn parse_mbr(data: &[u8]) -> nom::IResult<&[u8], BootInfo> {
         let (remaining, partition) = take(partition_size)(input)?;
         input = remaining;
 
-        let (_, (part, is_gpt)) = parse_partition(partition)?;
+        let (_, (part, is_gpt)) = parse_partition(partition, sector_size)?;
         info.partitions.push(part);
 
         if is_gpt {
@@ -35,17 +47,52 @@ pub(crate) fn parse_mbr(data: &[u8]) -> nom::IResult<&[u8], BootInfo> {
         }
         count += 1;
     }
-    let (input, _valid_bootsector) = le_u16(input)?;
+    let boot_signature = [0x55, 0xaa];
+    let (input, _) = tag(&boot_signature[..])(input)?;
     Ok((input, info))
 }
 
+/// Hash the boot code and match it against known boot loaders. Loaders patch values into their boot code when
+/// installed, so the hash differs between disks and strings are used to identify the loader
+fn boot_code(data: &[u8]) -> BootCode {
+    let contains = |value: &[u8]| data.windows(value.len()).any(|window| window == value);
+
+    // Windows stores the offsets of its three error messages before the disk signature
+    let windows_messages =
+        b"Invalid partition table\0Error loading operating system\0Missing operating system";
+    let windows7_offsets = [0x63, 0x7b, 0x9a];
+
+    let loader = if data.iter().all(|value| *value == 0) {
+        BootLoader::Empty
+    } else if contains(b"GRUB \0Geom\0Hard Disk\0Read\0 Error\r\n") {
+        BootLoader::Grub2
+    } else if contains(windows_messages) && data.get(437..440) == Some(&windows7_offsets[..]) {
+        BootLoader::Windows7
+    } else if contains(b"Operating system load error.\r\n") {
+        BootLoader::Syslinux
+    } else {
+        BootLoader::Unknown
+    };
+
+    BootCode {
+        sha256: format!("{:x}", Sha256::digest(data)),
+        loader,
+    }
+}
+
+/// Check for the 0x55AA signature at the end of the 512 byte boot sector
+pub(crate) fn has_boot_signature(data: &[u8]) -> bool {
+    let boot_signature = [0x55, 0xaa];
+    data.get(510..512) == Some(&boot_signature[..])
+}
+
 /// Parse the partition data. It is very small, 16 bytes.
-fn parse_partition(data: &[u8]) -> nom::IResult<&[u8], (Partition, bool)> {
+fn parse_partition(data: &[u8], sector_size: u64) -> nom::IResult<&[u8], (Partition, bool)> {
     let (input, bootable) = le_u8(data)?;
-    let sector_size: u8 = 3;
-    let (input, sector_start) = take(sector_size)(input)?;
+    let chs_size: u8 = 3;
+    let (input, sector_start) = take(chs_size)(input)?;
     let (input, partition_type) = le_u8(input)?;
-    let (input, sector_last) = take(sector_size)(input)?;
+    let (input, sector_last) = take(chs_size)(input)?;
 
     let (input, first_logical_offset) = le_u32(input)?;
     let (input, sectors_in_partition) = le_u32(input)?;
@@ -59,13 +106,23 @@ fn parse_partition(data: &[u8]) -> nom::IResult<&[u8], (Partition, bool)> {
     let part = Partition {
         partition_type: get_partition_type(partition_type),
         partition_type_value: partition_type,
+        type_name: get_partition_name(partition_type).to_string(),
         first_sector_offset,
         last_sector_offset,
         first_logical_offset,
-        offset_start: first_logical_offset as u64 * 512,
+        offset_start: first_logical_offset as u64 * sector_size,
         sectors_in_partition,
-        partition_size: (sectors_in_partition as u64 * 512),
+        partition_size: (sectors_in_partition as u64 * sector_size),
         bootable: bootable == 0x80,
+        first_chs: Some(decode_chs(sector_start)),
+        last_chs: Some(decode_chs(sector_last)),
+        chs_mismatch: false,
+        gpt: None,
+        bsd: None,
+        apm: None,
+        children: Vec::new(),
+        filesystem: None,
+        bitlocker: None,
     };
 
     if part.partition_type == PartitionType::Protective {
@@ -75,27 +132,145 @@ fn parse_partition(data: &[u8]) -> nom::IResult<&[u8], (Partition, bool)> {
     Ok((input, (part, is_gpt)))
 }
 
-/// Determine the partition type, only a few are supported right now
+/// Decode the 3 byte CHS value. Sector is 6 bits and the cylinder is 10 bits
+fn decode_chs(data: &[u8]) -> Chs {
+    // Safe to reference by slice index because nom ensures the CHS value is 3 bytes in size
+    let sector_mask = 0x3f;
+    let cylinder_mask = 0xc0;
+    Chs {
+        cylinder: (((data[1] & cylinder_mask) as u16) << 2) | data[2] as u16,
+        head: data[0],
+        sector: data[1] & sector_mask,
+    }
+}
+
+/// Check if the CHS values disagree with the LBA values of the partition.
+/// Disk geometry is not stored in the MBR, so a mismatch means no geometry can explain both addresses
+pub(crate) fn check_chs(part: &Partition, sector_size: u64) -> bool {
+    let (Some(first_chs), Some(last_chs)) = (&part.first_chs, &part.last_chs) else {
+        return false;
+    };
+    if part.partition_type == PartitionType::None || part.sectors_in_partition == 0 {
+        return false;
+    }
+
+    let first_lba = part.offset_start / sector_size;
+    let last_lba = first_lba + part.sectors_in_partition as u64 - 1;
+    let max_heads = 256;
+    let max_sectors = 63;
+    for heads in 1..=max_heads {
+        for sectors in 1..=max_sectors {
+            if chs_matches(first_chs, first_lba, heads, sectors)
+                && chs_matches(last_chs, last_lba, heads, sectors)
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Check a CHS address against the LBA using the provided geometry
+fn chs_matches(chs: &Chs, lba: u64, heads: u64, sectors: u64) -> bool {
+    // Unused CHS values and addresses past the CHS limit (cylinder 1023) cannot be checked
+    let max_cylinder = 1023;
+    if chs.cylinder == max_cylinder || (chs.cylinder == 0 && chs.head == 0 && chs.sector == 0) {
+        return true;
+    }
+    if chs.sector == 0 || chs.sector as u64 > sectors || chs.head as u64 >= heads {
+        return false;
+    }
+
+    (chs.cylinder as u64 * heads + chs.head as u64) * sectors + chs.sector as u64 - 1 == lba
+}
+
+/// Determine the partition type. Only 0xEE is a GPT protective partition. 0xEF is an EFI System Partition
 /// There are a lot: <https://en.wikipedia.org/wiki/Partition_type#List_of_partition_IDs>
 fn get_partition_type(part: u8) -> PartitionType {
     match part {
         0x0 => PartitionType::None,
-        0x7 | 0x27 => PartitionType::Ntfs,
+        0x1 => PartitionType::Fat12,
+        0x4 | 0x6 | 0xe => PartitionType::Fat16,
+        0xb | 0xc => PartitionType::Fat32,
+        0x7 => PartitionType::Ntfs,
+        0x11 => PartitionType::HiddenFat12,
+        0x14 | 0x16 | 0x1e => PartitionType::HiddenFat16,
+        0x1b | 0x1c => PartitionType::HiddenFat32,
+        0x17 => PartitionType::HiddenNtfs,
+        0x27 => PartitionType::WindowsRecovery,
+        0x42 => PartitionType::WindowsDynamic,
         0x83 => PartitionType::Linux,
         0x82 => PartitionType::LinuxSwap,
         0x8e => PartitionType::LinuxLvm,
-        0xc => PartitionType::Fat32,
-        0xee | 0xef => PartitionType::Protective,
-        0x5 | 0xf => PartitionType::Extended,
+        0xfd => PartitionType::LinuxRaid,
+        // Linux extended uses the same EBR chain as DOS extended partitions
+        0x5 | 0xf | 0x85 => PartitionType::Extended,
+        0xa5 => PartitionType::FreeBsd,
+        0xa6 => PartitionType::OpenBsd,
+        0xa9 => PartitionType::NetBsd,
+        0xbf => PartitionType::Solaris,
+        0xaf => PartitionType::HfsPlus,
+        0xab => PartitionType::AppleBoot,
+        0xfb => PartitionType::VmwareVmfs,
+        0xee => PartitionType::Protective,
+        0xef => PartitionType::EfiSystem,
         _ => PartitionType::Unknown,
     }
 }
 
+/// Human readable name for the MBR partition ID
+fn get_partition_name(part: u8) -> &'static str {
+    match part {
+        0x0 => "Empty",
+        0x1 => "FAT12",
+        0x4 => "FAT16 (less than 32 MB)",
+        0x5 => "Extended (CHS)",
+        0x6 => "FAT16",
+        0x7 => "NTFS/exFAT/HPFS",
+        0xb => "FAT32 (CHS)",
+        0xc => "FAT32 (LBA)",
+        0xe => "FAT16 (LBA)",
+        0xf => "Extended (LBA)",
+        0x11 => "Hidden FAT12",
+        0x12 => "OEM diagnostics/recovery",
+        0x14 => "Hidden FAT16 (less than 32 MB)",
+        0x16 => "Hidden FAT16",
+        0x17 => "Hidden NTFS/exFAT/HPFS",
+        0x1b => "Hidden FAT32 (CHS)",
+        0x1c => "Hidden FAT32 (LBA)",
+        0x1e => "Hidden FAT16 (LBA)",
+        0x27 => "Windows recovery environment",
+        0x42 => "Windows dynamic disk",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x84 => "Hibernation",
+        0x85 => "Linux extended",
+        0x86 | 0x87 => "NTFS volume set",
+        0x8e => "Linux LVM",
+        0xa5 => "FreeBSD",
+        0xa6 => "OpenBSD",
+        0xa8 => "Apple UFS",
+        0xa9 => "NetBSD",
+        0xab => "Apple boot",
+        0xaf => "Apple HFS/HFS+",
+        0xbe => "Solaris boot",
+        0xbf => "Solaris",
+        0xda => "Non-filesystem data",
+        0xee => "GPT protective",
+        0xef => "EFI System Partition",
+        0xfb => "VMware VMFS",
+        0xfc => "VMware swap",
+        0xfd => "Linux RAID",
+        _ => "Unknown",
+    }
+}
+
 /// Parse the extended partitions: <https://en.wikipedia.org/wiki/Extended_boot_record>
 pub(crate) fn parse_extended(
     data: &[u8],
     root_offset: u64,
     extended_offset: u64,
+    sector_size: u64,
 ) -> nom::IResult<&[u8], (Vec<Partition>, bool)> {
     let mut parts = Vec::new();
 
@@ -104,7 +279,7 @@ pub(crate) fn parse_extended(
     let (input, _) = take(unused)(data)?;
     let entry_size: u8 = 16;
     let (input, first_entry) = take(entry_size)(input)?;
-    let (_, (mut first_part, _)) = parse_partition(first_entry)?;
+    let (_, (mut first_part, _)) = parse_partition(first_entry, sector_size)?;
 
     let mut has_extened = false;
     // The first entry in an extended partition should never? be extended Type. But check just in case
@@ -117,7 +292,7 @@ pub(crate) fn parse_extended(
     }
 
     let (input, second_entry) = take(entry_size)(input)?;
-    let (_, (mut extended_part, _)) = parse_partition(second_entry)?;
+    let (_, (mut extended_part, _)) = parse_partition(second_entry, sector_size)?;
 
     // Extended partition only has two partitions. But technically allows 4?
     let (input, _third_entry) = take(entry_size)(input)?;
@@ -139,7 +314,7 @@ pub(crate) fn parse_extended(
         extended_part.offset_start += root_offset;
         // First entry offset combines the current extended partition offset and the relative offset
         first_part.offset_start =
-            (first_part.first_logical_offset as u64 + extended_offset / 512) * 512;
+            (first_part.first_logical_offset as u64 + extended_offset / sector_size) * sector_size;
     }
     parts.push(first_part);
 
@@ -150,8 +325,11 @@ pub(crate) fn parse_extended(
 #[cfg(test)]
 mod tests {
     use crate::bootsector::{
-        boot::{BootType, PartitionType},
-        mbr::{get_partition_type, parse_extended, parse_mbr, parse_partition},
+        boot::{BootLoader, BootType, PartitionType},
+        mbr::{
+            boot_code, check_chs, decode_chs, get_partition_name, get_partition_type,
+            parse_extended, parse_mbr, parse_partition,
+        },
     };
     use std::{fs::read, path::PathBuf};
 
@@ -160,7 +338,7 @@ mod tests {
         let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         test_location.push("tests/test_data/mbr/extended_partition.raw");
         let bytes = read(test_location.to_str().unwrap()).unwrap();
-        let (_, (results, has_extended)) = parse_extended(&bytes, 0, 0).unwrap();
+        let (_, (results, has_extended)) = parse_extended(&bytes, 0, 0, 512).unwrap();
         assert!(has_extended);
         assert_eq!(results.len(), 2);
         assert_eq!(results[0].partition_type, PartitionType::Linux);
@@ -175,15 +353,72 @@ mod tests {
         for entry in test {
             assert_ne!(get_partition_type(entry), PartitionType::Unknown);
         }
+        assert_eq!(get_partition_type(0xef), PartitionType::EfiSystem);
+        assert_eq!(get_partition_type(0xee), PartitionType::Protective);
+        assert_eq!(get_partition_type(0x85), PartitionType::Extended);
+        assert_eq!(get_partition_type(0x1b), PartitionType::HiddenFat32);
+    }
+
+    #[test]
+    fn test_get_partition_name() {
+        let test = [
+            0x1, 0x4, 0x6, 0xe, 0xb, 0x11, 0x16, 0x1c, 0x27, 0x85, 0xa5, 0xa6, 0xa9, 0xbf, 0xaf,
+            0xfd,
+        ];
+        for entry in test {
+            assert_ne!(get_partition_name(entry), "Unknown");
+            assert_ne!(get_partition_type(entry), PartitionType::Unknown);
+        }
+        assert_eq!(get_partition_name(0xef), "EFI System Partition");
+        assert_eq!(get_partition_name(0x99), "Unknown");
+    }
+
+    #[test]
+    fn test_parse_mbr_efi_system() {
+        let mut test = [0; 512];
+        test[446 + 4] = 0xef;
+        test[446 + 8] = 1;
+        test[446 + 12] = 100;
+        test[510] = 0x55;
+        test[511] = 0xaa;
+        let (_, result) = parse_mbr(&test, 512).unwrap();
+        assert_eq!(result.boot_type, BootType::MasterBootRecord);
+        assert_eq!(
+            result.partitions[0].partition_type,
+            PartitionType::EfiSystem
+        );
+        assert_eq!(result.partitions[0].type_name, "EFI System Partition");
     }
 
     #[test]
     fn test_parse_parition() {
         let test = [128, 4, 1, 4, 131, 254, 194, 255, 0, 8, 0, 0, 0, 128, 224, 0];
-        let (_, (result, is_gpt)) = parse_partition(&test).unwrap();
+        let (_, (result, is_gpt)) = parse_partition(&test, 512).unwrap();
         assert_eq!(result.partition_size, 7532969984);
         assert_eq!(result.first_sector_offset, 262404);
         assert!(!is_gpt);
+
+        let first = result.first_chs.as_ref().unwrap();
+        assert_eq!((first.cylinder, first.head, first.sector), (4, 4, 1));
+        let last = result.last_chs.as_ref().unwrap();
+        assert_eq!((last.cylinder, last.head, last.sector), (1023, 254, 2));
+        assert!(!check_chs(&result, 512));
+    }
+
+    #[test]
+    fn test_check_chs() {
+        // LBA 2048 is cylinder 0, head 32, sector 33 with 255 heads and 63 sectors
+        let test = [0, 32, 33, 0, 131, 254, 255, 255, 0, 8, 0, 0, 0, 0, 16, 0];
+        let (_, (mut result, _)) = parse_partition(&test, 512).unwrap();
+        assert!(!check_chs(&result, 512));
+
+        // Hand edited LBA without updating the CHS values
+        result.offset_start = 4096 * 512;
+        assert!(check_chs(&result, 512));
+
+        // Zeroed CHS values cannot be checked
+        result.first_chs = Some(decode_chs(&[0, 0, 0]));
+        assert!(!check_chs(&result, 512));
     }
 
     #[test]
@@ -216,11 +451,42 @@ mod tests {
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 85, 170,
         ];
-        let (_, result) = parse_mbr(&test).unwrap();
+        let (_, result) = parse_mbr(&test, 512).unwrap();
         assert_eq!(result.boot_type, BootType::MasterBootRecord);
         assert_eq!(result.partitions.len(), 4);
 
         assert_eq!(result.partitions[1].offset_start, 7535066112);
+        assert_eq!(result.disk_signature, Some(0xf0633b02));
+        let boot_code = result.boot_code.unwrap();
+        assert_eq!(boot_code.loader, BootLoader::Grub2);
+        assert_eq!(
+            boot_code.sha256,
+            "79013645221586bd73568ca4ec189710a25194bca010435a2b1dee7d081334d2"
+        );
+    }
+
+    #[test]
+    fn test_boot_code() {
+        let mut test = [0; 440];
+        assert_eq!(boot_code(&test).loader, BootLoader::Empty);
+        assert_eq!(
+            boot_code(&test).sha256,
+            "360d579dbd14759b41afdf7fb5e80c0101e15150ae401d59f92a1e32d129f7cb"
+        );
+
+        test[0] = 0x33;
+        assert_eq!(boot_code(&test).loader, BootLoader::Unknown);
+
+        let messages =
+            b"Invalid partition table\0Error loading operating system\0Missing operating system\0";
+        test[0x163..0x163 + messages.len()].copy_from_slice(messages);
+        test[437..440].copy_from_slice(&[0x63, 0x7b, 0x9a]);
+        assert_eq!(boot_code(&test).loader, BootLoader::Windows7);
+
+        let mut test = [0; 440];
+        let messages = b"Missing operating system.\r\nOperating system load error.\r\n";
+        test[0x170..0x170 + messages.len()].copy_from_slice(messages);
+        assert_eq!(boot_code(&test).loader, BootLoader::Syslinux);
     }
 
     #[test]
@@ -245,7 +511,7 @@ mod tests {
             40, 167, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 85, 170,
         ];
-        let (_, (results, has_extended)) = parse_extended(&test, 832568320, 0).unwrap();
+        let (_, (results, has_extended)) = parse_extended(&test, 832568320, 0, 512).unwrap();
         assert!(!has_extended);
         assert_eq!(results.len(), 2);
         assert_eq!(results[0].partition_type, PartitionType::LinuxLvm);
@@ -1,2 +1,7 @@
+pub(crate) mod apm;
+pub(crate) mod bitlocker;
 pub mod boot;
+pub(crate) mod bsd;
+pub(crate) mod gpt;
 pub(crate) mod mbr;
+pub(crate) mod probe;
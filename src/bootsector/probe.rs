@@ -0,0 +1,458 @@
+use crate::{
+    bootsector::{
+        bitlocker::{bitlocker_info, has_bitlocker_guid},
+        boot::{Filesystem, FilesystemType, Partition, PartitionType},
+    },
+    lvm::label::format_lvm_uuid,
+    partition::PartitionReader,
+    reader::OsReader,
+    utils::{
+        guid::{format_guid, format_uuid},
+        strings::extract_utf8_string,
+    },
+};
+use log::warn;
+use std::io::{Read, Seek};
+
+/// Probe the partitions and any nested partitions for filesystem signatures
+pub(crate) fn probe_partitions<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    partitions: &mut [Partition],
+) {
+    for part in partitions {
+        if !matches!(
+            part.partition_type,
+            PartitionType::None | PartitionType::Extended
+        ) && part.partition_size != 0
+        {
+            part.filesystem = probe_partition(reader, part);
+        }
+        if part
+            .filesystem
+            .as_ref()
+            .is_some_and(|fs| fs.fs_type == FilesystemType::BitLocker)
+        {
+            match bitlocker_info(reader, part) {
+                Ok(info) => part.bitlocker = Some(info),
+                Err(err) => warn!(
+                    "[calf] Could not read BitLocker metadata at {}: {err}",
+                    part.offset_start
+                ),
+            }
+        }
+        probe_partitions(reader, &mut part.children);
+    }
+}
+
+/// Read the start of the partition and check for known signatures
+pub(crate) fn probe_partition<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    part: &Partition,
+) -> Option<Filesystem> {
+    // Furthest signature is the ZFS uberblock array at 128 KiB. Read the first 256 KiB
+    let probe_size = 262144;
+    let mut data = Vec::new();
+    let part_reader = PartitionReader::new(&mut *reader, part);
+    if let Err(err) = part_reader.take(probe_size).read_to_end(&mut data) {
+        warn!(
+            "[calf] Could not read partition at {} to probe the filesystem: {err:?}",
+            part.offset_start
+        );
+        return None;
+    }
+
+    probe_filesystem(&data)
+}
+
+/// Check the data for filesystem signatures. Encrypted and volume manager signatures are checked first
+/// because their headers can look like other filesystems
+pub(crate) fn probe_filesystem(data: &[u8]) -> Option<Filesystem> {
+    let probes = [
+        probe_luks,
+        probe_lvm,
+        probe_bitlocker,
+        probe_ext,
+        probe_xfs,
+        probe_btrfs,
+        probe_ntfs,
+        probe_exfat,
+        probe_fat,
+        probe_swap,
+        probe_zfs,
+        probe_iso9660,
+    ];
+    probes.iter().find_map(|probe| probe(data))
+}
+
+/// ext2/3/4 superblock at 1024 bytes
+fn probe_ext(data: &[u8]) -> Option<Filesystem> {
+    let superblock = 1024;
+    if get_u16(data, superblock + 56)? != 0xef53 {
+        return None;
+    }
+    let compat = get_u32(data, superblock + 92)?;
+    let incompat = get_u32(data, superblock + 96)?;
+
+    let has_journal = 0x4;
+    // Extents, 64-bit, or flexible block groups
+    let ext4_features = 0x40 | 0x80 | 0x200;
+    let fs_type = if incompat & ext4_features != 0 {
+        FilesystemType::Ext4
+    } else if compat & has_journal != 0 {
+        FilesystemType::Ext3
+    } else {
+        FilesystemType::Ext2
+    };
+
+    Some(Filesystem {
+        fs_type,
+        label: get_string(data, superblock + 120, 16),
+        uuid: get_uuid(data, superblock + 104),
+    })
+}
+
+fn probe_xfs(data: &[u8]) -> Option<Filesystem> {
+    if !has_signature(data, 0, b"XFSB") {
+        return None;
+    }
+    Some(Filesystem {
+        fs_type: FilesystemType::Xfs,
+        label: get_string(data, 108, 12),
+        uuid: get_uuid(data, 32),
+    })
+}
+
+/// Btrfs superblock at 64 KiB
+fn probe_btrfs(data: &[u8]) -> Option<Filesystem> {
+    let superblock = 65536;
+    if !has_signature(data, superblock + 64, b"_BHRfS_M") {
+        return None;
+    }
+    Some(Filesystem {
+        fs_type: FilesystemType::Btrfs,
+        label: get_string(data, superblock + 299, 256),
+        uuid: get_uuid(data, superblock + 32),
+    })
+}
+
+/// NTFS volume label is in the `$Volume` file. Only the serial number is in the boot sector
+fn probe_ntfs(data: &[u8]) -> Option<Filesystem> {
+    if !has_signature(data, 3, b"NTFS    ") {
+        return None;
+    }
+    let serial = data.get(72..80)?;
+    let serial = u64::from_le_bytes(serial.try_into().ok()?);
+    Some(Filesystem {
+        fs_type: FilesystemType::Ntfs,
+        label: None,
+        uuid: Some(format!("{serial:016X}")),
+    })
+}
+
+/// exFAT volume label is in the root directory. Only the serial number is in the boot sector
+fn probe_exfat(data: &[u8]) -> Option<Filesystem> {
+    if !has_signature(data, 3, b"EXFAT   ") {
+        return None;
+    }
+    Some(Filesystem {
+        fs_type: FilesystemType::ExFat,
+        label: None,
+        uuid: Some(format_serial(get_u32(data, 100)?)),
+    })
+}
+
+fn probe_fat(data: &[u8]) -> Option<Filesystem> {
+    // FAT32 has a larger BIOS parameter block. So the extended boot record fields are further in
+    let (fs_type, serial_offset) = if has_signature(data, 82, b"FAT32   ") {
+        (FilesystemType::Fat32, 67)
+    } else if has_signature(data, 54, b"FAT16   ") {
+        (FilesystemType::Fat16, 39)
+    } else if has_signature(data, 54, b"FAT12   ") {
+        (FilesystemType::Fat12, 39)
+    } else {
+        return None;
+    };
+
+    let label_size = 11;
+    let label = get_string(data, serial_offset + 4, label_size).filter(|value| value != "NO NAME");
+    Some(Filesystem {
+        fs_type,
+        label,
+        uuid: Some(format_serial(get_u32(data, serial_offset)?)),
+    })
+}
+
+/// Linux swap signature is at the end of the first page. Page size depends on the architecture
+fn probe_swap(data: &[u8]) -> Option<Filesystem> {
+    let page_sizes = [4096, 8192, 16384, 65536];
+    let sig_size = 10;
+    for page in page_sizes {
+        let sig_offset = page - sig_size;
+        if has_signature(data, sig_offset, b"SWAPSPACE2") {
+            return Some(Filesystem {
+                fs_type: FilesystemType::Swap,
+                label: get_string(data, 1052, 16),
+                uuid: get_uuid(data, 1036),
+            });
+        }
+        // Old version 0 swap has no label or UUID
+        if has_signature(data, sig_offset, b"SWAP-SPACE") {
+            return Some(Filesystem {
+                fs_type: FilesystemType::Swap,
+                label: None,
+                uuid: None,
+            });
+        }
+    }
+    None
+}
+
+/// LVM2 label can be in any of the first four sectors
+fn probe_lvm(data: &[u8]) -> Option<Filesystem> {
+    let sector_size = 512;
+    let max_sectors = 4;
+    for sector in 0..max_sectors {
+        let label = sector * sector_size;
+        if !has_signature(data, label, b"LABELONE") || !has_signature(data, label + 24, b"LVM2 001")
+        {
+            continue;
+        }
+        let header_offset = get_u32(data, label + 20)? as usize;
+        let uuid_size = 32;
+        let uuid = data.get(label + header_offset..label + header_offset + uuid_size)?;
+        return Some(Filesystem {
+            fs_type: FilesystemType::LvmPv,
+            label: None,
+            uuid: Some(format_lvm_uuid(uuid)),
+        });
+    }
+    None
+}
+
+fn probe_luks(data: &[u8]) -> Option<Filesystem> {
+    if !has_signature(data, 0, b"LUKS\xba\xbe") {
+        return None;
+    }
+    let version = u16::from_be_bytes(data.get(6..8)?.try_into().ok()?);
+    let (fs_type, label) = match version {
+        1 => (FilesystemType::Luks1, None),
+        2 => (FilesystemType::Luks2, get_string(data, 24, 48)),
+        _ => return None,
+    };
+    Some(Filesystem {
+        fs_type,
+        label,
+        uuid: get_string(data, 168, 40),
+    })
+}
+
+/// To Go volumes have a FAT32 header with the BDE identifier GUID at 424
+fn probe_bitlocker(data: &[u8]) -> Option<Filesystem> {
+    let guid_offset = if has_signature(data, 3, b"-FVE-FS-") {
+        160
+    } else if has_signature(data, 3, b"MSWIN4.1") && has_bitlocker_guid(data, 424) {
+        424
+    } else {
+        return None;
+    };
+    let guid = data.get(guid_offset..guid_offset + 16)?;
+    Some(Filesystem {
+        fs_type: FilesystemType::BitLocker,
+        label: None,
+        uuid: Some(format_guid(guid.try_into().ok()?)),
+    })
+}
+
+/// ZFS pool name and GUID are in an nvlist. Only check the uberblock magic
+fn probe_zfs(data: &[u8]) -> Option<Filesystem> {
+    let uberblocks = 131072;
+    let uberblock_size = 1024;
+    let max_uberblocks = 128;
+    let magic: u64 = 0x00bab10c;
+    for slot in 0..max_uberblocks {
+        let offset = uberblocks + slot * uberblock_size;
+        let Some(value) = data.get(offset..offset + 8) else {
+            break;
+        };
+        let value: [u8; 8] = value.try_into().ok()?;
+        if u64::from_le_bytes(value) == magic || u64::from_be_bytes(value) == magic {
+            return Some(Filesystem {
+                fs_type: FilesystemType::Zfs,
+                label: None,
+                uuid: None,
+            });
+        }
+    }
+    None
+}
+
+/// Primary volume descriptor at 32 KiB
+fn probe_iso9660(data: &[u8]) -> Option<Filesystem> {
+    let descriptor = 32768;
+    if !has_signature(data, descriptor + 1, b"CD001") {
+        return None;
+    }
+    Some(Filesystem {
+        fs_type: FilesystemType::Iso9660,
+        label: get_string(data, descriptor + 40, 32),
+        uuid: None,
+    })
+}
+
+fn has_signature(data: &[u8], offset: usize, sig: &[u8]) -> bool {
+    data.get(offset..offset + sig.len()) == Some(sig)
+}
+
+fn get_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn get_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Get a NULL or space padded string. Empty strings are None
+fn get_string(data: &[u8], offset: usize, size: usize) -> Option<String> {
+    let value = data.get(offset..offset + size)?;
+    let end = value
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(value.len());
+    let value = extract_utf8_string(&value[..end]).trim_end().to_string();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value)
+}
+
+/// Get a big endian UUID. All zero UUIDs are None
+fn get_uuid(data: &[u8], offset: usize) -> Option<String> {
+    let value: [u8; 16] = data.get(offset..offset + 16)?.try_into().ok()?;
+    if value.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    Some(format_uuid(&value))
+}
+
+/// FAT and exFAT volume serial number. Ex: `1234-ABCD`
+fn format_serial(serial: u32) -> String {
+    format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::probe_filesystem;
+    use crate::{
+        bootsector::boot::FilesystemType,
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_probe_filesystem() {
+        let mut data = vec![0; 262144];
+        assert!(probe_filesystem(&data).is_none());
+
+        data[65536 + 64..65536 + 72].copy_from_slice(b"_BHRfS_M");
+        data[65536 + 299..65536 + 303].copy_from_slice(b"pool");
+        data[65536 + 32] = 0xab;
+        let result = probe_filesystem(&data).unwrap();
+        assert_eq!(result.fs_type, FilesystemType::Btrfs);
+        assert_eq!(result.label.unwrap(), "pool");
+        assert_eq!(result.uuid.unwrap(), "ab000000-0000-0000-0000-000000000000");
+
+        let mut data = vec![0; 8192];
+        data[4086..4096].copy_from_slice(b"SWAPSPACE2");
+        data[1052..1056].copy_from_slice(b"swap");
+        let result = probe_filesystem(&data).unwrap();
+        assert_eq!(result.fs_type, FilesystemType::Swap);
+        assert_eq!(result.label.unwrap(), "swap");
+        assert!(result.uuid.is_none());
+
+        let mut data = vec![0; 2048];
+        data[512..520].copy_from_slice(b"LABELONE");
+        data[532] = 32;
+        data[536..544].copy_from_slice(b"LVM2 001");
+        data[544..576].copy_from_slice(b"Aoo0nQdOC1WUMdGnOh7ywRwk5tB9mKVn");
+        let result = probe_filesystem(&data).unwrap();
+        assert_eq!(result.fs_type, FilesystemType::LvmPv);
+        assert_eq!(
+            result.uuid.unwrap(),
+            "Aoo0nQ-dOC1-WUMd-GnOh-7ywR-wk5t-B9mKVn"
+        );
+
+        let mut data = vec![0; 512];
+        data[3..11].copy_from_slice(b"NTFS    ");
+        data[72..80].copy_from_slice(&[0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01]);
+        let result = probe_filesystem(&data).unwrap();
+        assert_eq!(result.fs_type, FilesystemType::Ntfs);
+        assert_eq!(result.uuid.unwrap(), "0123456789ABCDEF");
+
+        data[3..11].copy_from_slice(b"-FVE-FS-");
+        assert_eq!(
+            probe_filesystem(&data).unwrap().fs_type,
+            FilesystemType::BitLocker
+        );
+
+        let mut data = vec![0; 262144];
+        data[131072 + 2048..131072 + 2056].copy_from_slice(&0x00bab10c_u64.to_le_bytes());
+        assert_eq!(
+            probe_filesystem(&data).unwrap().fs_type,
+            FilesystemType::Zfs
+        );
+
+        let mut data = vec![0; 65536];
+        data[32769..32774].copy_from_slice(b"CD001");
+        data[32808..32840].copy_from_slice(b"DEBIAN_13                       ");
+        let result = probe_filesystem(&data).unwrap();
+        assert_eq!(result.fs_type, FilesystemType::Iso9660);
+        assert_eq!(result.label.unwrap(), "DEBIAN_13");
+    }
+
+    #[test]
+    fn test_boot_info_filesystems() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/filesystems.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.get_boot_info().unwrap();
+
+        let ext4 = results.partitions[0].filesystem.as_ref().unwrap();
+        assert_eq!(ext4.fs_type, FilesystemType::Ext4);
+        assert_eq!(ext4.label.as_ref().unwrap(), "rootfs");
+        assert_eq!(
+            ext4.uuid.as_ref().unwrap(),
+            "0b43d8e6-e877-460f-a713-ce9d80ec6904"
+        );
+
+        let xfs = results.partitions[1].filesystem.as_ref().unwrap();
+        assert_eq!(xfs.fs_type, FilesystemType::Xfs);
+        assert_eq!(xfs.label.as_ref().unwrap(), "data");
+
+        let luks = results.partitions[2].filesystem.as_ref().unwrap();
+        assert_eq!(luks.fs_type, FilesystemType::Luks2);
+        assert_eq!(luks.label.as_ref().unwrap(), "secret");
+        assert_eq!(
+            luks.uuid.as_ref().unwrap(),
+            "8a3b0f4e-2c1d-4e5f-9a8b-7c6d5e4f3a2b"
+        );
+
+        let fat = results.partitions[3].filesystem.as_ref().unwrap();
+        assert_eq!(fat.fs_type, FilesystemType::Fat32);
+        assert_eq!(fat.label.as_ref().unwrap(), "BOOT");
+        assert_eq!(fat.uuid.as_ref().unwrap(), "1234-ABCD");
+    }
+}
@@ -13,6 +13,8 @@ pub struct CalfReader<T: std::io::Seek + std::io::Read> {
     pub fs: BufReader<T>,
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct QcowInfo {
     pub header: Header,
     pub level1_table: Vec<Level>,
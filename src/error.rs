@@ -1,30 +1,203 @@
-use std::fmt;
+use std::{fmt, io};
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CalfError {
+    /// Could not parse a structure in the QCOW file or guest disk
+    Parse {
+        structure: Structure,
+        offset: Offset,
+        /// Why the parser failed
+        detail: String,
+    },
+    SeekFile {
+        offset: Offset,
+        source: io::Error,
+    },
+    ReadFile {
+        offset: Offset,
+        source: io::Error,
+    },
+    WriteFile {
+        offset: Offset,
+        source: io::Error,
+    },
+}
+
+/// Structures calf parses
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Structure {
     Header,
-    HeaderExtensionFeatures,
     HeaderExtensions,
-    Level,
-    SeekFile,
-    ReadFile,
-    ParseMbr,
+    HeaderExtensionFeatures,
+    LevelTable,
+    Mbr,
     ExtendedPartition,
+    GptHeader,
+    GptEntries,
+    BsdLabel,
+    ApmEntry,
+    LvmLabel,
+    LvmMetadata,
+    ThinPoolMetadata,
+    SnapshotStore,
+    SwapHeader,
+    LuksHeader,
+    LuksKeySlot,
+    BitLockerMetadata,
+}
+
+/// Location of an error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offset {
+    /// Offset in the QCOW file
+    Host(u64),
+    /// Offset in the guest disk
+    Guest(u64),
+    /// Offset in an export output
+    Output(u64),
 }
 
-impl std::error::Error for CalfError {}
+impl CalfError {
+    /// Create a parse error from a nom failure
+    pub(crate) fn parse(
+        structure: Structure,
+        offset: Offset,
+        err: &nom::Err<nom::error::Error<&[u8]>>,
+    ) -> CalfError {
+        let detail = match err {
+            nom::Err::Incomplete(_) => String::from("not enough data"),
+            nom::Err::Error(err) | nom::Err::Failure(err) => format!(
+                "{} ({} bytes left)",
+                err.code.description(),
+                err.input.len()
+            ),
+        };
+        CalfError::Parse {
+            structure,
+            offset,
+            detail,
+        }
+    }
+
+    /// Where the error happened
+    pub fn offset(&self) -> Offset {
+        match self {
+            CalfError::Parse { offset, .. }
+            | CalfError::SeekFile { offset, .. }
+            | CalfError::ReadFile { offset, .. }
+            | CalfError::WriteFile { offset, .. } => *offset,
+        }
+    }
+}
+
+impl std::error::Error for CalfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CalfError::Parse { .. } => None,
+            CalfError::SeekFile { source, .. }
+            | CalfError::ReadFile { source, .. }
+            | CalfError::WriteFile { source, .. } => Some(source),
+        }
+    }
+}
 
 impl fmt::Display for CalfError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            CalfError::Header => write!(f, "Could not parse header"),
-            CalfError::HeaderExtensionFeatures => write!(f, "Could not parse features extension"),
-            CalfError::HeaderExtensions => write!(f, "Could not get header extensions"),
-            CalfError::Level => write!(f, "Failed to parse QCOW level"),
-            CalfError::SeekFile => write!(f, "Failed to seek to provided offset"),
-            CalfError::ReadFile => write!(f, "Failed to read bytes from QCOW file"),
-            CalfError::ParseMbr => write!(f, "Failed to parse MBR bytes"),
-            CalfError::ExtendedPartition => write!(f, "Failed to parse extended partition info"),
+            CalfError::Parse {
+                structure,
+                offset,
+                detail,
+            } => write!(f, "Could not parse {structure} at {offset}: {detail}"),
+            CalfError::SeekFile { offset, .. } => write!(f, "Failed to seek to {offset}"),
+            CalfError::ReadFile { offset, .. } => write!(f, "Failed to read bytes at {offset}"),
+            CalfError::WriteFile { offset, .. } => write!(f, "Failed to write bytes at {offset}"),
+        }
+    }
+}
+
+impl fmt::Display for Structure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Structure::Header => "header",
+            Structure::HeaderExtensions => "header extensions",
+            Structure::HeaderExtensionFeatures => "features extension",
+            Structure::LevelTable => "QCOW level table",
+            Structure::Mbr => "MBR",
+            Structure::ExtendedPartition => "extended partition",
+            Structure::GptHeader => "GPT header",
+            Structure::GptEntries => "GPT partition entries",
+            Structure::BsdLabel => "BSD disklabel",
+            Structure::ApmEntry => "Apple partition map entry",
+            Structure::LvmLabel => "LVM2 label",
+            Structure::LvmMetadata => "LVM2 metadata",
+            Structure::ThinPoolMetadata => "LVM2 thin pool metadata",
+            Structure::SnapshotStore => "LVM2 snapshot COW store",
+            Structure::SwapHeader => "swap header",
+            Structure::LuksHeader => "LUKS header",
+            Structure::LuksKeySlot => "LUKS key slot",
+            Structure::BitLockerMetadata => "BitLocker FVE metadata",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Offset::Host(offset) => write!(f, "host offset {offset}"),
+            Offset::Guest(offset) => write!(f, "guest offset {offset}"),
+            Offset::Output(offset) => write!(f, "output offset {offset}"),
         }
     }
 }
+
+/// Lets `Read` and `Seek` implementations return a `CalfError`. Callers can get it back with `downcast`
+impl From<CalfError> for io::Error {
+    fn from(err: CalfError) -> io::Error {
+        let kind = match &err {
+            CalfError::Parse { .. } => io::ErrorKind::InvalidData,
+            CalfError::SeekFile { source, .. }
+            | CalfError::ReadFile { source, .. }
+            | CalfError::WriteFile { source, .. } => source.kind(),
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CalfError, Offset, Structure};
+    use std::{error::Error, io};
+
+    #[test]
+    fn test_error_context() {
+        let err = CalfError::ReadFile {
+            offset: Offset::Host(4096),
+            source: io::Error::new(io::ErrorKind::UnexpectedEof, "short read"),
+        };
+        assert_eq!(err.to_string(), "Failed to read bytes at host offset 4096");
+        assert_eq!(err.offset(), Offset::Host(4096));
+        assert_eq!(err.source().unwrap().to_string(), "short read");
+
+        let io_err = io::Error::from(err);
+        assert_eq!(io_err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = io_err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(4096));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let data = [0u8; 2];
+        let err =
+            nom::number::complete::be_u32::<&[u8], nom::error::Error<&[u8]>>(&data).unwrap_err();
+        let err = CalfError::parse(Structure::Header, Offset::Host(0), &err);
+        assert_eq!(
+            err.to_string(),
+            "Could not parse header at host offset 0: End of file (2 bytes left)"
+        );
+        assert!(err.source().is_none());
+    }
+}
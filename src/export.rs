@@ -0,0 +1,348 @@
+use crate::{
+    error::{CalfError, Offset},
+    extents::{Extent, ExtentType, Extents},
+    reader::OsReader,
+};
+use log::error;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Hashes to calculate while exporting the guest disk
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportHash {
+    pub md5: bool,
+    pub sha1: bool,
+    pub sha256: bool,
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HashValue {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportInfo {
+    /// Total size of the raw output
+    pub bytes: u64,
+    /// Bytes actually written. Holes are skipped over
+    pub data_bytes: u64,
+    /// Hashes of the full raw output, including holes
+    pub hashes: HashValue,
+}
+
+struct Hashers {
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    sha256: Option<Sha256>,
+}
+
+impl Hashers {
+    fn new(hashes: &ExportHash) -> Hashers {
+        Hashers {
+            md5: hashes.md5.then(Md5::new),
+            sha1: hashes.sha1.then(Sha1::new),
+            sha256: hashes.sha256.then(Sha256::new),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+        if let Some(sha1) = &mut self.sha1 {
+            sha1.update(data);
+        }
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(data);
+        }
+    }
+
+    fn finalize(self) -> HashValue {
+        HashValue {
+            md5: self
+                .md5
+                .map(|hash| format!("{:x}", hash.finalize()))
+                .unwrap_or_default(),
+            sha1: self
+                .sha1
+                .map(|hash| format!("{:x}", hash.finalize()))
+                .unwrap_or_default(),
+            sha256: self
+                .sha256
+                .map(|hash| format!("{:x}", hash.finalize()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Export a range of the guest disk as raw bytes. Zero and unallocated extents are seeked over so the output stays sparse.
+/// Output is written relative to the start of `output`. `output` must be empty, otherwise old bytes would remain in the holes.
+/// `progress` gets the bytes exported so far and the total bytes
+pub(crate) fn export_range<T: Seek + Read, W: Write + Seek, F: FnMut(u64, u64)>(
+    reader: &mut OsReader<'_, '_, T>,
+    output: &mut W,
+    start: u64,
+    size: u64,
+    hashes: &ExportHash,
+    mut progress: F,
+) -> Result<ExportInfo, CalfError> {
+    let end = u64::min(start.saturating_add(size), reader.os_size);
+    let total = end.saturating_sub(start);
+
+    let output_size = match output.seek(SeekFrom::End(0)) {
+        Ok(result) => result,
+        Err(err) => {
+            error!("[calf] Could not get export output size: {err:?}");
+            return Err(CalfError::SeekFile {
+                offset: Offset::Output(0),
+                source: err,
+            });
+        }
+    };
+    if output_size != 0 {
+        error!("[calf] Export output already has {output_size} bytes. It must be empty");
+        return Err(CalfError::WriteFile {
+            offset: Offset::Output(0),
+            source: io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("export output is not empty. It has {output_size} bytes"),
+            ),
+        });
+    }
+
+    // Get the extents first. Our extent iterator borrows the reader
+    let mut extents: Vec<Extent> = Vec::new();
+    for extent in Extents::new(reader, start) {
+        let extent = extent?;
+        if extent.start >= end {
+            break;
+        }
+        if extent.start + extent.length <= start {
+            continue;
+        }
+        extents.push(extent);
+    }
+
+    let mut hashers = Hashers::new(hashes);
+    let chunk_size = 1024 * 1024;
+    let mut buf = vec![0; chunk_size];
+    let zeros = vec![0; chunk_size];
+    let mut data_bytes = 0;
+    let mut ends_with_hole = false;
+
+    for extent in extents {
+        let extent_start = u64::max(extent.start, start);
+        let extent_end = u64::min(extent.start + extent.length, end);
+        let mut offset = extent_start;
+
+        if extent.extent_type == ExtentType::Data {
+            ends_with_hole = false;
+            if let Err(err) = reader.seek(SeekFrom::Start(offset)) {
+                error!("[calf] Could not seek to guest offset {offset} for export: {err:?}");
+                return Err(CalfError::SeekFile {
+                    offset: Offset::Guest(offset),
+                    source: err,
+                });
+            }
+            if let Err(err) = output.seek(SeekFrom::Start(offset - start)) {
+                error!(
+                    "[calf] Could not seek export output to {}: {err:?}",
+                    offset - start
+                );
+                return Err(CalfError::SeekFile {
+                    offset: Offset::Output(offset - start),
+                    source: err,
+                });
+            }
+
+            while offset < extent_end {
+                let read_len = u64::min(chunk_size as u64, extent_end - offset) as usize;
+                if let Err(err) = reader.read_exact(&mut buf[..read_len]) {
+                    error!("[calf] Could not read guest offset {offset} for export: {err:?}");
+                    return Err(CalfError::ReadFile {
+                        offset: Offset::Guest(offset),
+                        source: err,
+                    });
+                }
+                if let Err(err) = output.write_all(&buf[..read_len]) {
+                    error!(
+                        "[calf] Could not write export bytes at {}: {err:?}",
+                        offset - start
+                    );
+                    return Err(CalfError::WriteFile {
+                        offset: Offset::Output(offset - start),
+                        source: err,
+                    });
+                }
+                hashers.update(&buf[..read_len]);
+
+                offset += read_len as u64;
+                data_bytes += read_len as u64;
+                progress(offset - start, total);
+            }
+            continue;
+        }
+
+        // Holes are never written. We still need to hash them
+        ends_with_hole = true;
+        while offset < extent_end {
+            let hole_len = u64::min(chunk_size as u64, extent_end - offset) as usize;
+            hashers.update(&zeros[..hole_len]);
+            offset += hole_len as u64;
+            progress(offset - start, total);
+        }
+    }
+
+    // Write the last byte so the output has the full size if it ends with a hole
+    if ends_with_hole && total > 0 {
+        if let Err(err) = output.seek(SeekFrom::Start(total - 1)) {
+            error!("[calf] Could not seek to end of export output: {err:?}");
+            return Err(CalfError::SeekFile {
+                offset: Offset::Output(total - 1),
+                source: err,
+            });
+        }
+        if let Err(err) = output.write_all(&[0]) {
+            error!("[calf] Could not write last export byte: {err:?}");
+            return Err(CalfError::WriteFile {
+                offset: Offset::Output(total - 1),
+                source: err,
+            });
+        }
+    }
+
+    if let Err(err) = output.flush() {
+        error!("[calf] Could not flush export output: {err:?}");
+        return Err(CalfError::WriteFile {
+            offset: Offset::Output(total),
+            source: err,
+        });
+    }
+
+    Ok(ExportInfo {
+        bytes: total,
+        data_bytes,
+        hashes: hashers.finalize(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportHash;
+    use crate::{
+        bootsector::boot::{Partition, PartitionType},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use md5::{Digest, Md5};
+    use std::{
+        fs::File,
+        io::{BufReader, Cursor, Read, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_export() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+
+        let mut expected = Vec::new();
+        os_reader.read_to_end(&mut expected).unwrap();
+
+        let mut output = Cursor::new(Vec::new());
+        let mut last_progress = 0;
+        let result = os_reader
+            .export(
+                &mut output,
+                &ExportHash {
+                    md5: true,
+                    sha1: true,
+                    sha256: false,
+                },
+                |done, total| {
+                    assert_eq!(total, 131072);
+                    last_progress = done;
+                },
+            )
+            .unwrap();
+
+        assert_eq!(last_progress, 131072);
+        assert_eq!(result.bytes, 131072);
+        assert_eq!(result.data_bytes, 2560);
+        assert_eq!(output.get_ref().len(), expected.len());
+        assert_eq!(output.get_ref()[..5120], expected[..5120]);
+        assert_eq!(output.get_ref()[65536..66048], expected[65536..66048]);
+
+        let mut md5 = Md5::new();
+        md5.update(output.get_ref());
+        assert_eq!(result.hashes.md5, format!("{:x}", md5.finalize()));
+        assert_eq!(result.hashes.sha1.len(), 40);
+        assert!(result.hashes.sha256.is_empty());
+
+        // Old bytes would remain in the holes
+        let mut used = Cursor::new(vec![1; 16]);
+        assert!(
+            os_reader
+                .export(&mut used, &ExportHash::default(), |_, _| {})
+                .is_err()
+        );
+        assert_eq!(used.get_ref(), &vec![1; 16]);
+    }
+
+    #[test]
+    fn test_export_partition() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let partition = Partition {
+            partition_type: PartitionType::Linux,
+            partition_type_value: 0x83,
+            type_name: String::from("Linux"),
+            first_logical_offset: 128,
+            offset_start: 65536,
+            sectors_in_partition: 4,
+            partition_size: 2048,
+            ..Default::default()
+        };
+
+        let mut output = Cursor::new(Vec::new());
+        let result = os_reader
+            .export_partition(&partition, &mut output, &ExportHash::default(), |_, _| {})
+            .unwrap();
+
+        assert_eq!(result.bytes, 2048);
+        assert_eq!(result.data_bytes, 512);
+        assert!(result.hashes.md5.is_empty());
+
+        let mut expected = vec![0; 2048];
+        os_reader.seek(SeekFrom::Start(65536)).unwrap();
+        os_reader.read_exact(&mut expected).unwrap();
+        assert_eq!(output.get_ref(), &expected);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{
+    error::CalfError,
+    map::{MapEntry, level1_map},
+    reader::OsReader,
+};
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek},
+};
+
+/// A contiguous range of the guest disk
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extent {
+    pub start: u64,
+    pub length: u64,
+    pub extent_type: ExtentType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExtentType {
+    /// Range has data in the QCOW file. Includes compressed clusters
+    Data,
+    /// Range is marked as all zeros in the level 2 table
+    Zero,
+    /// Range has no clusters allocated. Reads as zeros
+    Unallocated,
+}
+
+/// Iterate through the guest disk extents using the level 1 and level 2 tables.
+/// Level 2 tables are only read when needed
+pub struct Extents<'os, 'qcow, 'reader, T: Seek + Read> {
+    reader: &'os mut OsReader<'qcow, 'reader, T>,
+    level1_index: usize,
+    queue: VecDeque<Extent>,
+}
+
+impl<'os, 'qcow, 'reader, T: Seek + Read> Extents<'os, 'qcow, 'reader, T> {
+    /// Start iterating at the level 1 entry that covers the provided guest offset
+    pub(crate) fn new(reader: &'os mut OsReader<'qcow, 'reader, T>, offset: u64) -> Self {
+        let size = 8;
+        let level1_span = reader.cluster_size * (reader.cluster_size / size);
+
+        Extents {
+            level1_index: (offset / level1_span) as usize,
+            reader,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Seek + Read> Iterator for Extents<'_, '_, '_, T> {
+    type Item = Result<Extent, CalfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // The last queued extent may continue into the next level 1 range
+            if self.queue.len() > 1 {
+                return self.queue.pop_front().map(Ok);
+            }
+            if self.level1_index >= self.reader.qcow.level1_table.len() {
+                return self.queue.pop_front().map(Ok);
+            }
+
+            let entries = match level1_map(self.reader, self.level1_index) {
+                Ok(result) => result,
+                Err(err) => {
+                    // Stop iterating on any errors
+                    self.level1_index = self.reader.qcow.level1_table.len();
+                    self.queue.clear();
+                    return Some(Err(err));
+                }
+            };
+            self.level1_index += 1;
+
+            for entry in &entries {
+                merge_extent(&mut self.queue, extent_from_map(entry));
+            }
+        }
+    }
+}
+
+/// Find the next guest offset at or after the provided offset that has data. Same as `SEEK_DATA`
+pub(crate) fn next_data<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    offset: u64,
+) -> Result<Option<u64>, CalfError> {
+    if offset >= reader.os_size {
+        return Ok(None);
+    }
+
+    for extent in Extents::new(reader, offset) {
+        let extent = extent?;
+        if extent.extent_type == ExtentType::Data && extent.start + extent.length > offset {
+            return Ok(Some(u64::max(extent.start, offset)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the next guest offset at or after the provided offset that reads as zeros. Same as `SEEK_HOLE`.
+/// The end of the guest disk always counts as a hole
+pub(crate) fn next_hole<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    offset: u64,
+) -> Result<Option<u64>, CalfError> {
+    let os_size = reader.os_size;
+    if offset >= os_size {
+        return Ok(None);
+    }
+
+    for extent in Extents::new(reader, offset) {
+        let extent = extent?;
+        if extent.extent_type != ExtentType::Data && extent.start + extent.length > offset {
+            return Ok(Some(u64::max(extent.start, offset)));
+        }
+    }
+
+    Ok(Some(os_size))
+}
+
+/// Map entries track more details than an extent needs
+fn extent_from_map(entry: &MapEntry) -> Extent {
+    let extent_type = if entry.data {
+        ExtentType::Data
+    } else if entry.present {
+        ExtentType::Zero
+    } else {
+        ExtentType::Unallocated
+    };
+
+    Extent {
+        start: entry.start,
+        length: entry.length,
+        extent_type,
+    }
+}
+
+/// Merge the extent into the previous one if both are the same type
+fn merge_extent(extents: &mut VecDeque<Extent>, extent: Extent) {
+    if let Some(last) = extents.back_mut()
+        && last.extent_type == extent.extent_type
+        && last.start + last.length == extent.start
+    {
+        last.length += extent.length;
+        return;
+    }
+
+    extents.push_back(extent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExtentType;
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Read},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_extents() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+
+        let results: Vec<_> = os_reader.extents().map(|entry| entry.unwrap()).collect();
+        assert_eq!(results.len(), 8);
+        assert_eq!(results[0].extent_type, ExtentType::Data);
+        assert_eq!(results[0].length, 1024);
+        assert_eq!(results[1].extent_type, ExtentType::Zero);
+        assert_eq!(results[1].length, 1024);
+        assert_eq!(results[3].extent_type, ExtentType::Unallocated);
+        assert_eq!(results[5].start, 5632);
+        assert_eq!(results[5].length, 59904);
+        assert_eq!(results[7].start + results[7].length, 131072);
+    }
+
+    #[test]
+    fn test_next_data_hole() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+
+        assert_eq!(os_reader.next_data(0).unwrap(), Some(0));
+        assert_eq!(os_reader.next_hole(0).unwrap(), Some(1024));
+        assert_eq!(os_reader.next_data(1024).unwrap(), Some(2048));
+        assert_eq!(os_reader.next_hole(2048).unwrap(), Some(2560));
+        assert_eq!(os_reader.next_data(2600).unwrap(), Some(5120));
+        assert_eq!(os_reader.next_data(5632).unwrap(), Some(65536));
+        assert_eq!(os_reader.next_hole(65600).unwrap(), Some(66048));
+        assert_eq!(os_reader.next_data(66048).unwrap(), None);
+        assert_eq!(os_reader.next_hole(70000).unwrap(), Some(70000));
+        assert_eq!(os_reader.next_hole(131072).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_holes() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+
+        let mut bytes = Vec::new();
+        os_reader.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 131072);
+        assert_eq!(bytes[0..512], [1; 512]);
+        assert_eq!(bytes[512..1024], [2; 512]);
+        assert_eq!(bytes[1024..2048], [0; 1024]);
+        assert_eq!(bytes[2048..2560], [3; 512]);
+        assert_eq!(bytes[2560..5120], [0; 2560]);
+        assert!(bytes[5632..65536].iter().all(|value| *value == 0));
+        assert_eq!(bytes[65536..66048], [4; 512]);
+        assert!(bytes[66048..].iter().all(|value| *value == 0));
+    }
+}
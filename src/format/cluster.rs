@@ -1,7 +1,29 @@
-use super::header::Compression;
+use super::{header::Compression, level::Level};
 use log::warn;
 use std::io::{self, BufReader, Read, Seek, SeekFrom};
 
+/// Where the bytes for a guest cluster come from
+#[derive(Debug, PartialEq)]
+pub(crate) enum ClusterLocation {
+    /// Unallocated and zero clusters read as zeros
+    Zeros,
+    /// Offset to the cluster in the QCOW file
+    Host(u64),
+}
+
+/// Determine where to read a guest cluster from. Shared by the sync and async readers
+pub(crate) fn cluster_location(level1: &Level, level2: Option<&Level>) -> ClusterLocation {
+    if level1.offset == 0 {
+        return ClusterLocation::Zeros;
+    }
+
+    match level2 {
+        Some(entry) if entry.is_compressed => ClusterLocation::Host(entry.offset),
+        Some(entry) if !entry.is_zero && entry.offset != 0 => ClusterLocation::Host(entry.offset),
+        _ => ClusterLocation::Zeros,
+    }
+}
+
 /// Read bytes from the qcow cluster region
 pub(crate) fn read_cluster<T: std::io::Seek + std::io::Read>(
     reader: &mut BufReader<T>,
@@ -10,9 +32,6 @@ pub(crate) fn read_cluster<T: std::io::Seek + std::io::Read>(
     compression: &Compression,
     is_compressed: &bool,
 ) -> io::Result<Vec<u8>> {
-    if *is_compressed {
-        warn!("[calf] Got compressed data? This is unsupported right now! Type: {compression:?}");
-    }
     if reader.seek(SeekFrom::Start(offset)).is_err() {
         return Err(io::Error::new(
             io::ErrorKind::UnexpectedEof,
@@ -21,7 +40,19 @@ pub(crate) fn read_cluster<T: std::io::Seek + std::io::Read>(
     }
     let mut buf = vec![0; cluster_size as usize];
     reader.read_exact(&mut buf)?;
-    Ok(buf)
+    decode_cluster(buf, compression, is_compressed)
+}
+
+/// Decode the raw cluster bytes read from the QCOW file. Shared by the sync and async readers
+pub(crate) fn decode_cluster(
+    data: Vec<u8>,
+    compression: &Compression,
+    is_compressed: &bool,
+) -> io::Result<Vec<u8>> {
+    if *is_compressed {
+        warn!("[calf] Got compressed data? This is unsupported right now! Type: {compression:?}");
+    }
+    Ok(data)
 }
 
 #[cfg(test)]
@@ -29,8 +60,9 @@ mod tests {
     use crate::{
         calf::{CalfReader, CalfReaderAction, QcowInfo},
         format::{
-            cluster::read_cluster,
+            cluster::{ClusterLocation, cluster_location, read_cluster},
             header::{CalfHeader, Compression},
+            level::Level,
         },
     };
     use std::{fs::File, io::BufReader, path::PathBuf};
@@ -49,15 +81,9 @@ mod tests {
             header: calf.header().unwrap(),
             level1_table: calf.level1_entries().unwrap(),
         };
-        let mut os_reader = calf.os_reader(&info).unwrap();
-        let bytes = read_cluster(
-            &mut os_reader.reader,
-            327680,
-            65536,
-            &Compression::Zlib,
-            &false,
-        )
-        .unwrap();
+        let os_reader = calf.os_reader(&info).unwrap();
+        let bytes =
+            read_cluster(os_reader.reader, 327680, 65536, &Compression::Zlib, &false).unwrap();
 
         assert_eq!(
             bytes[0..305],
@@ -81,4 +107,42 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_cluster_location() {
+        let level1 = Level {
+            offset: 196608,
+            is_copied: true,
+            is_compressed: false,
+            is_zero: false,
+        };
+        let mut level2 = Level {
+            offset: 327680,
+            is_copied: true,
+            is_compressed: false,
+            is_zero: false,
+        };
+        assert_eq!(
+            cluster_location(&level1, Some(&level2)),
+            ClusterLocation::Host(327680)
+        );
+
+        level2.is_zero = true;
+        assert_eq!(
+            cluster_location(&level1, Some(&level2)),
+            ClusterLocation::Zeros
+        );
+        assert_eq!(cluster_location(&level1, None), ClusterLocation::Zeros);
+
+        let unallocated = Level {
+            offset: 0,
+            is_copied: false,
+            is_compressed: false,
+            is_zero: false,
+        };
+        assert_eq!(
+            cluster_location(&unallocated, Some(&level2)),
+            ClusterLocation::Zeros
+        );
+    }
 }
@@ -1,11 +1,16 @@
 use super::features::Features;
-use crate::{calf::CalfReader, error::CalfError, utils::read::read_bytes};
+use crate::{
+    calf::CalfReader,
+    error::{CalfError, Offset, Structure},
+    utils::read::read_bytes,
+};
 use log::{error, warn};
 use nom::{bytes::complete::take, number::complete::be_u32};
 
 /// QCOW may have header extensions.
 /// All are optional
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Extensions {
     pub features: Vec<Features>,
 }
@@ -20,18 +25,22 @@ impl<T: std::io::Seek + std::io::Read> CalfExtensions<T> for CalfReader<T> {
         let size = 512;
         let offset = 112;
         let bytes = read_bytes(offset, size, &mut self.fs)?;
-        Extensions::grab_extensions(&bytes)
+        Extensions::grab_extensions(&bytes, offset)
     }
 }
 
 impl Extensions {
     /// Grab option header extensions
-    pub(crate) fn grab_extensions(data: &[u8]) -> Result<Extensions, CalfError> {
-        let extenions = match Extensions::get_extensions(data) {
+    pub(crate) fn grab_extensions(data: &[u8], offset: u64) -> Result<Extensions, CalfError> {
+        let extenions = match Extensions::get_extensions(data, offset) {
             Ok((_, result)) => result,
-            Err(_err) => {
+            Err(err) => {
                 error!("[calf] Could not parse the header extensions");
-                return Err(CalfError::HeaderExtensions);
+                return Err(CalfError::parse(
+                    Structure::HeaderExtensions,
+                    Offset::Host(offset),
+                    &err,
+                ));
             }
         };
 
@@ -39,7 +48,7 @@ impl Extensions {
     }
 
     /// Parse each header extension
-    fn get_extensions(data: &[u8]) -> nom::IResult<&[u8], Extensions> {
+    fn get_extensions(data: &[u8], offset: u64) -> nom::IResult<&[u8], Extensions> {
         let mut input = data;
         let mut ext = Extensions {
             features: Vec::new(),
@@ -50,6 +59,7 @@ impl Extensions {
             // Does not include the sig and size bytes
             let (remaining, size) = be_u32(remaining)?;
 
+            let feature_offset = offset + (data.len() - remaining.len()) as u64;
             let (remaining, feature_data) = take(size)(remaining)?;
             let padding_size = 8;
             let padding_value = size % padding_size;
@@ -62,7 +72,8 @@ impl Extensions {
                 0x0 => break,
                 0xe2792aca => warn!("[calf] Have backing file extension"),
                 0x6803f857 => {
-                    ext.features = Features::grab_features(feature_data).unwrap_or_default();
+                    ext.features =
+                        Features::grab_features(feature_data, feature_offset).unwrap_or_default();
                 }
                 0x23852875 => warn!("[calf] Have bitmaps extension"),
                 0x0537be77 => warn!("[calf] Have encryption info"),
@@ -84,7 +95,7 @@ mod tests {
     fn test_grab_extensions() {
         let test = [104, 3, 248, 87];
 
-        let _ = Extensions::grab_extensions(&test).unwrap();
+        let _ = Extensions::grab_extensions(&test, 112).unwrap();
     }
 
     #[test]
@@ -113,7 +124,7 @@ mod tests {
             0,
         ];
 
-        let (_, extensions) = Extensions::get_extensions(&test).unwrap();
+        let (_, extensions) = Extensions::get_extensions(&test, 112).unwrap();
         assert_eq!(extensions.features.len(), 8);
     }
 }
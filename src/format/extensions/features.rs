@@ -1,8 +1,12 @@
-use crate::{error::CalfError, utils::strings::extract_utf8_string};
+use crate::{
+    error::{CalfError, Offset, Structure},
+    utils::strings::extract_utf8_string,
+};
 use log::{error, warn};
 use nom::{bytes::complete::take, number::complete::be_u8};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Features {
     pub feature_type: FeatureType,
     pub bit_number: u8,
@@ -10,6 +14,7 @@ pub struct Features {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FeatureType {
     Incompatible,
     Compatible,
@@ -19,12 +24,16 @@ pub enum FeatureType {
 
 impl Features {
     /// Grab any Features from header extension
-    pub(crate) fn grab_features(data: &[u8]) -> Result<Vec<Features>, CalfError> {
+    pub(crate) fn grab_features(data: &[u8], offset: u64) -> Result<Vec<Features>, CalfError> {
         let features = match Features::get_features(data) {
             Ok((_, result)) => result,
-            Err(_err) => {
+            Err(err) => {
                 error!("[calf] Could not pare features extension");
-                return Err(CalfError::HeaderExtensionFeatures);
+                return Err(CalfError::parse(
+                    Structure::HeaderExtensionFeatures,
+                    Offset::Host(offset),
+                    &err,
+                ));
             }
         };
 
@@ -99,7 +108,7 @@ mod tests {
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
 
-        let results = Features::grab_features(&test).unwrap();
+        let results = Features::grab_features(&test, 0).unwrap();
         assert_eq!(results.len(), 8);
 
         assert_eq!(results[0].feature_type, FeatureType::Incompatible);
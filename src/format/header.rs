@@ -1,10 +1,15 @@
-use crate::{calf::CalfReader, error::CalfError, utils::read::read_bytes};
+use crate::{
+    calf::CalfReader,
+    error::{CalfError, Offset, Structure},
+    utils::read::read_bytes,
+};
 use log::error;
 use nom::number::complete::{be_u8, be_u32, be_u64};
 
 /// Header info for QCOW file. Only Version 3 supported
 /// Header docs: `https://github.com/qemu/qemu/blob/master/docs/interop/qcow2.txt`
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub sig: u32,
     pub version: u32,
@@ -31,6 +36,7 @@ pub struct Header {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Encryption {
     None,
     Aes,
@@ -39,6 +45,7 @@ pub enum Encryption {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IncompatFlags {
     Dirty,
     Corrupt,
@@ -49,6 +56,7 @@ pub enum IncompatFlags {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Compression {
     Zlib,
     Zstd,
@@ -57,12 +65,14 @@ pub enum Compression {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AutoClear {
     Bitmaps,
     DataFileRaw,
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompatFlags {
     LazyRefCounts,
 }
@@ -76,18 +86,23 @@ impl<T: std::io::Seek + std::io::Read> CalfHeader<T> for CalfReader<T> {
     fn header(&mut self) -> Result<Header, CalfError> {
         let size = 112;
         let bytes = read_bytes(0, size, &mut self.fs)?;
-        let header = match Header::get_header(&bytes) {
+        Header::grab_header(&bytes)
+    }
+}
+impl Header {
+    /// Grab the QCOW header from the provided bytes
+    pub(crate) fn grab_header(data: &[u8]) -> Result<Header, CalfError> {
+        let header = match Header::get_header(data) {
             Ok((_, results)) => results,
             Err(err) => {
                 error!("[calf] Could not parse the QCOW header: {err:?}");
-                return Err(CalfError::Header);
+                return Err(CalfError::parse(Structure::Header, Offset::Host(0), &err));
             }
         };
 
         Ok(header)
     }
-}
-impl Header {
+
     /// Parse the QCOW header data
     fn get_header(data: &[u8]) -> nom::IResult<&[u8], Header> {
         let (remaining, sig) = be_u32(data)?;
@@ -268,7 +283,7 @@ mod tests {
     fn test_get_incompat_flags() {
         let test = [1, 2, 4, 8, 16];
         for entry in test {
-            assert!(!Header::get_incompat_flags(&entry).is_empty())
+            assert!(!Header::get_incompat_flags(&entry).is_empty());
         }
     }
 
@@ -276,7 +291,7 @@ mod tests {
     fn test_get_compat_flags() {
         let test = [1];
         for entry in test {
-            assert!(!Header::get_compat_flags(&entry).is_empty())
+            assert!(!Header::get_compat_flags(&entry).is_empty());
         }
     }
 
@@ -284,7 +299,28 @@ mod tests {
     fn test_get_auto_clear_flags() {
         let test = [1, 2];
         for entry in test {
-            assert!(!Header::get_auto_clear_flags(&entry).is_empty())
+            assert!(!Header::get_auto_clear_flags(&entry).is_empty());
         }
     }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialize_header() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/headers/header_version3.raw");
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let result = calf.header().unwrap();
+
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["size"], 85899345920u64);
+        assert_eq!(value["compression_method"], "Zlib");
+        assert_eq!(value["encryption_method"], "None");
+
+        let header: Header = serde_json::from_value(value).unwrap();
+        assert_eq!(header.level_one_table_offset, 262144);
+        assert_eq!(header.compression_method, Compression::Zlib);
+    }
 }
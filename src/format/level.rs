@@ -1,15 +1,22 @@
-use crate::{calf::CalfReader, error::CalfError, utils::read::read_bytes};
-use log::{error, warn};
+use crate::{
+    calf::CalfReader,
+    error::{CalfError, Offset, Structure},
+    utils::read::read_bytes,
+};
+use log::error;
 use nom::number::complete::be_u64;
 use std::io::{BufReader, Read, Seek, SeekFrom};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Level {
     /// Level 1 table offset is to Level 2 table.  
     /// Level 2 table offset is to cluster block
     pub offset: u64,
     pub is_copied: bool,
     pub is_compressed: bool,
+    /// Level 2 cluster reads as all zeros. Never set for compressed clusters
+    pub is_zero: bool,
 }
 
 pub trait CalfLevel<T: std::io::Seek + std::io::Read> {
@@ -20,15 +27,7 @@ pub trait CalfLevel<T: std::io::Seek + std::io::Read> {
 impl<T: std::io::Seek + std::io::Read> CalfLevel<T> for CalfReader<T> {
     fn levels(&mut self, offset: u64, level_entries: u32) -> Result<Vec<Level>, CalfError> {
         let bytes = read_bytes(offset, level_entries as u64, &mut self.fs)?;
-        let value = match Level::get_levels(&bytes) {
-            Ok((_, results)) => results,
-            Err(_err) => {
-                error!("[calf] Failed to parse level");
-                return Err(CalfError::Level);
-            }
-        };
-
-        Ok(value)
+        Level::grab_levels(&bytes, offset)
     }
 }
 
@@ -37,63 +36,78 @@ pub(crate) fn read_level<T: std::io::Seek + std::io::Read>(
     cluster_bits: &u32,
     offset: &u64,
 ) -> Result<Vec<Level>, CalfError> {
-    if reader.seek(SeekFrom::Start(*offset)).is_err() {
+    if let Err(err) = reader.seek(SeekFrom::Start(*offset)) {
         error!("[calf] Could not seek to level offset");
-        return Err(CalfError::SeekFile);
+        return Err(CalfError::SeekFile {
+            offset: Offset::Host(*offset),
+            source: err,
+        });
     }
 
+    // A level table cut off by the end of the qcow file is an error. Missing entries must not read as zeros
     let mut buf = vec![0; (1 << *cluster_bits) as usize];
-    if let Ok(bytes) = reader.read(&mut buf) {
-        let levels = match Level::get_levels(&buf) {
+    if let Err(err) = reader.read_exact(&mut buf) {
+        error!("[calf] Could not read level table at {offset}: {err:?}");
+        return Err(CalfError::ReadFile {
+            offset: Offset::Host(*offset),
+            source: err,
+        });
+    }
+    Level::grab_levels(&buf, *offset)
+}
+
+impl Level {
+    /// Grab the `Levels` from the provided bytes
+    pub(crate) fn grab_levels(data: &[u8], offset: u64) -> Result<Vec<Level>, CalfError> {
+        let value = match Level::get_levels(data) {
             Ok((_, results)) => results,
-            Err(_err) => {
+            Err(err) => {
                 error!("[calf] Failed to parse level");
-                return Err(CalfError::Level);
+                return Err(CalfError::parse(
+                    Structure::LevelTable,
+                    Offset::Host(offset),
+                    &err,
+                ));
             }
         };
 
-        if bytes != buf.len() {
-            warn!(
-                "[calf] Bytes read does not equal expected cluster bits size {}",
-                1 << *cluster_bits
-            );
-        }
-
-        return Ok(levels);
+        Ok(value)
     }
 
-    error!("[calf] Could not read to level data");
-    Err(CalfError::ReadFile)
-}
-
-impl Level {
     /// Parse the `Levels` data
     fn get_levels(data: &[u8]) -> nom::IResult<&[u8], Vec<Level>> {
         let mut input = data;
         let min_size = 8;
-        let offset_check = 0xfffffffffffe00;
 
         let mut levels = Vec::new();
-        // Last two bits will determine if the data is compressed
-        let is_copied = 0x8000000000000000;
-        let is_compressed = 0x4000000000000000;
         while input.len() >= min_size {
             let (remaining, value) = be_u64(input)?;
             input = remaining;
 
             // Even if the offset is 0. Do not skip
-            let offset = value & offset_check;
-            let level = Level {
-                offset,
-                is_compressed: value & is_compressed != 0,
-                is_copied: value & is_copied != 0,
-            };
-
-            levels.push(level);
+            levels.push(Level::from_value(value));
         }
 
         Ok((input, levels))
     }
+
+    /// Decode a single level table entry
+    pub(crate) fn from_value(value: u64) -> Level {
+        let offset_check = 0xfffffffffffe00;
+        // Last two bits will determine if the data is compressed
+        let is_copied = 0x8000000000000000;
+        let is_compressed = 0x4000000000000000;
+        // First bit marks a zero cluster (standard clusters only)
+        let is_zero = 0x1;
+
+        let compressed = value & is_compressed != 0;
+        Level {
+            offset: value & offset_check,
+            is_compressed: compressed,
+            is_copied: value & is_copied != 0,
+            is_zero: !compressed && value & is_zero != 0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -117,8 +131,8 @@ mod tests {
         let results = calf.levels(0, 1280).unwrap();
         assert_eq!(results.len(), 160);
         assert_eq!(results[0].offset, 196608);
-        assert_eq!(results[0].is_compressed, false);
-        assert_eq!(results[0].is_copied, true);
+        assert!(!results[0].is_compressed);
+        assert!(results[0].is_copied);
         assert_eq!(results[123].offset, 10878976);
         assert_eq!(results[159].offset, 393216);
         assert_eq!(results[1].offset, 1572864);
@@ -135,8 +149,8 @@ mod tests {
         let results = calf.levels(0, 65536).unwrap();
         assert_eq!(results.len(), 8192);
         assert_eq!(results[0].offset, 327680);
-        assert_eq!(results[0].is_compressed, false);
-        assert_eq!(results[0].is_copied, true);
+        assert!(!results[0].is_compressed);
+        assert!(results[0].is_copied);
         assert_eq!(results[123].offset, 39911424);
         assert_eq!(results[159].offset, 42532864);
         assert_eq!(results[1].offset, 0);
@@ -151,8 +165,8 @@ mod tests {
         let (_, results) = Level::get_levels(&test).unwrap();
         assert_eq!(results.len(), 8192);
         assert_eq!(results[0].offset, 327680);
-        assert_eq!(results[0].is_compressed, false);
-        assert_eq!(results[0].is_copied, true);
+        assert!(!results[0].is_compressed);
+        assert!(results[0].is_copied);
         assert_eq!(results[123].offset, 39911424);
         assert_eq!(results[159].offset, 42532864);
         assert_eq!(results[1].offset, 0);
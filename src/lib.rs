@@ -7,7 +7,7 @@
     clippy::dbg_macro,
     clippy::debug_assert_with_mut_call,
     clippy::doc_markdown,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::enum_glob_use,
     clippy::exit,
     clippy::expl_impl_clone_on_copy,
@@ -71,9 +71,20 @@
     rust_2018_idioms
 )]
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
 pub mod bootsector;
 pub mod calf;
-mod error;
+pub mod error;
+pub mod export;
+pub mod extents;
 pub mod format;
+pub mod luks;
+pub mod lvm;
+pub mod map;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod partition;
 pub mod reader;
+pub mod swap;
 mod utils;
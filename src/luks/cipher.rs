@@ -0,0 +1,167 @@
+use aes::{
+    Aes128, Aes192, Aes256,
+    cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray},
+};
+use sha2::{Digest, Sha256};
+
+/// AES with any of the key sizes LUKS uses
+enum AesKey {
+    Aes128(Box<Aes128>),
+    Aes192(Box<Aes192>),
+    Aes256(Box<Aes256>),
+}
+
+impl AesKey {
+    fn new(key: &[u8]) -> Result<AesKey, String> {
+        let cipher = match key.len() {
+            16 => Aes128::new_from_slice(key).map(|value| AesKey::Aes128(Box::new(value))),
+            24 => Aes192::new_from_slice(key).map(|value| AesKey::Aes192(Box::new(value))),
+            32 => Aes256::new_from_slice(key).map(|value| AesKey::Aes256(Box::new(value))),
+            _ => return Err(format!("AES key size {} is not supported", key.len())),
+        };
+        cipher.map_err(|err| format!("bad AES key: {err}"))
+    }
+
+    fn encrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            AesKey::Aes128(cipher) => cipher.encrypt_block(block),
+            AesKey::Aes192(cipher) => cipher.encrypt_block(block),
+            AesKey::Aes256(cipher) => cipher.encrypt_block(block),
+        }
+    }
+
+    fn decrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            AesKey::Aes128(cipher) => cipher.decrypt_block(block),
+            AesKey::Aes192(cipher) => cipher.decrypt_block(block),
+            AesKey::Aes256(cipher) => cipher.decrypt_block(block),
+        }
+    }
+}
+
+enum Mode {
+    /// XTS has a second key for the tweak
+    Xts(AesKey),
+    Cbc,
+}
+
+enum Iv {
+    /// Lower 32 bits of the sector number
+    Plain,
+    Plain64,
+    /// Sector number encrypted with the hash of the key
+    Essiv(AesKey),
+}
+
+/// dm-crypt sector cipher from a cipher spec. Ex: `aes-xts-plain64` or `aes-cbc-essiv:sha256`
+pub(crate) struct SectorCipher {
+    cipher: AesKey,
+    mode: Mode,
+    iv: Iv,
+}
+
+impl SectorCipher {
+    pub(crate) fn new(spec: &str, key: &[u8]) -> Result<SectorCipher, String> {
+        let mut parts = spec.splitn(3, '-');
+        let (Some("aes"), Some(mode), Some(iv)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("cipher {spec} is not supported"));
+        };
+
+        let (cipher, mode) = match mode {
+            "xts" => {
+                let (data_key, tweak_key) = key.split_at(key.len() / 2);
+                (AesKey::new(data_key)?, Mode::Xts(AesKey::new(tweak_key)?))
+            }
+            "cbc" => (AesKey::new(key)?, Mode::Cbc),
+            _ => return Err(format!("cipher mode {mode} is not supported")),
+        };
+        let iv = match iv {
+            "plain" => Iv::Plain,
+            "plain64" => Iv::Plain64,
+            "essiv:sha256" => Iv::Essiv(AesKey::new(&Sha256::digest(key))?),
+            _ => return Err(format!("IV mode {iv} is not supported")),
+        };
+
+        Ok(SectorCipher { cipher, mode, iv })
+    }
+
+    /// Decrypt one sector in place. The sector size must be a multiple of 16
+    pub(crate) fn decrypt_sector(&self, sector: u64, data: &mut [u8]) {
+        let mut iv = [0; 16];
+        match &self.iv {
+            Iv::Plain => iv[..4].copy_from_slice(&(sector as u32).to_le_bytes()),
+            Iv::Plain64 => iv[..8].copy_from_slice(&sector.to_le_bytes()),
+            Iv::Essiv(essiv) => {
+                iv[..8].copy_from_slice(&sector.to_le_bytes());
+                essiv.encrypt(&mut iv);
+            }
+        }
+
+        let block_size = 16;
+        match &self.mode {
+            Mode::Xts(tweak_key) => {
+                let mut tweak = iv;
+                tweak_key.encrypt(&mut tweak);
+                for block in data.chunks_exact_mut(block_size) {
+                    xor(block, &tweak);
+                    self.cipher.decrypt(block);
+                    xor(block, &tweak);
+                    multiply_tweak(&mut tweak);
+                }
+            }
+            Mode::Cbc => {
+                let mut previous = iv;
+                for block in data.chunks_exact_mut(block_size) {
+                    let mut encrypted = [0; 16];
+                    encrypted.copy_from_slice(block);
+                    self.cipher.decrypt(block);
+                    xor(block, &previous);
+                    previous = encrypted;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn xor(data: &mut [u8], value: &[u8]) {
+    for (byte, other) in data.iter_mut().zip(value) {
+        *byte ^= other;
+    }
+}
+
+/// Multiply the XTS tweak by x in GF(2^128)
+fn multiply_tweak(tweak: &mut [u8; 16]) {
+    let carry = tweak[15] >> 7;
+    for index in (1..16).rev() {
+        tweak[index] = (tweak[index] << 1) | (tweak[index - 1] >> 7);
+    }
+    tweak[0] <<= 1;
+    if carry == 1 {
+        tweak[0] ^= 0x87;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SectorCipher;
+
+    #[test]
+    fn test_sector_cipher() {
+        // IEEE 1619 XTS-AES-128 test vector 2
+        let key = [[0x11; 16], [0x22; 16]].concat();
+        let mut data = [
+            0xc4, 0x54, 0x18, 0x5e, 0x6a, 0x16, 0x93, 0x6e, 0x39, 0x33, 0x40, 0x38, 0xac, 0xef,
+            0x83, 0x8b, 0xfb, 0x18, 0x6f, 0xff, 0x74, 0x80, 0xad, 0xc4, 0x28, 0x93, 0x82, 0xec,
+            0xd6, 0xd3, 0x94, 0xf0,
+        ];
+        let cipher = SectorCipher::new("aes-xts-plain64", &key).unwrap();
+        cipher.decrypt_sector(0x3333333333, &mut data);
+        assert_eq!(data, [0x44; 32]);
+
+        assert!(SectorCipher::new("twofish-xts-plain64", &key).is_err());
+        assert!(SectorCipher::new("aes-ecb", &key).is_err());
+        assert!(SectorCipher::new("aes-cbc-essiv:sha256", &[0; 15]).is_err());
+    }
+}
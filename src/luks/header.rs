@@ -0,0 +1,624 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    utils::{encoding::base64_decode_standard, strings::extract_utf8_string},
+};
+use log::warn;
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{be_u16, be_u32, be_u64},
+};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom};
+
+/// LUKS1 or LUKS2 header. LUKS1 headers are converted to the LUKS2 layout of key slots, digests, and segments:
+/// <https://gitlab.com/cryptsetup/cryptsetup/-/wikis/Specification>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksHeader {
+    pub version: u16,
+    pub uuid: String,
+    /// Only LUKS2 has a label
+    pub label: Option<String>,
+    pub subsystem: Option<String>,
+    /// Size of the binary header and JSON area. LUKS1 uses the size of the binary header
+    pub header_size: u64,
+    /// LUKS2 header update counter
+    pub sequence_id: u64,
+    pub key_slots: Vec<LuksKeySlot>,
+    pub digests: Vec<LuksDigest>,
+    pub segments: Vec<LuksSegment>,
+    pub tokens: Vec<LuksToken>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksKeySlot {
+    pub id: u32,
+    /// Ex: `luks2`. LUKS1 key slots use `luks1`
+    pub slot_type: String,
+    /// Size of the master key in bytes
+    pub key_size: u64,
+    pub kdf: LuksKdf,
+    /// Offset of the encrypted key material from the start of the LUKS device
+    pub area_offset: u64,
+    pub area_size: u64,
+    /// Cipher for the key material. Ex: `aes-xts-plain64`
+    pub area_encryption: String,
+    /// Key size for the key material cipher in bytes
+    pub area_key_size: u64,
+    /// Anti-forensic splitter stripes
+    pub af_stripes: u32,
+    pub af_hash: String,
+}
+
+/// Key derivation function that turns a passphrase into the key slot key
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksKdf {
+    /// `pbkdf2`, `argon2i`, or `argon2id`
+    pub kdf_type: String,
+    /// Only for PBKDF2
+    pub hash: Option<String>,
+    /// Only for PBKDF2
+    pub iterations: Option<u32>,
+    /// Only for Argon2
+    pub time: Option<u32>,
+    /// Memory in KiB. Only for Argon2
+    pub memory: Option<u32>,
+    /// Only for Argon2
+    pub cpus: Option<u32>,
+    pub salt: Vec<u8>,
+}
+
+/// Digest used to check a master key
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksDigest {
+    pub id: u32,
+    /// Always `pbkdf2`
+    pub digest_type: String,
+    pub keyslots: Vec<u32>,
+    pub segments: Vec<u32>,
+    pub hash: String,
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub digest: Vec<u8>,
+}
+
+/// Encrypted data area
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksSegment {
+    pub id: u32,
+    /// Ex: `crypt`
+    pub segment_type: String,
+    /// Offset from the start of the LUKS device
+    pub offset: u64,
+    /// None if the segment goes to the end of the device
+    pub size: Option<u64>,
+    /// Added to the sector number for the IV
+    pub iv_tweak: u64,
+    /// Ex: `aes-xts-plain64`
+    pub encryption: String,
+    pub sector_size: u64,
+}
+
+/// Token with information for external unlock tools. Ex: `systemd-tpm2` or `systemd-fido2`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LuksToken {
+    pub id: u32,
+    pub token_type: String,
+    pub keyslots: Vec<u32>,
+    /// Token JSON object
+    pub json: String,
+}
+
+/// LUKS1 key slot before it is converted
+struct Luks1Slot {
+    active: u32,
+    iterations: u32,
+    salt: Vec<u8>,
+    key_material_offset: u32,
+    stripes: u32,
+}
+
+/// Read the LUKS header at the start of a LUKS device. Returns None if there is no LUKS signature.
+/// `start` is the location of the LUKS device in the reader. Ex: a partition offset in an `OsReader` or 0 for a `LogicalVolumeReader`
+pub fn read_luks_header<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+) -> Result<Option<LuksHeader>, CalfError> {
+    let binary_size = 4096;
+    let data = read_bytes(reader, start, binary_size)?;
+    if !data.starts_with(b"LUKS\xba\xbe") {
+        return Ok(None);
+    }
+    let version = u16::from_be_bytes([data[6], data[7]]);
+    match version {
+        1 => match parse_luks1(&data) {
+            Ok((_, result)) => Ok(Some(result)),
+            Err(err) => Err(CalfError::parse(
+                Structure::LuksHeader,
+                Offset::Guest(start),
+                &err,
+            )),
+        },
+        2 => read_luks2(reader, start, &data).map(Some),
+        _ => Err(CalfError::Parse {
+            structure: Structure::LuksHeader,
+            offset: Offset::Guest(start),
+            detail: format!("unknown LUKS version {version}"),
+        }),
+    }
+}
+
+/// Parse the LUKS1 header and convert it to the LUKS2 layout
+fn parse_luks1(data: &[u8]) -> nom::IResult<&[u8], LuksHeader> {
+    let (input, _magic) = take(6u8)(data)?;
+    let (input, version) = be_u16(input)?;
+    let name_size: u8 = 32;
+    let (input, cipher_name) = take(name_size)(input)?;
+    let (input, cipher_mode) = take(name_size)(input)?;
+    let (input, hash_spec) = take(name_size)(input)?;
+    let (input, payload_offset) = be_u32(input)?;
+    let (input, key_bytes) = be_u32(input)?;
+    let digest_size: u8 = 20;
+    let (input, mk_digest) = take(digest_size)(input)?;
+    let salt_size: u8 = 32;
+    let (input, mk_digest_salt) = take(salt_size)(input)?;
+    let (input, mk_digest_iter) = be_u32(input)?;
+    let uuid_size: u8 = 40;
+    let (mut input, uuid) = take(uuid_size)(input)?;
+
+    let max_slots = 8;
+    let mut slots = Vec::new();
+    for _ in 0..max_slots {
+        let (remaining, active) = be_u32(input)?;
+        let (remaining, iterations) = be_u32(remaining)?;
+        let (remaining, salt) = take(salt_size)(remaining)?;
+        let (remaining, key_material_offset) = be_u32(remaining)?;
+        let (remaining, stripes) = be_u32(remaining)?;
+        input = remaining;
+        slots.push(Luks1Slot {
+            active,
+            iterations,
+            salt: salt.to_vec(),
+            key_material_offset,
+            stripes,
+        });
+    }
+
+    let sector_size = 512;
+    let cipher = format!(
+        "{}-{}",
+        get_string(cipher_name).unwrap_or_default(),
+        get_string(cipher_mode).unwrap_or_default()
+    );
+    let hash = get_string(hash_spec).unwrap_or_default();
+
+    let enabled = 0x00ac71f3;
+    let mut key_slots = Vec::new();
+    for (id, slot) in slots.into_iter().enumerate() {
+        if slot.active != enabled {
+            continue;
+        }
+        // Key material is padded to a full sector
+        let material_size = (slot.stripes as u64 * key_bytes as u64).div_ceil(sector_size);
+        key_slots.push(LuksKeySlot {
+            id: id as u32,
+            slot_type: String::from("luks1"),
+            key_size: key_bytes as u64,
+            kdf: LuksKdf {
+                kdf_type: String::from("pbkdf2"),
+                hash: Some(hash.clone()),
+                iterations: Some(slot.iterations),
+                time: None,
+                memory: None,
+                cpus: None,
+                salt: slot.salt,
+            },
+            area_offset: slot.key_material_offset as u64 * sector_size,
+            area_size: material_size * sector_size,
+            area_encryption: cipher.clone(),
+            area_key_size: key_bytes as u64,
+            af_stripes: slot.stripes,
+            af_hash: hash.clone(),
+        });
+    }
+
+    let header = LuksHeader {
+        version,
+        uuid: get_string(uuid).unwrap_or_default(),
+        label: None,
+        subsystem: None,
+        header_size: data.len() as u64 - input.len() as u64,
+        sequence_id: 0,
+        digests: vec![LuksDigest {
+            id: 0,
+            digest_type: String::from("pbkdf2"),
+            keyslots: key_slots.iter().map(|slot| slot.id).collect(),
+            segments: vec![0],
+            hash,
+            iterations: mk_digest_iter,
+            salt: mk_digest_salt.to_vec(),
+            digest: mk_digest.to_vec(),
+        }],
+        key_slots,
+        segments: vec![LuksSegment {
+            id: 0,
+            segment_type: String::from("crypt"),
+            offset: payload_offset as u64 * sector_size,
+            size: None,
+            iv_tweak: 0,
+            encryption: cipher,
+            sector_size,
+        }],
+        tokens: Vec::new(),
+    };
+    Ok((input, header))
+}
+
+/// LUKS2 binary header fields before the JSON area
+struct Luks2Binary {
+    header_size: u64,
+    sequence_id: u64,
+    label: Option<String>,
+    uuid: String,
+    subsystem: Option<String>,
+    checksum: Vec<u8>,
+}
+
+/// Read the LUKS2 binary header and JSON area. The secondary header is used if the primary checksum is bad
+fn read_luks2<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    data: &[u8],
+) -> Result<LuksHeader, CalfError> {
+    let (binary, area) = read_luks2_area(reader, start, data)?;
+    let (binary, area) = if luks2_checksum(&area) == binary.checksum {
+        (binary, area)
+    } else {
+        let secondary_offset = start + binary.header_size;
+        let secondary = read_bytes(reader, secondary_offset, data.len() as u64)?;
+        match read_luks2_area(reader, secondary_offset, &secondary) {
+            Ok((secondary, secondary_area))
+                if luks2_checksum(&secondary_area) == secondary.checksum =>
+            {
+                warn!(
+                    "[calf] LUKS2 primary header at {start} has a bad checksum. Using the secondary header"
+                );
+                (secondary, secondary_area)
+            }
+            _ => {
+                warn!("[calf] LUKS2 header at {start} has a bad checksum");
+                (binary, area)
+            }
+        }
+    };
+
+    let json_start = 4096;
+    let json_data = &area[json_start..];
+    let end = json_data
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(json_data.len());
+    let json_offset = Offset::Guest(start + json_start as u64);
+    let json: Value = match serde_json::from_slice(&json_data[..end]) {
+        Ok(result) => result,
+        Err(err) => {
+            return Err(CalfError::Parse {
+                structure: Structure::LuksHeader,
+                offset: json_offset,
+                detail: format!("bad JSON: {err}"),
+            });
+        }
+    };
+
+    parse_luks2_json(&json, binary).ok_or(CalfError::Parse {
+        structure: Structure::LuksHeader,
+        offset: json_offset,
+        detail: String::from("JSON is missing required values"),
+    })
+}
+
+/// Read the whole LUKS2 header area at the offset. `data` is the binary header
+fn read_luks2_area<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    data: &[u8],
+) -> Result<(Luks2Binary, Vec<u8>), CalfError> {
+    let binary = match parse_luks2_binary(data) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            return Err(CalfError::parse(
+                Structure::LuksHeader,
+                Offset::Guest(offset),
+                &err,
+            ));
+        }
+    };
+    // Header sizes are 16 KiB to 4 MiB
+    let max_size = 4 * 1024 * 1024;
+    if binary.header_size <= data.len() as u64 || binary.header_size > max_size {
+        return Err(CalfError::Parse {
+            structure: Structure::LuksHeader,
+            offset: Offset::Guest(offset),
+            detail: format!("bad header size {}", binary.header_size),
+        });
+    }
+    let area = read_bytes(reader, offset, binary.header_size)?;
+    Ok((binary, area))
+}
+
+fn parse_luks2_binary(data: &[u8]) -> nom::IResult<&[u8], Luks2Binary> {
+    let (input, _magic) = take(6u8)(data)?;
+    let (input, _version) = be_u16(input)?;
+    let (input, header_size) = be_u64(input)?;
+    let (input, sequence_id) = be_u64(input)?;
+    let label_size: u8 = 48;
+    let (input, label) = take(label_size)(input)?;
+    let algorithm_size: u8 = 32;
+    let (input, checksum_algorithm) = take(algorithm_size)(input)?;
+    let salt_size: u8 = 64;
+    let (input, _salt) = take(salt_size)(input)?;
+    let uuid_size: u8 = 40;
+    let (input, uuid) = take(uuid_size)(input)?;
+    let (input, subsystem) = take(label_size)(input)?;
+    let (input, _header_offset) = be_u64(input)?;
+    let padding_size: u8 = 184;
+    let (input, _padding) = take(padding_size)(input)?;
+    let checksum_size: u8 = 32;
+    let (input, checksum) = take(checksum_size)(input)?;
+
+    // Only sha256 checksums are used by cryptsetup
+    let (_, _) = tag(&b"sha256\0"[..])(checksum_algorithm)?;
+
+    let binary = Luks2Binary {
+        header_size,
+        sequence_id,
+        label: get_string(label),
+        uuid: get_string(uuid).unwrap_or_default(),
+        subsystem: get_string(subsystem),
+        checksum: checksum.to_vec(),
+    };
+    Ok((input, binary))
+}
+
+/// SHA256 of the header area with the checksum field zeroed
+fn luks2_checksum(area: &[u8]) -> Vec<u8> {
+    let checksum_offset = 448;
+    let checksum_size = 64;
+    let mut hasher = Sha256::new();
+    hasher.update(&area[..checksum_offset]);
+    hasher.update([0; 64]);
+    hasher.update(&area[checksum_offset + checksum_size..]);
+    hasher.finalize().to_vec()
+}
+
+fn parse_luks2_json(json: &Value, binary: Luks2Binary) -> Option<LuksHeader> {
+    let mut key_slots = Vec::new();
+    for (id, slot) in json.get("keyslots")?.as_object()? {
+        let kdf = slot.get("kdf")?;
+        let area = slot.get("area")?;
+        let af = slot.get("af")?;
+        key_slots.push(LuksKeySlot {
+            id: id.parse().ok()?,
+            slot_type: get_json_string(slot, "type")?,
+            key_size: get_json_number(slot, "key_size")?,
+            kdf: LuksKdf {
+                kdf_type: get_json_string(kdf, "type")?,
+                hash: get_json_string(kdf, "hash"),
+                iterations: get_json_number(kdf, "iterations").map(|value| value as u32),
+                time: get_json_number(kdf, "time").map(|value| value as u32),
+                memory: get_json_number(kdf, "memory").map(|value| value as u32),
+                cpus: get_json_number(kdf, "cpus").map(|value| value as u32),
+                salt: get_json_base64(kdf, "salt")?,
+            },
+            area_offset: get_json_number(area, "offset")?,
+            area_size: get_json_number(area, "size")?,
+            area_encryption: get_json_string(area, "encryption")?,
+            area_key_size: get_json_number(area, "key_size")?,
+            af_stripes: get_json_number(af, "stripes")? as u32,
+            af_hash: get_json_string(af, "hash")?,
+        });
+    }
+
+    let mut digests = Vec::new();
+    for (id, digest) in json.get("digests")?.as_object()? {
+        digests.push(LuksDigest {
+            id: id.parse().ok()?,
+            digest_type: get_json_string(digest, "type")?,
+            keyslots: get_json_ids(digest, "keyslots"),
+            segments: get_json_ids(digest, "segments"),
+            hash: get_json_string(digest, "hash")?,
+            iterations: get_json_number(digest, "iterations")? as u32,
+            salt: get_json_base64(digest, "salt")?,
+            digest: get_json_base64(digest, "digest")?,
+        });
+    }
+
+    let mut segments = Vec::new();
+    for (id, segment) in json.get("segments")?.as_object()? {
+        segments.push(LuksSegment {
+            id: id.parse().ok()?,
+            segment_type: get_json_string(segment, "type")?,
+            offset: get_json_number(segment, "offset")?,
+            // Size is "dynamic" if the segment goes to the end of the device
+            size: get_json_number(segment, "size"),
+            iv_tweak: get_json_number(segment, "iv_tweak").unwrap_or_default(),
+            encryption: get_json_string(segment, "encryption").unwrap_or_default(),
+            sector_size: get_json_number(segment, "sector_size").unwrap_or(512),
+        });
+    }
+
+    let empty = Map::new();
+    let mut tokens = Vec::new();
+    let token_values = json
+        .get("tokens")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    for (id, token) in token_values {
+        tokens.push(LuksToken {
+            id: id.parse().ok()?,
+            token_type: get_json_string(token, "type")?,
+            keyslots: get_json_ids(token, "keyslots"),
+            json: token.to_string(),
+        });
+    }
+
+    key_slots.sort_by_key(|slot| slot.id);
+    digests.sort_by_key(|digest| digest.id);
+    segments.sort_by_key(|segment| segment.id);
+    tokens.sort_by_key(|token| token.id);
+
+    Some(LuksHeader {
+        version: 2,
+        uuid: binary.uuid,
+        label: binary.label,
+        subsystem: binary.subsystem,
+        header_size: binary.header_size,
+        sequence_id: binary.sequence_id,
+        key_slots,
+        digests,
+        segments,
+        tokens,
+    })
+}
+
+fn get_json_string(value: &Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(String::from)
+}
+
+/// LUKS2 stores 64-bit values as strings because JSON numbers may lose precision
+fn get_json_number(value: &Value, key: &str) -> Option<u64> {
+    match value.get(key)? {
+        Value::String(number) => number.parse().ok(),
+        number => number.as_u64(),
+    }
+}
+
+fn get_json_base64(value: &Value, key: &str) -> Option<Vec<u8>> {
+    base64_decode_standard(value.get(key)?.as_str()?)
+}
+
+/// Key slot and segment IDs are lists of strings
+fn get_json_ids(value: &Value, key: &str) -> Vec<u32> {
+    let Some(ids) = value.get(key).and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    ids.iter()
+        .filter_map(|id| id.as_str()?.parse().ok())
+        .collect()
+}
+
+/// Get a NULL padded string. Empty strings are None
+fn get_string(data: &[u8]) -> Option<String> {
+    let end = data
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(data.len());
+    let value = extract_utf8_string(&data[..end]);
+    if value.is_empty() {
+        return None;
+    }
+    Some(value)
+}
+
+pub(crate) fn read_bytes<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>, CalfError> {
+    if let Err(err) = reader.seek(SeekFrom::Start(offset)) {
+        return Err(CalfError::SeekFile {
+            offset: Offset::Guest(offset),
+            source: err,
+        });
+    }
+    let mut data = vec![0; size as usize];
+    if let Err(err) = reader.read_exact(&mut data) {
+        return Err(CalfError::ReadFile {
+            offset: Offset::Guest(offset),
+            source: err,
+        });
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_luks_header;
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Cursor, Read},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_luks_header() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/luks/luks.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+
+        let luks1 = os_reader.luks_header(&boot.partitions[0]).unwrap().unwrap();
+        assert_eq!(luks1.version, 1);
+        assert_eq!(luks1.uuid, "0f0e0d0c-1111-4222-8333-444444444444");
+        assert_eq!(luks1.key_slots.len(), 1);
+        let slot = &luks1.key_slots[0];
+        assert_eq!(slot.kdf.kdf_type, "pbkdf2");
+        assert_eq!(slot.kdf.iterations, Some(1000));
+        assert_eq!(slot.area_offset, 8 * 512);
+        assert_eq!(slot.area_size, 250 * 512);
+        assert_eq!(slot.af_stripes, 4000);
+        assert_eq!(luks1.segments[0].encryption, "aes-xts-plain64");
+        assert_eq!(luks1.segments[0].offset, 264 * 512);
+        assert_eq!(luks1.digests[0].digest.len(), 20);
+        assert_eq!(luks1.digests[0].keyslots, vec![0]);
+
+        let luks2 = os_reader.luks_header(&boot.partitions[1]).unwrap().unwrap();
+        assert_eq!(luks2.version, 2);
+        assert_eq!(luks2.uuid, "7a7a7a7a-5555-4666-8777-888888888888");
+        assert_eq!(luks2.label.as_deref(), Some("cryptroot"));
+        assert_eq!(luks2.header_size, 16384);
+        assert_eq!(luks2.sequence_id, 3);
+        assert_eq!(luks2.key_slots.len(), 2);
+        assert_eq!(luks2.key_slots[0].kdf.kdf_type, "argon2id");
+        assert_eq!(luks2.key_slots[0].kdf.memory, Some(1024));
+        assert_eq!(luks2.key_slots[1].kdf.hash.as_deref(), Some("sha512"));
+        assert_eq!(luks2.key_slots[1].area_offset, 290816);
+        assert_eq!(luks2.segments[0].size, None);
+        assert_eq!(luks2.segments[0].sector_size, 4096);
+        assert_eq!(luks2.digests[0].keyslots, vec![0, 1]);
+        assert_eq!(luks2.tokens[0].token_type, "systemd-tpm2");
+        assert!(luks2.tokens[0].json.contains("tpm2-pcrs"));
+
+        let mut partition = os_reader.partition_reader(&boot.partitions[1]);
+        let mut data = Vec::new();
+        partition.read_to_end(&mut data).unwrap();
+
+        // Secondary header is used if the primary checksum is bad
+        data[4096 + 10] ^= 0xff;
+        let mut reader = Cursor::new(data);
+        let header = read_luks_header(&mut reader, 0).unwrap().unwrap();
+        assert_eq!(header, luks2);
+
+        let mut reader = Cursor::new(vec![0; 4096]);
+        assert!(read_luks_header(&mut reader, 0).unwrap().is_none());
+    }
+}
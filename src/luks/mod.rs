@@ -0,0 +1,7 @@
+#[cfg(feature = "luks")]
+mod cipher;
+pub mod header;
+#[cfg(feature = "luks")]
+pub mod reader;
+#[cfg(feature = "luks")]
+pub mod unlock;
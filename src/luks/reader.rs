@@ -0,0 +1,276 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    luks::{cipher::SectorCipher, header::LuksHeader, unlock::verify_master_key},
+    reader::seek_position,
+};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Decrypted reader for the data segment of a LUKS device. Offset 0 is the start of the decrypted data
+pub struct LuksReader<R: Read + Seek> {
+    reader: R,
+    cipher: SectorCipher,
+    /// Offset of the encrypted data in the reader
+    start: u64,
+    size: u64,
+    sector_size: u64,
+    iv_tweak: u64,
+    position: u64,
+    /// Last decrypted sector
+    sector: Option<(u64, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> LuksReader<R> {
+    /// Create a decrypted reader for the first crypt segment. `start` and `size` are the location of the LUKS device in the reader.
+    /// Fails if the master key does not match the header digests
+    pub fn new(
+        reader: R,
+        start: u64,
+        size: u64,
+        header: &LuksHeader,
+        master_key: &[u8],
+    ) -> Result<LuksReader<R>, CalfError> {
+        let error = |detail: String| CalfError::Parse {
+            structure: Structure::LuksHeader,
+            offset: Offset::Guest(start),
+            detail,
+        };
+        let Some(segment) = header
+            .segments
+            .iter()
+            .find(|segment| segment.segment_type == "crypt")
+        else {
+            return Err(error(String::from("no crypt segment")));
+        };
+        if !verify_master_key(header, master_key)? {
+            return Err(error(String::from(
+                "master key does not match the header digests",
+            )));
+        }
+        let block_size = 16;
+        if segment.sector_size == 0 || segment.sector_size % block_size != 0 {
+            return Err(error(format!("bad sector size {}", segment.sector_size)));
+        }
+        let cipher = SectorCipher::new(&segment.encryption, master_key).map_err(error)?;
+
+        let available = size.saturating_sub(segment.offset);
+        let size = segment.size.unwrap_or(available).min(available);
+        Ok(LuksReader {
+            reader,
+            cipher,
+            start: start + segment.offset,
+            size,
+            sector_size: segment.sector_size,
+            iv_tweak: segment.iv_tweak,
+            position: 0,
+            sector: None,
+        })
+    }
+
+    /// Decrypted data size in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the guest disk reader back
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read and decrypt a sector if it is not the last one read
+    fn decrypt_sector(&mut self, sector: u64) -> std::io::Result<()> {
+        if self
+            .sector
+            .as_ref()
+            .is_some_and(|(cached, _)| *cached == sector)
+        {
+            return Ok(());
+        }
+        let mut data = vec![0; self.sector_size as usize];
+        self.reader
+            .seek(SeekFrom::Start(self.start + sector * self.sector_size))?;
+        self.reader.read_exact(&mut data)?;
+        // LUKS2 counts IVs in sector size units
+        self.cipher
+            .decrypt_sector(self.iv_tweak + sector, &mut data);
+        self.sector = Some((sector, data));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for LuksReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+
+        let sector = self.position / self.sector_size;
+        self.decrypt_sector(sector)?;
+        let Some((_, data)) = &self.sector else {
+            return Ok(0);
+        };
+        let in_sector = (self.position % self.sector_size) as usize;
+        let read_len = (data.len() - in_sector)
+            .min(buf.len())
+            .min((self.size - self.position) as usize);
+        buf[..read_len].copy_from_slice(&data[in_sector..in_sector + read_len]);
+        self.position += read_len as u64;
+
+        Ok(read_len)
+    }
+}
+
+impl<R: Read + Seek> Seek for LuksReader<R> {
+    /// Seeking past the end of the decrypted data stops at the data size
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let new_position = seek_position(self.position, self.size, position)?;
+        self.position = new_position.min(self.size);
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        utils::strings::sector_tag,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_luks1_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/luks/luks.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let partition = &boot.partitions[0];
+        let header = os_reader.luks_header(partition).unwrap().unwrap();
+
+        assert!(
+            os_reader
+                .luks_unlock(partition, &header, b"wrong")
+                .unwrap()
+                .is_none()
+        );
+        let key = os_reader
+            .luks_unlock(partition, &header, b"calf-luks1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.len(), 32);
+
+        // Key slots that cannot be used are skipped
+        let mut bad_slot = header.key_slots[0].clone();
+        bad_slot.kdf.kdf_type = String::from("scrypt");
+        let mut skipped = header.clone();
+        skipped.key_slots.insert(0, bad_slot.clone());
+        let unlocked = os_reader
+            .luks_unlock(partition, &skipped, b"calf-luks1")
+            .unwrap();
+        assert_eq!(unlocked.unwrap(), key);
+
+        // Error if no key slot could be tried
+        skipped.key_slots = vec![bad_slot];
+        assert!(
+            os_reader
+                .luks_unlock(partition, &skipped, b"calf-luks1")
+                .is_err()
+        );
+
+        // Values from a crafted header are rejected instead of overflowing or allocating
+        let mut crafted = Vec::new();
+        let mut slot = header.key_slots[0].clone();
+        slot.key_size = u64::MAX;
+        crafted.push(slot);
+        let mut slot = header.key_slots[0].clone();
+        slot.area_key_size = 1 << 40;
+        crafted.push(slot);
+        let mut slot = header.key_slots[0].clone();
+        slot.area_size = u64::MAX;
+        crafted.push(slot);
+        let mut slot = header.key_slots[0].clone();
+        slot.area_offset = u64::MAX;
+        crafted.push(slot);
+        let mut slot = header.key_slots[0].clone();
+        slot.kdf.iterations = Some(u32::MAX);
+        crafted.push(slot);
+        let mut slot = header.key_slots[0].clone();
+        slot.kdf.memory = Some(u32::MAX);
+        crafted.push(slot);
+        for slot in crafted {
+            skipped.key_slots = vec![slot];
+            assert!(
+                os_reader
+                    .luks_unlock(partition, &skipped, b"calf-luks1")
+                    .is_err()
+            );
+        }
+
+        let mut luks = os_reader.luks_reader(partition, &header, &key).unwrap();
+        assert_eq!(luks.size(), (1024 - 264) * 512);
+        let mut data = Vec::new();
+        luks.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), (1024 - 264) * 512);
+        assert_eq!(sector_tag(&data), "luks1:0");
+        assert_eq!(sector_tag(&data[1024..]), "luks1:1024");
+        assert_eq!(sector_tag(&data[data.len() - 512..]), "luks1:388608");
+
+        // Wrong master keys are rejected
+        let os_reader = luks.into_inner();
+        assert!(os_reader.luks_reader(partition, &header, &[0; 32]).is_err());
+    }
+
+    #[test]
+    fn test_luks2_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/luks/luks.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let partition = &boot.partitions[1];
+        let header = os_reader.luks_header(partition).unwrap().unwrap();
+
+        // Argon2id key slot and PBKDF2 key slot unlock the same master key
+        let key = os_reader
+            .luks_unlock(partition, &header, b"calf-luks2")
+            .unwrap()
+            .unwrap();
+        let backup = os_reader
+            .luks_unlock(partition, &header, b"backup-pass")
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, backup);
+        assert_eq!(key.len(), 64);
+
+        // 4096 byte sectors
+        let mut luks = os_reader.luks_reader(partition, &header, &key).unwrap();
+        assert_eq!(luks.size(), 3072 * 512 - 1048576);
+        let mut sector = [0; 512];
+        for offset in [0, 512, 4096, 8192 + 1536, 524288 - 512] {
+            luks.seek(SeekFrom::Start(offset)).unwrap();
+            luks.read_exact(&mut sector).unwrap();
+            assert_eq!(sector_tag(&sector), format!("luks2:{offset}"));
+        }
+        assert_eq!(luks.read(&mut sector).unwrap(), 0);
+    }
+}
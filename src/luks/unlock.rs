@@ -0,0 +1,303 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    luks::{
+        cipher::{SectorCipher, xor},
+        header::{LuksHeader, LuksKdf, LuksKeySlot, read_bytes},
+    },
+};
+use log::warn;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::io::{Read, Seek};
+
+/// Try the passphrase on every key slot. Returns the master key if a key slot unlocks.
+/// Key slots that cannot be used are skipped. Returns an error only if no key slot could be tried.
+/// `start` is the location of the LUKS device in the reader
+pub fn unlock_passphrase<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    header: &LuksHeader,
+    passphrase: &[u8],
+) -> Result<Option<Vec<u8>>, CalfError> {
+    let mut last_error = None;
+    let mut tried = false;
+    for slot in &header.key_slots {
+        match try_key_slot(reader, start, header, slot, passphrase) {
+            Ok(Some(master_key)) => return Ok(Some(master_key)),
+            Ok(None) => tried = true,
+            Err(err) => {
+                warn!("[calf] Could not use LUKS key slot {}: {err}", slot.id);
+                last_error = Some(err);
+            }
+        }
+    }
+    match last_error {
+        Some(err) if !tried => Err(err),
+        _ => Ok(None),
+    }
+}
+
+/// Try the passphrase on one key slot. Returns the master key if the digest matches
+fn try_key_slot<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    header: &LuksHeader,
+    slot: &LuksKeySlot,
+    passphrase: &[u8],
+) -> Result<Option<Vec<u8>>, CalfError> {
+    let slot_error = |detail: String| key_slot_error(start, slot, detail);
+
+    check_key_slot(slot).map_err(slot_error)?;
+    let key = derive_key(&slot.kdf, passphrase, slot.area_key_size).map_err(slot_error)?;
+    let key_material = read_key_material(reader, start, slot, &key)?;
+    let master_key = af_merge(
+        &key_material,
+        slot.key_size as usize,
+        slot.af_stripes as usize,
+        &slot.af_hash,
+    )
+    .map_err(slot_error)?;
+
+    if digest_matches(header, Some(slot.id), &master_key).map_err(slot_error)? {
+        return Ok(Some(master_key));
+    }
+    Ok(None)
+}
+
+/// Reject key slot values that are larger than cryptsetup allows. The values come from the untrusted header
+fn check_key_slot(slot: &LuksKeySlot) -> Result<(), String> {
+    // Same limits as cryptsetup. Key slot areas are limited to 128 MiB
+    let max_key_size = 64;
+    let max_area_size = 128 * 1024 * 1024;
+    let max_stripes = 4000;
+    if slot.key_size == 0 || slot.key_size > max_key_size {
+        return Err(format!("key size {} is not supported", slot.key_size));
+    }
+    if slot.area_key_size == 0 || slot.area_key_size > max_key_size {
+        return Err(format!(
+            "area key size {} is not supported",
+            slot.area_key_size
+        ));
+    }
+    if slot.area_size > max_area_size {
+        return Err(format!("area size {} is too large", slot.area_size));
+    }
+    if slot.af_stripes > max_stripes {
+        return Err(format!("{} stripes is too many", slot.af_stripes));
+    }
+    check_kdf_cost(
+        slot.kdf.iterations,
+        slot.kdf.memory,
+        slot.kdf.time,
+        slot.kdf.cpus,
+    )
+}
+
+/// Cap the KDF cost so a crafted header cannot use all the memory or take forever
+fn check_kdf_cost(
+    iterations: Option<u32>,
+    memory: Option<u32>,
+    time: Option<u32>,
+    cpus: Option<u32>,
+) -> Result<(), String> {
+    // cryptsetup uses at most 4 threads and 4 GiB. We keep memory to its 1 GiB default
+    let max_iterations = 50_000_000;
+    let max_memory = 1024 * 1024;
+    let max_time = 1000;
+    let max_cpus = 4;
+    if iterations.is_some_and(|value| value > max_iterations) {
+        return Err(format!(
+            "{} PBKDF2 iterations is too many",
+            iterations.unwrap_or_default()
+        ));
+    }
+    if memory.is_some_and(|value| value > max_memory)
+        || time.is_some_and(|value| value > max_time)
+        || cpus.is_some_and(|value| value > max_cpus)
+    {
+        return Err(String::from("Argon2 cost parameters are too large"));
+    }
+    Ok(())
+}
+
+/// Check the master key against the header digests
+pub fn verify_master_key(header: &LuksHeader, master_key: &[u8]) -> Result<bool, CalfError> {
+    digest_matches(header, None, master_key).map_err(|detail| CalfError::Parse {
+        structure: Structure::LuksHeader,
+        offset: Offset::Guest(0),
+        detail,
+    })
+}
+
+/// Check the master key against the digests. Only digests for the key slot are used if one is provided
+fn digest_matches(
+    header: &LuksHeader,
+    slot: Option<u32>,
+    master_key: &[u8],
+) -> Result<bool, String> {
+    for digest in &header.digests {
+        if slot.is_some_and(|id| !digest.keyslots.contains(&id)) {
+            continue;
+        }
+        if digest.digest_type != "pbkdf2" {
+            return Err(format!(
+                "digest type {} is not supported",
+                digest.digest_type
+            ));
+        }
+        check_kdf_cost(Some(digest.iterations), None, None, None)?;
+        let mut value = vec![0; digest.digest.len()];
+        pbkdf2(
+            &digest.hash,
+            master_key,
+            &digest.salt,
+            digest.iterations,
+            &mut value,
+        )?;
+        if value == digest.digest {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Read and decrypt the split master key in the key slot area
+fn read_key_material<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    slot: &LuksKeySlot,
+    key: &[u8],
+) -> Result<Vec<u8>, CalfError> {
+    let sector_size = 512;
+    let material_size = slot.key_size.saturating_mul(u64::from(slot.af_stripes));
+    let read_size = material_size
+        .div_ceil(sector_size)
+        .saturating_mul(sector_size);
+    if material_size == 0 || read_size > slot.area_size {
+        return Err(key_slot_error(
+            start,
+            slot,
+            String::from("key material does not fit in the key slot area"),
+        ));
+    }
+    let Some(area_offset) = start.checked_add(slot.area_offset) else {
+        return Err(key_slot_error(
+            start,
+            slot,
+            String::from("area offset is too large"),
+        ));
+    };
+
+    let mut data = read_bytes(reader, area_offset, read_size)?;
+    let cipher = SectorCipher::new(&slot.area_encryption, key)
+        .map_err(|detail| key_slot_error(start, slot, detail))?;
+    for (sector, value) in data.chunks_exact_mut(sector_size as usize).enumerate() {
+        cipher.decrypt_sector(sector as u64, value);
+    }
+    data.truncate(material_size as usize);
+    Ok(data)
+}
+
+/// Turn the passphrase into the key slot key
+fn derive_key(kdf: &LuksKdf, passphrase: &[u8], key_size: u64) -> Result<Vec<u8>, String> {
+    let mut key = vec![0; key_size as usize];
+    match kdf.kdf_type.as_str() {
+        "pbkdf2" => {
+            let hash = kdf.hash.as_deref().unwrap_or("sha256");
+            let iterations = kdf.iterations.unwrap_or_default();
+            pbkdf2(hash, passphrase, &kdf.salt, iterations, &mut key)?;
+        }
+        "argon2i" | "argon2id" => {
+            let algorithm = if kdf.kdf_type == "argon2i" {
+                argon2::Algorithm::Argon2i
+            } else {
+                argon2::Algorithm::Argon2id
+            };
+            let params = argon2::Params::new(
+                kdf.memory.unwrap_or_default(),
+                kdf.time.unwrap_or_default(),
+                kdf.cpus.unwrap_or_default(),
+                Some(key.len()),
+            )
+            .map_err(|err| format!("bad Argon2 parameters: {err}"))?;
+            argon2::Argon2::new(algorithm, argon2::Version::V0x13, params)
+                .hash_password_into(passphrase, &kdf.salt, &mut key)
+                .map_err(|err| format!("Argon2 failed: {err}"))?;
+        }
+        kdf_type => return Err(format!("KDF {kdf_type} is not supported")),
+    }
+    Ok(key)
+}
+
+fn pbkdf2(
+    hash: &str,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    output: &mut [u8],
+) -> Result<(), String> {
+    match hash {
+        "sha1" => pbkdf2::pbkdf2_hmac::<Sha1>(password, salt, iterations, output),
+        "sha256" => pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, output),
+        "sha512" => pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, iterations, output),
+        _ => return Err(format!("hash {hash} is not supported")),
+    }
+    Ok(())
+}
+
+/// Merge the anti-forensic stripes back into the master key
+fn af_merge(data: &[u8], key_size: usize, stripes: usize, hash: &str) -> Result<Vec<u8>, String> {
+    let mut key = vec![0; key_size];
+    if key_size == 0 {
+        return Ok(key);
+    }
+    for (index, stripe) in data.chunks_exact(key_size).take(stripes).enumerate() {
+        xor(&mut key, stripe);
+        // Every stripe except the last is diffused
+        if index + 1 < stripes {
+            key = diffuse(&key, hash)?;
+        }
+    }
+    Ok(key)
+}
+
+/// Hash each digest sized block of the data with its block number
+fn diffuse(data: &[u8], hash: &str) -> Result<Vec<u8>, String> {
+    let digest_size = match hash {
+        "sha1" => 20,
+        "sha256" => 32,
+        "sha512" => 64,
+        _ => return Err(format!("hash {hash} is not supported")),
+    };
+    let mut output = Vec::with_capacity(data.len());
+    for (index, block) in data.chunks(digest_size).enumerate() {
+        let index = (index as u32).to_be_bytes();
+        let value = match hash {
+            "sha1" => Sha1::new()
+                .chain_update(index)
+                .chain_update(block)
+                .finalize()
+                .to_vec(),
+            "sha256" => Sha256::new()
+                .chain_update(index)
+                .chain_update(block)
+                .finalize()
+                .to_vec(),
+            _ => Sha512::new()
+                .chain_update(index)
+                .chain_update(block)
+                .finalize()
+                .to_vec(),
+        };
+        output.extend_from_slice(&value[..block.len()]);
+    }
+    Ok(output)
+}
+
+fn key_slot_error(start: u64, slot: &LuksKeySlot, detail: String) -> CalfError {
+    CalfError::Parse {
+        structure: Structure::LuksKeySlot,
+        offset: Offset::Guest(start.saturating_add(slot.area_offset)),
+        detail: format!("key slot {}: {detail}", slot.id),
+    }
+}
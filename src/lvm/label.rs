@@ -0,0 +1,281 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    reader::{OsReader, read_guest},
+    utils::strings::extract_utf8_string,
+};
+use log::warn;
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u32, le_u64},
+};
+use std::io::{Read, Seek};
+
+/// LVM2 physical volume label: <https://github.com/lvmteam/lvm2/blob/main/lib/format_text/layout.h>
+#[derive(Debug)]
+pub(crate) struct PvLabel {
+    /// PV UUID with dashes. Same format as the metadata
+    pub(crate) pv_uuid: String,
+    /// Offset and size of the metadata areas from the start of the PV
+    pub(crate) metadata_areas: Vec<(u64, u64)>,
+}
+
+/// Look for the label in the first four sectors of the PV. Returns None if the PV has no label
+pub(crate) fn read_pv_label<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    pv_offset: u64,
+) -> Result<Option<PvLabel>, CalfError> {
+    let sector_size = 512;
+    let max_sectors = 4;
+    let data = read_guest(reader, pv_offset, sector_size * max_sectors)?;
+
+    for sector in data.chunks(sector_size as usize) {
+        if !sector.starts_with(b"LABELONE") {
+            continue;
+        }
+        let (_, label) = match parse_label(sector) {
+            Ok(result) => result,
+            Err(err) => {
+                return Err(CalfError::parse(
+                    Structure::LvmLabel,
+                    Offset::Guest(pv_offset),
+                    &err,
+                ));
+            }
+        };
+        return Ok(Some(label));
+    }
+    Ok(None)
+}
+
+/// Parse the label header and the PV header that follows it
+fn parse_label(data: &[u8]) -> nom::IResult<&[u8], PvLabel> {
+    let (input, _) = tag(&b"LABELONE"[..])(data)?;
+    let (input, _sector) = le_u64(input)?;
+    let (input, checksum) = le_u32(input)?;
+    let (_, header_offset) = le_u32(input)?;
+
+    // Checksum covers the rest of the sector starting at the header offset field
+    let checksum_start = 20;
+    if lvm_crc(&data[checksum_start..]) != checksum {
+        warn!("[calf] LVM label checksum does not match");
+    }
+
+    let label_type_offset: u8 = 24;
+    let (input, _) = take(label_type_offset)(data)?;
+    let (_, _) = tag(&b"LVM2 001"[..])(input)?;
+
+    let (input, _) = take(header_offset)(data)?;
+    let uuid_size: u8 = 32;
+    let (input, pv_uuid) = take(uuid_size)(input)?;
+    let (input, _device_size) = le_u64(input)?;
+    let (input, _data_areas) = parse_locations(input)?;
+    let (input, metadata_areas) = parse_locations(input)?;
+
+    let label = PvLabel {
+        pv_uuid: format_lvm_uuid(pv_uuid),
+        metadata_areas,
+    };
+    Ok((input, label))
+}
+
+/// Parse a list of offset and size pairs. The list ends with a zero entry
+fn parse_locations(data: &[u8]) -> nom::IResult<&[u8], Vec<(u64, u64)>> {
+    let mut input = data;
+    let mut locations = Vec::new();
+    loop {
+        let (remaining, offset) = le_u64(input)?;
+        let (remaining, size) = le_u64(remaining)?;
+        input = remaining;
+        if offset == 0 {
+            break;
+        }
+        locations.push((offset, size));
+    }
+    Ok((input, locations))
+}
+
+/// Read the newest metadata text in the metadata area
+pub(crate) fn read_metadata<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    pv_offset: u64,
+    area: (u64, u64),
+) -> Result<(u64, String), CalfError> {
+    let (area_offset, label_area_size) = area;
+    let header_size = 512;
+    let area_error = |detail: String| CalfError::Parse {
+        structure: Structure::LvmMetadata,
+        offset: Offset::Guest(pv_offset),
+        detail,
+    };
+    // Area size comes from the label. Do not trust it past the end of the guest disk
+    let header_offset = pv_offset
+        .checked_add(area_offset)
+        .filter(|offset| {
+            offset
+                .checked_add(label_area_size)
+                .is_some_and(|end| end <= reader.os_size)
+        })
+        .ok_or_else(|| {
+            area_error(format!(
+                "metadata area at {area_offset} with size {label_area_size} is outside the guest disk"
+            ))
+        })?;
+    let data = read_guest(reader, header_offset, header_size)?;
+    let (start, header_area_size, location) = match parse_metadata_header(&data) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            return Err(CalfError::parse(
+                Structure::LvmMetadata,
+                Offset::Guest(header_offset),
+                &err,
+            ));
+        }
+    };
+    let Some((text_offset, text_size, checksum)) = location else {
+        return Err(CalfError::Parse {
+            structure: Structure::LvmMetadata,
+            offset: Offset::Guest(header_offset),
+            detail: String::from("metadata area has no metadata"),
+        });
+    };
+
+    // Metadata area is a circular buffer after the header. The text may wrap around to the start
+    let area_size = header_area_size.min(label_area_size);
+    if text_offset < header_size || text_offset >= area_size || text_size > area_size - header_size
+    {
+        return Err(area_error(format!(
+            "metadata text at {text_offset} with size {text_size} is outside the metadata area"
+        )));
+    }
+    let (Some(area_start), Some(text_start)) = (
+        pv_offset.checked_add(start),
+        pv_offset
+            .checked_add(start)
+            .and_then(|offset| offset.checked_add(text_offset)),
+    ) else {
+        return Err(area_error(format!(
+            "metadata area start {start} is too large"
+        )));
+    };
+
+    let first_size = text_size.min(area_size - text_offset);
+    let mut text = read_guest(reader, text_start, first_size)?;
+    if first_size < text_size {
+        let mut wrapped = read_guest(reader, area_start + header_size, text_size - first_size)?;
+        text.append(&mut wrapped);
+    }
+
+    if lvm_crc(&text) != checksum {
+        warn!("[calf] LVM metadata at {text_start} has a bad checksum");
+    }
+    let end = text
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(text.len());
+    Ok((text_start, extract_utf8_string(&text[..end])))
+}
+
+/// Parse the metadata area header. Returns the area start, area size, and the first metadata location in use
+#[allow(clippy::type_complexity)]
+fn parse_metadata_header(data: &[u8]) -> nom::IResult<&[u8], (u64, u64, Option<(u64, u64, u32)>)> {
+    let (input, _checksum) = le_u32(data)?;
+    let (input, _) = tag(&b" LVM2 x[5A%r0N*>"[..])(input)?;
+    let (input, _version) = le_u32(input)?;
+    let (input, start) = le_u64(input)?;
+    let (mut input, size) = le_u64(input)?;
+
+    // Ignored locations are old metadata that should not be used
+    let ignored = 0x1;
+    let mut location = None;
+    loop {
+        let (remaining, offset) = le_u64(input)?;
+        let (remaining, text_size) = le_u64(remaining)?;
+        let (remaining, checksum) = le_u32(remaining)?;
+        let (remaining, flags) = le_u32(remaining)?;
+        input = remaining;
+        if offset == 0 {
+            break;
+        }
+        if flags & ignored == 0 && location.is_none() {
+            location = Some((offset, text_size, checksum));
+        }
+    }
+
+    Ok((input, (start, size, location)))
+}
+
+/// LVM uses CRC32 with its own initial value and no final XOR
+pub(crate) fn lvm_crc(data: &[u8]) -> u32 {
+    let initial = 0xf597a6cf;
+    let mut hasher = crc32fast::Hasher::new_with_initial(!initial);
+    hasher.update(data);
+    !hasher.finalize()
+}
+
+/// LVM UUIDs are 32 characters split into groups of 6-4-4-4-4-4-6
+pub(crate) fn format_lvm_uuid(data: &[u8]) -> String {
+    let groups = [6, 4, 4, 4, 4, 4, 6];
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for size in groups {
+        let Some(value) = data.get(start..start + size) else {
+            break;
+        };
+        parts.push(String::from_utf8_lossy(value).to_string());
+        start += size;
+    }
+    parts.join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_lvm_uuid, lvm_crc, read_metadata, read_pv_label};
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_read_pv_label() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let label = read_pv_label(&mut os_reader, 64 * 512).unwrap().unwrap();
+        assert_eq!(label.pv_uuid, "Aoo0nQ-dOC1-WUMd-GnOh-7ywR-wk5t-B9mKVn");
+        assert_eq!(label.metadata_areas, vec![(4096, 1044480)]);
+
+        let (offset, text) =
+            read_metadata(&mut os_reader, 64 * 512, label.metadata_areas[0]).unwrap();
+        assert_eq!(offset, 64 * 512 + 4096 + 512);
+        assert!(text.starts_with("vg0 {"));
+
+        // Second PV metadata wraps around the end of the metadata area
+        let label = read_pv_label(&mut os_reader, 8192 * 512).unwrap().unwrap();
+        let (_, wrapped) =
+            read_metadata(&mut os_reader, 8192 * 512, label.metadata_areas[0]).unwrap();
+        assert_eq!(wrapped, text);
+
+        assert!(read_pv_label(&mut os_reader, 0).unwrap().is_none());
+
+        // Area sizes past the guest disk and metadata text larger than the area are rejected before reading
+        assert!(read_metadata(&mut os_reader, 64 * 512, (4096, u64::MAX)).is_err());
+        assert!(read_metadata(&mut os_reader, u64::MAX, (4096, 1044480)).is_err());
+        assert!(read_metadata(&mut os_reader, 64 * 512, (4096, 1024)).is_err());
+    }
+
+    #[test]
+    fn test_lvm_crc() {
+        assert_eq!(lvm_crc(b""), 0xf597a6cf);
+        assert_eq!(format_lvm_uuid(b"abc"), "");
+    }
+}
@@ -0,0 +1,259 @@
+/// Parser for the LVM2 text metadata format. Ex: `extent_size = 8192` or `stripes = ["pv0", 0]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Number(i64),
+    List(Vec<Value>),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Section {
+    pub(crate) name: String,
+    pub(crate) values: Vec<(String, Value)>,
+    pub(crate) sections: Vec<Section>,
+}
+
+impl Section {
+    pub(crate) fn value(&self, key: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn string(&self, key: &str) -> Option<String> {
+        match self.value(key)? {
+            Value::String(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn number(&self, key: &str) -> Option<u64> {
+        match self.value(key)? {
+            Value::Number(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get a list of strings. Other values in the list are skipped
+    pub(crate) fn strings(&self, key: &str) -> Vec<String> {
+        let Some(Value::List(values)) = self.value(key) else {
+            return Vec::new();
+        };
+        values
+            .iter()
+            .filter_map(|value| match value {
+                Value::String(value) => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Name(String),
+    String(String),
+    Number(i64),
+    Equals,
+    OpenSection,
+    CloseSection,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+/// Parse the metadata text into sections. The root section holds the volume group section
+pub(crate) fn parse_metadata(text: &str) -> Result<Section, String> {
+    let tokens = tokenize(text)?;
+    let mut position = 0;
+    let mut root = parse_section(&tokens, &mut position, 0)?;
+    if position < tokens.len() {
+        return Err(format!("unexpected token {:?}", tokens[position]));
+    }
+    root.name = String::from("root");
+    Ok(root)
+}
+
+/// Parse values and sections until the end of the section or text
+fn parse_section(tokens: &[Token], position: &mut usize, depth: usize) -> Result<Section, String> {
+    check_depth(depth)?;
+    let mut section = Section::default();
+    while let Some(token) = tokens.get(*position) {
+        let name = match token {
+            Token::Name(name) => name.clone(),
+            Token::CloseSection => return Ok(section),
+            _ => return Err(format!("expected a name, got {token:?}")),
+        };
+        *position += 1;
+
+        match tokens.get(*position) {
+            Some(Token::Equals) => {
+                *position += 1;
+                let value = parse_value(tokens, position, depth + 1)?;
+                section.values.push((name, value));
+            }
+            Some(Token::OpenSection) => {
+                *position += 1;
+                let mut child = parse_section(tokens, position, depth + 1)?;
+                if tokens.get(*position) != Some(&Token::CloseSection) {
+                    return Err(format!("section {name} is not closed"));
+                }
+                *position += 1;
+                child.name = name;
+                section.sections.push(child);
+            }
+            other => return Err(format!("expected = or {{ after {name}, got {other:?}")),
+        }
+    }
+    Ok(section)
+}
+
+fn parse_value(tokens: &[Token], position: &mut usize, depth: usize) -> Result<Value, String> {
+    check_depth(depth)?;
+    let value = match tokens.get(*position) {
+        Some(Token::String(value)) => Value::String(value.clone()),
+        Some(Token::Number(value)) => Value::Number(*value),
+        Some(Token::OpenList) => {
+            *position += 1;
+            let mut values = Vec::new();
+            loop {
+                match tokens.get(*position) {
+                    Some(Token::CloseList) => break,
+                    Some(Token::Comma) => *position += 1,
+                    Some(_) => {
+                        values.push(parse_value(tokens, position, depth + 1)?);
+                    }
+                    None => return Err(String::from("list is not closed")),
+                }
+            }
+            Value::List(values)
+        }
+        other => return Err(format!("expected a value, got {other:?}")),
+    };
+    *position += 1;
+    Ok(value)
+}
+
+/// LVM metadata is only a few levels deep. Crafted metadata could nest until the stack overflows
+fn check_depth(depth: usize) -> Result<(), String> {
+    let max_depth = 32;
+    if depth > max_depth {
+        return Err(format!("metadata is nested more than {max_depth} levels"));
+    }
+    Ok(())
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(value) = chars.next() {
+        match value {
+            '#' => {
+                // Comments run to the end of the line
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            '=' => tokens.push(Token::Equals),
+            '{' => tokens.push(Token::OpenSection),
+            '}' => tokens.push(Token::CloseSection),
+            '[' => tokens.push(Token::OpenList),
+            ']' => tokens.push(Token::CloseList),
+            ',' => tokens.push(Token::Comma),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            if let Some(escaped) = chars.next() {
+                                string.push(escaped);
+                            }
+                        }
+                        Some('"') => break,
+                        Some(next) => string.push(next),
+                        None => return Err(String::from("string is not closed")),
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            value if value.is_whitespace() => {}
+            value => {
+                let mut word = String::from(value);
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "=#{}[],\"".contains(*next) {
+                        break;
+                    }
+                    word.push(*next);
+                    chars.next();
+                }
+                match word.parse::<i64>() {
+                    Ok(number) => tokens.push(Token::Number(number)),
+                    Err(_) => tokens.push(Token::Name(word)),
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, parse_metadata};
+
+    #[test]
+    fn test_parse_metadata() {
+        let text = r#"vg0 {
+id = "abc"
+seqno = 4 # comment
+status = ["READ", "WRITE"]
+flags = []
+lv {
+segment1 {
+stripes = [
+"pv0", 10,
+"pv1", 2
+]
+}
+}
+}
+description = "Created *after* executing 'lvcreate -n \"data\"'"
+"#;
+        let root = parse_metadata(text).unwrap();
+        let vg = root.section("vg0").unwrap();
+        assert_eq!(vg.string("id").unwrap(), "abc");
+        assert_eq!(vg.number("seqno").unwrap(), 4);
+        assert_eq!(vg.strings("status"), vec!["READ", "WRITE"]);
+        assert!(vg.strings("flags").is_empty());
+
+        let segment = vg.section("lv").unwrap().section("segment1").unwrap();
+        assert_eq!(
+            segment.value("stripes").unwrap(),
+            &Value::List(vec![
+                Value::String(String::from("pv0")),
+                Value::Number(10),
+                Value::String(String::from("pv1")),
+                Value::Number(2),
+            ])
+        );
+        assert_eq!(
+            root.string("description").unwrap(),
+            "Created *after* executing 'lvcreate -n \"data\"'"
+        );
+
+        assert!(parse_metadata("vg0 {").is_err());
+        assert!(parse_metadata("vg0 = ").is_err());
+        assert!(parse_metadata("vg0 }").is_err());
+
+        let nested = "a {".repeat(100000);
+        assert!(parse_metadata(&nested).unwrap_err().contains("nested"));
+        let nested = format!("a = {}", "[".repeat(100000));
+        assert!(parse_metadata(&nested).unwrap_err().contains("nested"));
+    }
+}
@@ -0,0 +1,6 @@
+pub(crate) mod label;
+pub(crate) mod metadata;
+pub mod reader;
+pub(crate) mod snapshot;
+pub(crate) mod thin;
+pub mod volume;
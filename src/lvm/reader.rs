@@ -0,0 +1,721 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    lvm::{
+        snapshot::snapshot_exceptions,
+        thin::thin_blocks,
+        volume::{LogicalVolume, SnapshotSegment, ThinSegment, VolumeGroup},
+    },
+    reader::seek_position,
+};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, SeekFrom},
+};
+
+/// Reader for an LVM2 logical volume. Offset 0 is the start of the logical volume.
+/// Extents are mapped to guest offsets through the provided guest disk reader
+pub struct LogicalVolumeReader<R: Read + Seek> {
+    reader: R,
+    mapping: Mapping,
+    size: u64,
+    position: u64,
+}
+
+/// How logical volume offsets map to guest offsets
+#[derive(Debug)]
+pub(crate) enum Mapping {
+    /// Linear and striped segments
+    Segments(Vec<MappedSegment>),
+    /// Thin LV blocks stored in the pool data LV
+    Thin {
+        /// Block size in bytes
+        block_size: u64,
+        /// Thin LV block to pool data block
+        blocks: BTreeMap<u64, u64>,
+        data: Box<Mapping>,
+        /// Unmapped blocks are read from the external origin. Otherwise they are zeros
+        external_origin: Option<Box<Mapping>>,
+    },
+    /// Chunks that changed after the snapshot was taken are in the COW store. The rest are read from the origin
+    Snapshot {
+        /// Chunk size in bytes
+        chunk_size: u64,
+        /// Origin chunk to COW store chunk
+        exceptions: BTreeMap<u64, u64>,
+        origin: Box<Mapping>,
+        cow: Box<Mapping>,
+    },
+}
+
+/// Segment with the guest offsets of its stripes
+#[derive(Debug)]
+pub(crate) struct MappedSegment {
+    /// Offset in the logical volume
+    start: u64,
+    size: u64,
+    /// Stripe size in bytes. Equal to the segment size for linear segments
+    stripe_size: u64,
+    /// Guest offset of the first byte of each stripe
+    stripes: Vec<u64>,
+}
+
+impl<R: Read + Seek> LogicalVolumeReader<R> {
+    /// Create a reader for the logical volume. Fails if a segment type is not supported or a PV is not on the guest disk.
+    /// Reading the COW store of a snapshot returns the snapshot contents
+    pub fn new(
+        reader: R,
+        group: &VolumeGroup,
+        volume: &LogicalVolume,
+    ) -> Result<LogicalVolumeReader<R>, CalfError> {
+        let mut reader = reader;
+        let (mapping, size) = volume_mapping(&mut reader, group, volume, 0)?;
+
+        Ok(LogicalVolumeReader {
+            reader,
+            mapping,
+            size,
+            position: 0,
+        })
+    }
+
+    /// Logical volume size in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the guest disk reader back
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl Mapping {
+    /// Get the guest offset for the logical volume offset and how many bytes can be read from there.
+    /// Returns None for the guest offset if the bytes read as zeros
+    pub(crate) fn map_offset(&self, offset: u64) -> (Option<u64>, u64) {
+        match self {
+            Mapping::Segments(segments) => segments_offset(segments, offset),
+            Mapping::Thin {
+                block_size,
+                blocks,
+                data,
+                external_origin,
+            } => {
+                let in_block = offset % block_size;
+                let block_left = block_size - in_block;
+                let (guest_offset, available) =
+                    match (blocks.get(&(offset / block_size)), external_origin) {
+                        // Block offsets are checked when the block map is read
+                        (Some(block), _) => data.map_offset(block * block_size + in_block),
+                        (None, Some(origin)) => origin.map_offset(offset),
+                        (None, None) => (None, block_left),
+                    };
+                (guest_offset, available.min(block_left))
+            }
+            Mapping::Snapshot {
+                chunk_size,
+                exceptions,
+                origin,
+                cow,
+            } => {
+                let in_chunk = offset % chunk_size;
+                let chunk_left = chunk_size - in_chunk;
+                let (guest_offset, available) = match exceptions.get(&(offset / chunk_size)) {
+                    // Chunk offsets are checked when the exceptions are read
+                    Some(chunk) => cow.map_offset(chunk * chunk_size + in_chunk),
+                    None => origin.map_offset(offset),
+                };
+                (guest_offset, available.min(chunk_left))
+            }
+        }
+    }
+
+    /// Linear mapping from the start of the reader. Used by tests with metadata built in memory
+    #[cfg(test)]
+    pub(crate) fn linear(size: u64) -> Mapping {
+        Mapping::Segments(vec![MappedSegment {
+            start: 0,
+            size,
+            stripe_size: size,
+            stripes: vec![0],
+        }])
+    }
+
+    /// Guest offset for errors. Unmapped offsets are reported as 0
+    pub(crate) fn guest_offset(&self, offset: u64) -> Offset {
+        Offset::Guest(self.map_offset(offset).0.unwrap_or_default())
+    }
+}
+
+fn segments_offset(segments: &[MappedSegment], offset: u64) -> (Option<u64>, u64) {
+    let Some(segment) = segments
+        .iter()
+        .find(|segment| segment.start <= offset && offset < segment.start + segment.size)
+    else {
+        // Gaps read as zeros up to the next segment
+        let next = segments
+            .iter()
+            .map(|segment| segment.start)
+            .filter(|start| *start > offset)
+            .min()
+            .unwrap_or(u64::MAX);
+        return (None, next - offset);
+    };
+
+    let segment_offset = offset - segment.start;
+    let chunk = segment_offset / segment.stripe_size;
+    let stripe_count = segment.stripes.len() as u64;
+    let stripe = segment.stripes[(chunk % stripe_count) as usize];
+    let in_chunk = segment_offset % segment.stripe_size;
+    let guest_offset = stripe + (chunk / stripe_count) * segment.stripe_size + in_chunk;
+    let available = u64::min(
+        segment.stripe_size - in_chunk,
+        segment.size - segment_offset,
+    );
+    (Some(guest_offset), available)
+}
+
+/// Read bytes through a mapping. Used for the thin pool metadata and the snapshot COW store
+pub(crate) fn read_mapping<R: Read + Seek>(
+    reader: &mut R,
+    mapping: &Mapping,
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>, CalfError> {
+    let mut data = vec![0; size as usize];
+    let mut position = 0;
+    while position < data.len() {
+        let (guest_offset, available) = mapping.map_offset(offset + position as u64);
+        let read_len = u64::min(available, (data.len() - position) as u64) as usize;
+        if let Some(guest_offset) = guest_offset {
+            if let Err(err) = reader.seek(SeekFrom::Start(guest_offset)) {
+                return Err(CalfError::SeekFile {
+                    offset: Offset::Guest(guest_offset),
+                    source: err,
+                });
+            }
+            if let Err(err) = reader.read_exact(&mut data[position..position + read_len]) {
+                return Err(CalfError::ReadFile {
+                    offset: Offset::Guest(guest_offset),
+                    source: err,
+                });
+            }
+        }
+        position += read_len;
+    }
+    Ok(data)
+}
+
+/// Build the mapping and size of the LV. The COW store of a snapshot is mapped through the snapshot
+fn volume_mapping<R: Read + Seek>(
+    reader: &mut R,
+    group: &VolumeGroup,
+    volume: &LogicalVolume,
+    depth: usize,
+) -> Result<(Mapping, u64), CalfError> {
+    // LVs point at other LVs. Metadata with a loop would never finish
+    let max_depth = 8;
+    if depth > max_depth {
+        return Err(lvm_error(
+            group,
+            format!("logical volume {} is nested too deeply", volume.name),
+        ));
+    }
+
+    let snapshot = group
+        .logical_volumes
+        .iter()
+        .flat_map(|lv| &lv.segments)
+        .filter_map(|segment| segment.snapshot.as_ref())
+        .find(|snapshot| snapshot.cow_store == volume.name);
+    if let Some(snapshot) = snapshot {
+        let origin = find_volume(group, &snapshot.origin)?;
+        let mapping = snapshot_mapping(reader, group, snapshot, depth)?;
+        return Ok((mapping, origin.size));
+    }
+
+    let mapping = segment_mapping(reader, group, volume, depth)?;
+    Ok((mapping, volume.size))
+}
+
+/// Build the mapping from the segments of the LV
+fn segment_mapping<R: Read + Seek>(
+    reader: &mut R,
+    group: &VolumeGroup,
+    volume: &LogicalVolume,
+    depth: usize,
+) -> Result<Mapping, CalfError> {
+    if let [segment] = volume.segments.as_slice() {
+        // Reading the pool LV reads the pool data
+        if let Some(pool) = &segment.thin_pool {
+            let data = find_volume(group, &pool.data)?;
+            return Ok(volume_mapping(reader, group, data, depth + 1)?.0);
+        }
+        if let Some(thin) = &segment.thin {
+            return thin_mapping(reader, group, thin, depth);
+        }
+        if let Some(snapshot) = &segment.snapshot {
+            return snapshot_mapping(reader, group, snapshot, depth);
+        }
+    }
+    striped_mapping(group, volume)
+}
+
+fn thin_mapping<R: Read + Seek>(
+    reader: &mut R,
+    group: &VolumeGroup,
+    thin: &ThinSegment,
+    depth: usize,
+) -> Result<Mapping, CalfError> {
+    let pool = find_volume(group, &thin.pool)?;
+    let Some(pool_segment) = pool
+        .segments
+        .iter()
+        .find_map(|segment| segment.thin_pool.as_ref())
+    else {
+        return Err(lvm_error(
+            group,
+            format!("logical volume {} is not a thin pool", pool.name),
+        ));
+    };
+
+    let metadata_volume = find_volume(group, &pool_segment.metadata)?;
+    let (metadata, _) = volume_mapping(reader, group, metadata_volume, depth + 1)?;
+    let data_volume = find_volume(group, &pool_segment.data)?;
+    let (data, _) = volume_mapping(reader, group, data_volume, depth + 1)?;
+    let (block_size, blocks) = thin_blocks(reader, &metadata, thin.device_id)?;
+
+    let external_origin = match &thin.external_origin {
+        Some(name) => {
+            let origin = find_volume(group, name)?;
+            Some(Box::new(
+                volume_mapping(reader, group, origin, depth + 1)?.0,
+            ))
+        }
+        None => None,
+    };
+
+    Ok(Mapping::Thin {
+        block_size,
+        blocks,
+        data: Box::new(data),
+        external_origin,
+    })
+}
+
+fn snapshot_mapping<R: Read + Seek>(
+    reader: &mut R,
+    group: &VolumeGroup,
+    snapshot: &SnapshotSegment,
+    depth: usize,
+) -> Result<Mapping, CalfError> {
+    let origin_volume = find_volume(group, &snapshot.origin)?;
+    let (origin, _) = volume_mapping(reader, group, origin_volume, depth + 1)?;
+    // The COW store segments hold the exceptions. Do not map it through the snapshot again
+    let cow_volume = find_volume(group, &snapshot.cow_store)?;
+    let cow = segment_mapping(reader, group, cow_volume, depth + 1)?;
+    let (chunk_size, exceptions) = snapshot_exceptions(reader, &cow)?;
+
+    Ok(Mapping::Snapshot {
+        chunk_size,
+        exceptions,
+        origin: Box::new(origin),
+        cow: Box::new(cow),
+    })
+}
+
+/// Map linear and striped segments to the PVs
+fn striped_mapping(group: &VolumeGroup, volume: &LogicalVolume) -> Result<Mapping, CalfError> {
+    let sector_size: u64 = 512;
+    let overflow = || {
+        lvm_error(
+            group,
+            format!(
+                "segment offsets in logical volume {} are too large",
+                volume.name
+            ),
+        )
+    };
+    let extent_bytes = group
+        .extent_size
+        .checked_mul(sector_size)
+        .ok_or_else(overflow)?;
+
+    let mut segments = Vec::new();
+    for segment in &volume.segments {
+        if segment.segment_type != "striped" {
+            return Err(lvm_error(
+                group,
+                format!(
+                    "segment type {} in logical volume {} is not supported",
+                    segment.segment_type, volume.name
+                ),
+            ));
+        }
+        if segment.stripes.is_empty() {
+            return Err(lvm_error(
+                group,
+                format!("segment in logical volume {} has no stripes", volume.name),
+            ));
+        }
+
+        let mut stripes = Vec::new();
+        for stripe in &segment.stripes {
+            let pv = group
+                .physical_volumes
+                .iter()
+                .find(|pv| pv.name == stripe.physical_volume);
+            let Some((pv, Some(pv_offset))) = pv.map(|pv| (pv, pv.offset)) else {
+                return Err(lvm_error(
+                    group,
+                    format!(
+                        "PV {} for logical volume {} is not on the guest disk",
+                        stripe.physical_volume, volume.name
+                    ),
+                ));
+            };
+            let stripe_offset = pv
+                .pe_start
+                .checked_mul(sector_size)
+                .zip(stripe.start_extent.checked_mul(extent_bytes))
+                .and_then(|(pe_start, extent)| pe_start.checked_add(extent))
+                .and_then(|offset| offset.checked_add(pv_offset))
+                .ok_or_else(overflow)?;
+            stripes.push(stripe_offset);
+        }
+
+        let size = segment
+            .extent_count
+            .checked_mul(extent_bytes)
+            .ok_or_else(overflow)?;
+        // Offsets in the segment are added to the segment start and the stripe offsets when reading
+        let start = segment
+            .start_extent
+            .checked_mul(extent_bytes)
+            .filter(|start| start.checked_add(size).is_some())
+            .ok_or_else(overflow)?;
+        if stripes
+            .iter()
+            .any(|stripe| stripe.checked_add(size).is_none())
+        {
+            return Err(overflow());
+        }
+        let stripe_size = if stripes.len() == 1 {
+            size
+        } else {
+            segment
+                .stripe_size
+                .checked_mul(sector_size)
+                .ok_or_else(overflow)?
+        };
+        if stripe_size == 0 {
+            return Err(lvm_error(
+                group,
+                format!(
+                    "striped segment in logical volume {} has no stripe size",
+                    volume.name
+                ),
+            ));
+        }
+        segments.push(MappedSegment {
+            start,
+            size,
+            stripe_size,
+            stripes,
+        });
+    }
+    Ok(Mapping::Segments(segments))
+}
+
+fn find_volume<'a>(group: &'a VolumeGroup, name: &str) -> Result<&'a LogicalVolume, CalfError> {
+    match group.logical_volumes.iter().find(|lv| lv.name == name) {
+        Some(volume) => Ok(volume),
+        None => Err(lvm_error(
+            group,
+            format!(
+                "logical volume {name} is not in volume group {}",
+                group.name
+            ),
+        )),
+    }
+}
+
+fn lvm_error(group: &VolumeGroup, detail: String) -> CalfError {
+    CalfError::Parse {
+        structure: Structure::LvmMetadata,
+        offset: Offset::Guest(group.metadata_offset),
+        detail,
+    }
+}
+
+impl<R: Read + Seek> Read for LogicalVolumeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+
+        let (guest_offset, available) = self.mapping.map_offset(self.position);
+        let read_len = available
+            .min(buf.len() as u64)
+            .min(self.size - self.position) as usize;
+        let bytes_read = if let Some(offset) = guest_offset {
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read(&mut buf[..read_len])?
+        } else {
+            buf[..read_len].fill(0);
+            read_len
+        };
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read + Seek> Seek for LogicalVolumeReader<R> {
+    /// Seeking past the end of the logical volume stops at the logical volume size
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let new_position = seek_position(self.position, self.size, position)?;
+        self.position = new_position.min(self.size);
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogicalVolumeReader;
+    use crate::{
+        bootsector::{boot::FilesystemType, probe::probe_filesystem},
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        utils::strings::sector_tag,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_logical_volume_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+        let vg = &groups[0];
+
+        // Linear volume that spans both PVs
+        let mut root = os_reader
+            .logical_volume_reader(vg, &vg.logical_volumes[0])
+            .unwrap();
+        let mut data = Vec::new();
+        root.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 8 * 65536);
+        let fs = probe_filesystem(&data).unwrap();
+        assert_eq!(fs.fs_type, FilesystemType::Ext4);
+        assert_eq!(fs.label.unwrap(), "lvroot");
+        assert_eq!(sector_tag(&data[4096..]), "root:4096");
+        assert_eq!(sector_tag(&data[4 * 65536 - 512..]), "root:261632");
+        assert_eq!(sector_tag(&data[4 * 65536..]), "root:262144");
+
+        // Striped volume alternates between the PVs every 8 KiB
+        let os_reader = root.into_inner();
+        let mut striped = os_reader
+            .logical_volume_reader(vg, &vg.logical_volumes[1])
+            .unwrap();
+        let mut sector = [0; 512];
+        for offset in [
+            0,
+            8192,
+            8192 + 512,
+            16384,
+            300000 / 512 * 512,
+            8 * 65536 - 512,
+        ] {
+            striped.seek(SeekFrom::Start(offset)).unwrap();
+            striped.read_exact(&mut sector).unwrap();
+            assert_eq!(sector_tag(&sector), format!("data:{offset}"));
+        }
+        assert_eq!(striped.read(&mut sector).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_missing_pv() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let mut groups = os_reader.volume_groups(&boot).unwrap();
+        groups[0].physical_volumes[1].offset = None;
+
+        let vg = &groups[0];
+        let result = os_reader.logical_volume_reader(vg, &vg.logical_volumes[0]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_segment_overflow() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+
+        let mut vg = groups[0].clone();
+        vg.extent_size = u64::MAX / 256;
+        let result = LogicalVolumeReader::new(&mut os_reader, &vg, &vg.logical_volumes[0]);
+        assert!(result.err().unwrap().to_string().contains("too large"));
+
+        let mut vg = groups[0].clone();
+        vg.physical_volumes[0].pe_start = u64::MAX / 256;
+        let result = LogicalVolumeReader::new(&mut os_reader, &vg, &vg.logical_volumes[0]);
+        assert!(result.err().unwrap().to_string().contains("too large"));
+    }
+
+    #[test]
+    fn test_thin_volume_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm_thin.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+        let vg = &groups[0];
+        let volume = |name: &str| {
+            vg.logical_volumes
+                .iter()
+                .find(|lv| lv.name == name)
+                .unwrap()
+        };
+
+        // Thin blocks are 64 KiB. Each pool data sector is tagged with its pool offset
+        let mut thin1 = os_reader
+            .logical_volume_reader(vg, volume("thin1"))
+            .unwrap();
+        assert_eq!(thin1.size(), 16 * 65536);
+        let mut sector = [0; 512];
+        for (offset, expected) in [
+            (0, "pool:0"),
+            (65536 + 512, "pool:66048"),
+            (5 * 65536, "pool:131072"),
+            (8 * 65536 + 1024, "pool:197632"),
+        ] {
+            thin1.seek(SeekFrom::Start(offset)).unwrap();
+            thin1.read_exact(&mut sector).unwrap();
+            assert_eq!(sector_tag(&sector), expected);
+        }
+        // Unmapped blocks are zeros
+        thin1.seek(SeekFrom::Start(2 * 65536)).unwrap();
+        thin1.read_exact(&mut sector).unwrap();
+        assert!(sector.iter().all(|value| *value == 0));
+
+        // Thin snapshot shares blocks with its origin except the ones written after the snapshot
+        let os_reader = thin1.into_inner();
+        let mut thin2 = os_reader
+            .logical_volume_reader(vg, volume("thin2"))
+            .unwrap();
+        thin2.read_exact(&mut sector).unwrap();
+        assert_eq!(sector_tag(&sector), "pool:0");
+        thin2.seek(SeekFrom::Start(65536)).unwrap();
+        thin2.read_exact(&mut sector).unwrap();
+        assert_eq!(sector_tag(&sector), "pool:262144");
+
+        // The pool LV reads the pool data
+        let os_reader = thin2.into_inner();
+        let mut pool = os_reader.logical_volume_reader(vg, volume("pool")).unwrap();
+        assert_eq!(pool.size(), 8 * 65536);
+        pool.seek(SeekFrom::Start(4096)).unwrap();
+        pool.read_exact(&mut sector).unwrap();
+        assert_eq!(sector_tag(&sector), "pool:4096");
+    }
+
+    #[test]
+    fn test_snapshot_volume_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm_thin.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+        let vg = &groups[0];
+        let volume = |name: &str| {
+            vg.logical_volumes
+                .iter()
+                .find(|lv| lv.name == name)
+                .unwrap()
+        };
+
+        // The COW store LV reads as the snapshot and is the size of the origin
+        let mut snap = os_reader.logical_volume_reader(vg, volume("snap")).unwrap();
+        assert_eq!(snap.size(), 4 * 65536);
+        let mut data = Vec::new();
+        snap.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 4 * 65536);
+        assert_eq!(sector_tag(&data[0..]), "origin:0");
+        assert_eq!(sector_tag(&data[12288..]), "snap:12288");
+        assert_eq!(sector_tag(&data[12288 + 3584..]), "snap:15872");
+        assert_eq!(sector_tag(&data[16384..]), "origin:16384");
+        assert_eq!(sector_tag(&data[40960 + 512..]), "snap:41472");
+
+        let os_reader = snap.into_inner();
+        let mut snapshot = os_reader
+            .logical_volume_reader(vg, volume("snapshot0"))
+            .unwrap();
+        let mut snapshot_data = Vec::new();
+        snapshot.read_to_end(&mut snapshot_data).unwrap();
+        assert_eq!(snapshot_data, data);
+
+        // The origin is unchanged
+        let os_reader = snapshot.into_inner();
+        let mut origin = os_reader
+            .logical_volume_reader(vg, volume("origin"))
+            .unwrap();
+        let mut sector = [0; 512];
+        origin.seek(SeekFrom::Start(12288)).unwrap();
+        origin.read_exact(&mut sector).unwrap();
+        assert_eq!(sector_tag(&sector), "origin:12288");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    error::{CalfError, Structure},
+    lvm::reader::{Mapping, read_mapping},
+};
+use nom::{
+    bytes::complete::tag,
+    number::complete::{le_u32, le_u64},
+};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+};
+
+/// Read the exceptions in a classic snapshot COW store: <https://github.com/torvalds/linux/blob/master/drivers/md/dm-snap-persistent.c>.
+/// Returns the chunk size in bytes and the origin chunk to COW store chunk map
+pub(crate) fn snapshot_exceptions<R: Read + Seek>(
+    reader: &mut R,
+    cow: &Mapping,
+) -> Result<(u64, BTreeMap<u64, u64>), CalfError> {
+    let sector_size = 512;
+    let header_size = 16;
+    let data = read_mapping(reader, cow, 0, header_size)?;
+    let (valid, chunk_sectors) = match parse_header(&data) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            return Err(CalfError::parse(
+                Structure::SnapshotStore,
+                cow.guest_offset(0),
+                &err,
+            ));
+        }
+    };
+    if valid == 0 {
+        return Err(snapshot_error(
+            cow,
+            0,
+            String::from("snapshot is invalid. It may have run out of space"),
+        ));
+    }
+    // LVM allows chunk sizes from 4 KiB to 512 KiB. Chunks are read into memory
+    let max_sectors = 1024;
+    if !chunk_sectors.is_power_of_two() || chunk_sectors > max_sectors {
+        return Err(snapshot_error(
+            cow,
+            0,
+            format!("chunk size of {chunk_sectors} sectors in the header is not supported"),
+        ));
+    }
+
+    let chunk_size = chunk_sectors as u64 * sector_size;
+    let exception_size = 16;
+    let exceptions_per_area = chunk_size / exception_size;
+    let mut exceptions = BTreeMap::new();
+    // Each metadata area is one chunk of exceptions followed by the chunks they point to. The header is chunk 0
+    let mut area_chunk: u64 = 1;
+    loop {
+        let Some(offset) = area_chunk.checked_mul(chunk_size) else {
+            return Err(snapshot_error(
+                cow,
+                0,
+                format!("exception area at chunk {area_chunk} is too large"),
+            ));
+        };
+        let data = read_mapping(reader, cow, offset, chunk_size)?;
+        let mut input = data.as_slice();
+        for _ in 0..exceptions_per_area {
+            let (old_chunk, new_chunk) = match parse_exception(input) {
+                Ok((remaining, result)) => {
+                    input = remaining;
+                    result
+                }
+                Err(err) => {
+                    return Err(CalfError::parse(
+                        Structure::SnapshotStore,
+                        cow.guest_offset(offset),
+                        &err,
+                    ));
+                }
+            };
+            // A new chunk of 0 is the header, which marks the end of the exceptions
+            if new_chunk == 0 {
+                return Ok((chunk_size, exceptions));
+            }
+            // Reads add the offset in the chunk to the chunk offset. The end of the chunk must fit too
+            let chunk_end = |chunk: u64| {
+                chunk
+                    .checked_add(1)
+                    .and_then(|end| end.checked_mul(chunk_size))
+            };
+            if chunk_end(new_chunk).is_none() || chunk_end(old_chunk).is_none() {
+                return Err(snapshot_error(
+                    cow,
+                    offset,
+                    format!(
+                        "exception for chunk {old_chunk} points to chunk {new_chunk} that is too large"
+                    ),
+                ));
+            }
+            exceptions.insert(old_chunk, new_chunk);
+        }
+        area_chunk = area_chunk.saturating_add(exceptions_per_area + 1);
+    }
+}
+
+/// Returns whether the snapshot is valid and the chunk size in 512 byte sectors
+fn parse_header(data: &[u8]) -> nom::IResult<&[u8], (u32, u32)> {
+    let (input, _) = tag(&b"SnAp"[..])(data)?;
+    let (input, valid) = le_u32(input)?;
+    let (input, _version) = le_u32(input)?;
+    let (input, chunk_size) = le_u32(input)?;
+    Ok((input, (valid, chunk_size)))
+}
+
+fn parse_exception(data: &[u8]) -> nom::IResult<&[u8], (u64, u64)> {
+    let (input, old_chunk) = le_u64(data)?;
+    let (input, new_chunk) = le_u64(input)?;
+    Ok((input, (old_chunk, new_chunk)))
+}
+
+fn snapshot_error(cow: &Mapping, offset: u64, detail: String) -> CalfError {
+    CalfError::Parse {
+        structure: Structure::SnapshotStore,
+        offset: cow.guest_offset(offset),
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::snapshot_exceptions;
+    use crate::lvm::reader::Mapping;
+    use std::io::Cursor;
+
+    /// COW store with 4 KiB chunks. The exceptions are in chunk 1
+    fn cow_store(chunk_sectors: u32, exceptions: &[(u64, u64)]) -> Vec<u8> {
+        let mut data = vec![0; 4096 * 4];
+        data[0..4].copy_from_slice(b"SnAp");
+        data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        data[8..12].copy_from_slice(&1u32.to_le_bytes());
+        data[12..16].copy_from_slice(&chunk_sectors.to_le_bytes());
+        for (index, (old_chunk, new_chunk)) in exceptions.iter().enumerate() {
+            let offset = 4096 + index * 16;
+            data[offset..offset + 8].copy_from_slice(&old_chunk.to_le_bytes());
+            data[offset + 8..offset + 16].copy_from_slice(&new_chunk.to_le_bytes());
+        }
+        data
+    }
+
+    fn exceptions(data: Vec<u8>) -> Result<Vec<(u64, u64)>, String> {
+        let mapping = Mapping::linear(data.len() as u64);
+        snapshot_exceptions(&mut Cursor::new(data), &mapping)
+            .map(|(_, exceptions)| exceptions.into_iter().collect())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_snapshot_exceptions() {
+        assert_eq!(
+            exceptions(cow_store(8, &[(5, 2), (9, 3)])).unwrap(),
+            vec![(5, 2), (9, 3)]
+        );
+
+        for chunk_sectors in [0, 3, 2048, u32::MAX] {
+            assert!(
+                exceptions(cow_store(chunk_sectors, &[]))
+                    .unwrap_err()
+                    .contains("not supported")
+            );
+        }
+        assert!(
+            exceptions(cow_store(8, &[(5, u64::MAX / 4096)]))
+                .unwrap_err()
+                .contains("too large")
+        );
+        assert!(
+            exceptions(cow_store(8, &[(u64::MAX, 2)]))
+                .unwrap_err()
+                .contains("too large")
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    error::{CalfError, Structure},
+    lvm::reader::{Mapping, read_mapping},
+};
+use nom::{
+    bytes::complete::{tag, take},
+    number::complete::{le_u32, le_u64},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{Read, Seek},
+};
+
+/// Thin pool metadata superblock: <https://github.com/torvalds/linux/blob/master/drivers/md/dm-thin-metadata.c>
+#[derive(Debug)]
+struct ThinSuperblock {
+    data_mapping_root: u64,
+    device_details_root: u64,
+    /// Data block size in 512 byte sectors
+    data_block_size: u32,
+    /// Metadata block size in 512 byte sectors
+    metadata_block_size: u32,
+}
+
+/// Persistent data B-tree node header: <https://github.com/torvalds/linux/blob/master/drivers/md/persistent-data/dm-btree-internal.h>
+#[derive(Debug)]
+struct NodeHeader {
+    flags: u32,
+    block: u64,
+    entries: u32,
+    max_entries: u32,
+    value_size: u32,
+}
+
+/// Get the pool data block for each block of the thin device from the pool metadata.
+/// Returns the data block size in bytes and the block map
+pub(crate) fn thin_blocks<R: Read + Seek>(
+    reader: &mut R,
+    metadata: &Mapping,
+    device_id: u64,
+) -> Result<(u64, BTreeMap<u64, u64>), CalfError> {
+    let sector_size = 512;
+    let superblock_size = 512;
+    let data = read_mapping(reader, metadata, 0, superblock_size)?;
+    let superblock = match parse_superblock(&data) {
+        Ok((_, result)) => result,
+        Err(err) => {
+            return Err(CalfError::parse(
+                Structure::ThinPoolMetadata,
+                metadata.guest_offset(0),
+                &err,
+            ));
+        }
+    };
+    if superblock.data_block_size == 0 {
+        return Err(thin_error(
+            metadata,
+            0,
+            String::from("data block size in the superblock is zero"),
+        ));
+    }
+    // dm-thin metadata blocks are always 4 KiB
+    let metadata_sectors = 8;
+    if superblock.metadata_block_size != metadata_sectors {
+        return Err(thin_error(
+            metadata,
+            0,
+            format!(
+                "metadata block size in the superblock is {} sectors instead of {metadata_sectors}",
+                superblock.metadata_block_size
+            ),
+        ));
+    }
+    let block_size = superblock.data_block_size as u64 * sector_size;
+    let metadata_block_size = superblock.metadata_block_size as u64 * sector_size;
+
+    let details = btree_entries(
+        reader,
+        metadata,
+        metadata_block_size,
+        superblock.device_details_root,
+    )?;
+    if !details.iter().any(|(key, _)| *key == device_id) {
+        return Err(thin_error(
+            metadata,
+            block_offset(superblock.device_details_root, metadata_block_size),
+            format!("thin device {device_id} is not in the pool"),
+        ));
+    }
+
+    // The top level tree has the root of the block tree for each thin device
+    let devices = btree_entries(
+        reader,
+        metadata,
+        metadata_block_size,
+        superblock.data_mapping_root,
+    )?;
+    let Some(root) = devices
+        .iter()
+        .find(|(key, _)| *key == device_id)
+        .and_then(|(_, value)| get_u64(value, 0))
+    else {
+        return Err(thin_error(
+            metadata,
+            block_offset(superblock.data_mapping_root, metadata_block_size),
+            format!("thin device {device_id} has no block mappings"),
+        ));
+    };
+
+    let mut blocks = BTreeMap::new();
+    for (key, value) in btree_entries(reader, metadata, metadata_block_size, root)? {
+        let Some(mapping) = get_u64(&value, 0) else {
+            continue;
+        };
+        // The lower 24 bits are the time the block was mapped
+        let time_bits = 24;
+        let data_block = mapping >> time_bits;
+        // Reads add the offset in the block to the block offset. The end of the block must fit too
+        let block_end = |block: u64| {
+            block
+                .checked_add(1)
+                .and_then(|end| end.checked_mul(block_size))
+        };
+        if block_end(data_block).is_none() || block_end(key).is_none() {
+            return Err(thin_error(
+                metadata,
+                block_offset(root, metadata_block_size),
+                format!(
+                    "thin device block {key} maps to data block {data_block} that is too large"
+                ),
+            ));
+        }
+        blocks.insert(key, data_block);
+    }
+    Ok((block_size, blocks))
+}
+
+fn parse_superblock(data: &[u8]) -> nom::IResult<&[u8], ThinSuperblock> {
+    let (input, _checksum) = le_u32(data)?;
+    let (input, _flags) = le_u32(input)?;
+    let (input, _block) = le_u64(input)?;
+    let uuid_size: u8 = 16;
+    let (input, _uuid) = take(uuid_size)(input)?;
+    let magic = 27022010u64.to_le_bytes();
+    let (input, _) = tag(&magic[..])(input)?;
+    let (input, _version) = le_u32(input)?;
+    let (input, _time) = le_u32(input)?;
+    let (input, _transaction_id) = le_u64(input)?;
+    let (input, _held_root) = le_u64(input)?;
+    let space_map_size: u8 = 128;
+    let (input, _data_space_map) = take(space_map_size)(input)?;
+    let (input, _metadata_space_map) = take(space_map_size)(input)?;
+    let (input, data_mapping_root) = le_u64(input)?;
+    let (input, device_details_root) = le_u64(input)?;
+    let (input, data_block_size) = le_u32(input)?;
+    let (input, metadata_block_size) = le_u32(input)?;
+
+    let superblock = ThinSuperblock {
+        data_mapping_root,
+        device_details_root,
+        data_block_size,
+        metadata_block_size,
+    };
+    Ok((input, superblock))
+}
+
+/// Walk the B-tree and return the key and value of every leaf entry
+fn btree_entries<R: Read + Seek>(
+    reader: &mut R,
+    metadata: &Mapping,
+    block_size: u64,
+    root: u64,
+) -> Result<Vec<(u64, Vec<u8>)>, CalfError> {
+    let internal_node = 0x1;
+    let leaf_node = 0x2;
+    let header_size = 32;
+    let key_size = 8;
+
+    let mut entries = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![root];
+    while let Some(block) = pending.pop() {
+        let Some(offset) = block.checked_mul(block_size) else {
+            return Err(thin_error(
+                metadata,
+                0,
+                format!("B-tree block {block} is too large"),
+            ));
+        };
+        if !visited.insert(block) {
+            return Err(thin_error(
+                metadata,
+                offset,
+                format!("B-tree block {block} is referenced more than once"),
+            ));
+        }
+
+        let data = read_mapping(reader, metadata, offset, block_size)?;
+        let header = match parse_node_header(&data) {
+            Ok((_, result)) => result,
+            Err(err) => {
+                return Err(CalfError::parse(
+                    Structure::ThinPoolMetadata,
+                    metadata.guest_offset(offset),
+                    &err,
+                ));
+            }
+        };
+        if header.block != block {
+            return Err(thin_error(
+                metadata,
+                offset,
+                format!(
+                    "B-tree node at block {block} says it is block {}",
+                    header.block
+                ),
+            ));
+        }
+        let keys_end = header_size + header.max_entries as u64 * key_size;
+        let values_end = keys_end + header.max_entries as u64 * header.value_size as u64;
+        if header.entries > header.max_entries || header.value_size == 0 || values_end > block_size
+        {
+            return Err(thin_error(
+                metadata,
+                offset,
+                format!("B-tree node at block {block} has a bad size"),
+            ));
+        }
+
+        let keys = data[header_size as usize..keys_end as usize].chunks_exact(key_size as usize);
+        let values =
+            data[keys_end as usize..values_end as usize].chunks_exact(header.value_size as usize);
+        let node_entries = keys
+            .zip(values)
+            .take(header.entries as usize)
+            .map(|(key, value)| {
+                let mut key_bytes = [0; 8];
+                key_bytes.copy_from_slice(key);
+                (u64::from_le_bytes(key_bytes), value)
+            });
+
+        if header.flags & internal_node != 0 {
+            // Internal node values are child blocks. Visit them in key order
+            let mut children: Vec<u64> = node_entries
+                .filter_map(|(_, value)| get_u64(value, 0))
+                .collect();
+            children.reverse();
+            pending.append(&mut children);
+        } else if header.flags & leaf_node != 0 {
+            entries.extend(node_entries.map(|(key, value)| (key, value.to_vec())));
+        } else {
+            return Err(thin_error(
+                metadata,
+                offset,
+                format!("B-tree node at block {block} is not an internal or leaf node"),
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_node_header(data: &[u8]) -> nom::IResult<&[u8], NodeHeader> {
+    let (input, _checksum) = le_u32(data)?;
+    let (input, flags) = le_u32(input)?;
+    let (input, block) = le_u64(input)?;
+    let (input, entries) = le_u32(input)?;
+    let (input, max_entries) = le_u32(input)?;
+    let (input, value_size) = le_u32(input)?;
+    let (input, _padding) = le_u32(input)?;
+
+    let header = NodeHeader {
+        flags,
+        block,
+        entries,
+        max_entries,
+        value_size,
+    };
+    Ok((input, header))
+}
+
+fn get_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Offset of a metadata block for error messages. Blocks that are too large are reported at offset 0
+fn block_offset(block: u64, block_size: u64) -> u64 {
+    block.checked_mul(block_size).unwrap_or_default()
+}
+
+fn thin_error(metadata: &Mapping, offset: u64, detail: String) -> CalfError {
+    CalfError::Parse {
+        structure: Structure::ThinPoolMetadata,
+        offset: metadata.guest_offset(offset),
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::thin_blocks;
+    use crate::lvm::reader::Mapping;
+    use std::io::Cursor;
+
+    /// Pool metadata with a superblock in block 0 and B-tree nodes after it
+    fn superblock(metadata_block_size: u32, root: u64) -> Vec<u8> {
+        let mut data = vec![0; 4096 * 4];
+        data[32..40].copy_from_slice(&27022010u64.to_le_bytes());
+        data[320..328].copy_from_slice(&root.to_le_bytes());
+        data[328..336].copy_from_slice(&root.to_le_bytes());
+        data[336..340].copy_from_slice(&128u32.to_le_bytes());
+        data[340..344].copy_from_slice(&metadata_block_size.to_le_bytes());
+        data
+    }
+
+    /// Write a B-tree node header with 8 byte values
+    fn node(data: &mut [u8], block: u64, flags: u32, entries: u32, max_entries: u32) {
+        let offset = block as usize * 4096;
+        data[offset + 4..offset + 8].copy_from_slice(&flags.to_le_bytes());
+        data[offset + 8..offset + 16].copy_from_slice(&block.to_le_bytes());
+        data[offset + 16..offset + 20].copy_from_slice(&entries.to_le_bytes());
+        data[offset + 20..offset + 24].copy_from_slice(&max_entries.to_le_bytes());
+        data[offset + 24..offset + 28].copy_from_slice(&8u32.to_le_bytes());
+    }
+
+    fn blocks_error(data: Vec<u8>) -> String {
+        let mapping = Mapping::linear(data.len() as u64);
+        thin_blocks(&mut Cursor::new(data), &mapping, 0)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_thin_blocks_bad_metadata() {
+        assert!(blocks_error(superblock(0, 1)).contains("0 sectors instead of 8"));
+        assert!(blocks_error(superblock(u32::MAX, 1)).contains("instead of 8"));
+        assert!(blocks_error(superblock(8, u64::MAX)).contains("too large"));
+
+        // Internal node that points to itself
+        let mut data = superblock(8, 1);
+        node(&mut data, 1, 1, 1, 1);
+        data[4096 + 40..4096 + 48].copy_from_slice(&1u64.to_le_bytes());
+        assert!(blocks_error(data).contains("referenced more than once"));
+
+        let mut data = superblock(8, 1);
+        node(&mut data, 1, 2, 5, 4);
+        assert!(blocks_error(data).contains("bad size"));
+
+        // Leaf with more entries than fit in the block
+        let mut data = superblock(8, 1);
+        node(&mut data, 1, 2, 1, 1000);
+        assert!(blocks_error(data).contains("bad size"));
+    }
+}
@@ -0,0 +1,458 @@
+use crate::{
+    bootsector::boot::{BootInfo, FilesystemType, Partition, PartitionType},
+    error::{CalfError, Offset, Structure},
+    lvm::{
+        label::{read_metadata, read_pv_label},
+        metadata::{Section, Value, parse_metadata},
+    },
+    reader::OsReader,
+};
+use log::warn;
+use std::io::{Read, Seek};
+
+/// LVM2 volume group from the PV metadata
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VolumeGroup {
+    pub name: String,
+    pub id: String,
+    /// Metadata version. The newest metadata is used if the PVs disagree
+    pub seqno: u64,
+    /// Extent size in 512 byte sectors
+    pub extent_size: u64,
+    pub physical_volumes: Vec<PhysicalVolume>,
+    pub logical_volumes: Vec<LogicalVolume>,
+    /// Guest offset of the metadata text the volume group came from
+    pub metadata_offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhysicalVolume {
+    /// Name used by the segments. Ex: `pv0`
+    pub name: String,
+    pub id: String,
+    /// Device path when the metadata was written
+    pub device: String,
+    /// Start of the first extent in 512 byte sectors
+    pub pe_start: u64,
+    pub pe_count: u64,
+    /// Guest offset of the PV. None if the PV is not on this disk
+    pub offset: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogicalVolume {
+    pub name: String,
+    pub id: String,
+    pub status: Vec<String>,
+    /// Size in bytes
+    pub size: u64,
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Segment {
+    pub start_extent: u64,
+    pub extent_count: u64,
+    /// Segment type from the metadata. Ex: `striped`
+    pub segment_type: String,
+    /// Stripe size in 512 byte sectors. Zero for linear segments
+    pub stripe_size: u64,
+    pub stripes: Vec<Stripe>,
+    /// Only for `thin-pool` segments
+    pub thin_pool: Option<ThinPoolSegment>,
+    /// Only for `thin` segments
+    pub thin: Option<ThinSegment>,
+    /// Only for `snapshot` segments
+    pub snapshot: Option<SnapshotSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stripe {
+    /// PV name in the volume group
+    pub physical_volume: String,
+    pub start_extent: u64,
+}
+
+/// Thin pool built from a metadata LV and a data LV
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThinPoolSegment {
+    /// LV with the thin pool metadata B-trees. Ex: `pool_tmeta`
+    pub metadata: String,
+    /// LV with the thin pool data blocks. Ex: `pool_tdata`
+    pub data: String,
+    /// Data block size in 512 byte sectors
+    pub chunk_size: u64,
+}
+
+/// Thin LV in a thin pool
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThinSegment {
+    /// Thin pool LV name
+    pub pool: String,
+    /// Thin device ID in the pool metadata
+    pub device_id: u64,
+    /// LV the thin LV was snapshotted from
+    pub origin: Option<String>,
+    /// Read only LV outside the pool that provides blocks the thin LV has not written
+    pub external_origin: Option<String>,
+}
+
+/// Classic copy-on-write snapshot
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotSegment {
+    /// LV the snapshot was taken of
+    pub origin: String,
+    /// LV with the snapshot exceptions and changed chunks
+    pub cow_store: String,
+    /// Chunk size in 512 byte sectors
+    pub chunk_size: u64,
+}
+
+/// Find the LVM2 physical volumes in the partitions and build the volume groups.
+/// The whole disk is checked if there are no partitions
+pub(crate) fn volume_groups<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    boot: &BootInfo,
+) -> Result<Vec<VolumeGroup>, CalfError> {
+    let mut offsets = Vec::new();
+    pv_offsets(&boot.partitions, &mut offsets);
+    if boot
+        .partitions
+        .iter()
+        .all(|part| part.partition_type == PartitionType::None)
+    {
+        offsets.push(0);
+    }
+
+    let mut groups: Vec<VolumeGroup> = Vec::new();
+    let mut found_pvs = Vec::new();
+    for offset in offsets {
+        let Some(label) = read_pv_label(reader, offset)? else {
+            continue;
+        };
+        found_pvs.push((label.pv_uuid.clone(), offset));
+
+        let Some(area) = label.metadata_areas.first() else {
+            // PVs can be created without metadata. Another PV in the group will have it
+            continue;
+        };
+        let group = match read_volume_group(reader, offset, *area) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("[calf] Could not read LVM metadata for PV at {offset}: {err}");
+                continue;
+            }
+        };
+
+        match groups.iter_mut().find(|value| value.id == group.id) {
+            Some(existing) if existing.seqno < group.seqno => *existing = group,
+            Some(_) => {}
+            None => groups.push(group),
+        }
+    }
+
+    for group in &mut groups {
+        for pv in &mut group.physical_volumes {
+            pv.offset = found_pvs
+                .iter()
+                .find(|(uuid, _)| *uuid == pv.id)
+                .map(|(_, offset)| *offset);
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Partitions that may be LVM2 physical volumes
+fn pv_offsets(partitions: &[Partition], offsets: &mut Vec<u64>) {
+    for part in partitions {
+        let is_pv = part
+            .filesystem
+            .as_ref()
+            .is_some_and(|fs| fs.fs_type == FilesystemType::LvmPv);
+        if is_pv || part.partition_type == PartitionType::LinuxLvm {
+            offsets.push(part.offset_start);
+        }
+        pv_offsets(&part.children, offsets);
+    }
+}
+
+fn read_volume_group<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    pv_offset: u64,
+    area: (u64, u64),
+) -> Result<VolumeGroup, CalfError> {
+    let (metadata_offset, text) = read_metadata(reader, pv_offset, area)?;
+    let parse_error = |detail: String| CalfError::Parse {
+        structure: Structure::LvmMetadata,
+        offset: Offset::Guest(metadata_offset),
+        detail,
+    };
+
+    let root = parse_metadata(&text).map_err(parse_error)?;
+    // The only section in the root is the volume group
+    let Some(section) = root.sections.first() else {
+        return Err(parse_error(String::from("no volume group section")));
+    };
+    build_volume_group(section, metadata_offset).ok_or_else(|| {
+        parse_error(format!(
+            "volume group {} is missing required values or has values out of range",
+            section.name
+        ))
+    })
+}
+
+/// Build the volume group model from the metadata section
+fn build_volume_group(section: &Section, metadata_offset: u64) -> Option<VolumeGroup> {
+    let extent_size = section.number("extent_size")?;
+
+    let mut physical_volumes = Vec::new();
+    if let Some(pvs) = section.section("physical_volumes") {
+        for pv in &pvs.sections {
+            physical_volumes.push(PhysicalVolume {
+                name: pv.name.clone(),
+                id: pv.string("id")?,
+                device: pv.string("device").unwrap_or_default(),
+                pe_start: pv.number("pe_start")?,
+                pe_count: pv.number("pe_count")?,
+                offset: None,
+            });
+        }
+    }
+
+    let mut logical_volumes = Vec::new();
+    if let Some(lvs) = section.section("logical_volumes") {
+        for lv in &lvs.sections {
+            logical_volumes.push(build_logical_volume(lv, extent_size)?);
+        }
+    }
+
+    Some(VolumeGroup {
+        name: section.name.clone(),
+        id: section.string("id")?,
+        seqno: section.number("seqno").unwrap_or_default(),
+        extent_size,
+        physical_volumes,
+        logical_volumes,
+        metadata_offset,
+    })
+}
+
+fn build_logical_volume(section: &Section, extent_size: u64) -> Option<LogicalVolume> {
+    let mut segments = Vec::new();
+    for segment in &section.sections {
+        if !segment.name.starts_with("segment") {
+            continue;
+        }
+        segments.push(Segment {
+            start_extent: segment.number("start_extent")?,
+            extent_count: segment.number("extent_count")?,
+            segment_type: segment.string("type")?,
+            stripe_size: segment.number("stripe_size").unwrap_or_default(),
+            stripes: build_stripes(segment.value("stripes"))?,
+            thin_pool: build_thin_pool(segment),
+            thin: build_thin(segment),
+            snapshot: build_snapshot(segment),
+        });
+    }
+
+    let sector_size = 512;
+    let mut extents = 0;
+    for segment in &segments {
+        extents = u64::max(
+            extents,
+            segment.start_extent.checked_add(segment.extent_count)?,
+        );
+    }
+    Some(LogicalVolume {
+        name: section.name.clone(),
+        id: section.string("id")?,
+        status: section.strings("status"),
+        size: extents.checked_mul(extent_size)?.checked_mul(sector_size)?,
+        segments,
+    })
+}
+
+/// Stripes are a list of PV name and starting extent pairs. Returns None if an extent is negative
+fn build_stripes(value: Option<&Value>) -> Option<Vec<Stripe>> {
+    let Some(Value::List(values)) = value else {
+        return Some(Vec::new());
+    };
+    let mut stripes = Vec::new();
+    for pair in values.chunks_exact(2) {
+        if let [Value::String(name), Value::Number(extent)] = pair {
+            stripes.push(Stripe {
+                physical_volume: name.clone(),
+                start_extent: u64::try_from(*extent).ok()?,
+            });
+        }
+    }
+    Some(stripes)
+}
+
+fn build_thin_pool(segment: &Section) -> Option<ThinPoolSegment> {
+    if segment.string("type")? != "thin-pool" {
+        return None;
+    }
+    Some(ThinPoolSegment {
+        metadata: segment.string("metadata")?,
+        data: segment.string("pool")?,
+        chunk_size: segment.number("chunk_size")?,
+    })
+}
+
+fn build_thin(segment: &Section) -> Option<ThinSegment> {
+    if segment.string("type")? != "thin" {
+        return None;
+    }
+    Some(ThinSegment {
+        pool: segment.string("thin_pool")?,
+        device_id: segment.number("device_id")?,
+        origin: segment.string("origin"),
+        external_origin: segment.string("external_origin"),
+    })
+}
+
+fn build_snapshot(segment: &Section) -> Option<SnapshotSegment> {
+    if segment.string("type")? != "snapshot" {
+        return None;
+    }
+    Some(SnapshotSegment {
+        origin: segment.string("origin")?,
+        cow_store: segment.string("cow_store")?,
+        chunk_size: segment.number("chunk_size")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_volume_group;
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+        lvm::metadata::parse_metadata,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_volume_groups() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        let vg = &groups[0];
+        assert_eq!(vg.name, "vg0");
+        assert_eq!(vg.seqno, 4);
+        assert_eq!(vg.extent_size, 128);
+        assert_eq!(vg.physical_volumes.len(), 2);
+        assert_eq!(vg.physical_volumes[0].offset, Some(64 * 512));
+        assert_eq!(vg.physical_volumes[1].offset, Some(8192 * 512));
+        assert_eq!(vg.physical_volumes[1].device, "/dev/vda2");
+
+        assert_eq!(vg.logical_volumes.len(), 2);
+        let root = &vg.logical_volumes[0];
+        assert_eq!(root.name, "root");
+        assert_eq!(root.size, 8 * 128 * 512);
+        assert_eq!(root.segments.len(), 2);
+        assert_eq!(root.segments[1].stripes[0].physical_volume, "pv1");
+        assert_eq!(root.segments[1].stripes[0].start_extent, 2);
+        assert_eq!(root.status, vec!["READ", "WRITE", "VISIBLE"]);
+
+        let data = &vg.logical_volumes[1];
+        assert_eq!(data.segments[0].segment_type, "striped");
+        assert_eq!(data.segments[0].stripe_size, 16);
+        assert_eq!(data.segments[0].stripes.len(), 2);
+    }
+
+    #[test]
+    fn test_thin_volume_group() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/lvm/lvm_thin.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let groups = os_reader.volume_groups(&boot).unwrap();
+        let vg = &groups[0];
+        assert_eq!(vg.logical_volumes.len(), 8);
+
+        let pool = vg.logical_volumes[0].segments[0]
+            .thin_pool
+            .as_ref()
+            .unwrap();
+        assert_eq!(pool.metadata, "pool_tmeta");
+        assert_eq!(pool.data, "pool_tdata");
+        assert_eq!(pool.chunk_size, 128);
+
+        let thin = vg.logical_volumes[2].segments[0].thin.as_ref().unwrap();
+        assert_eq!(thin.pool, "pool");
+        assert_eq!(thin.device_id, 2);
+        assert_eq!(thin.origin.as_deref(), Some("thin1"));
+        assert!(thin.external_origin.is_none());
+
+        let snapshot = &vg.logical_volumes[5].segments[0];
+        assert!(snapshot.thin.is_none());
+        let snapshot = snapshot.snapshot.as_ref().unwrap();
+        assert_eq!(snapshot.origin, "origin");
+        assert_eq!(snapshot.cow_store, "snap");
+        assert_eq!(snapshot.chunk_size, 8);
+    }
+
+    #[test]
+    fn test_build_volume_group_out_of_range() {
+        let text = |extent_count: &str, extent: &str| {
+            format!(
+                r#"vg0 {{
+id = "abc"
+extent_size = 8192
+logical_volumes {{
+root {{
+id = "def"
+segment1 {{
+start_extent = 0
+extent_count = {extent_count}
+type = "striped"
+stripes = ["pv0", {extent}]
+}}
+}}
+}}
+}}"#
+            )
+        };
+        let root = parse_metadata(&text("4", "0")).unwrap();
+        let vg = build_volume_group(&root.sections[0], 0).unwrap();
+        assert_eq!(vg.logical_volumes[0].size, 4 * 8192 * 512);
+
+        let root = parse_metadata(&text("9223372036854775807", "0")).unwrap();
+        assert!(build_volume_group(&root.sections[0], 0).is_none());
+        let root = parse_metadata(&text("4", "-1")).unwrap();
+        assert!(build_volume_group(&root.sections[0], 0).is_none());
+    }
+}
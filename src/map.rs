@@ -0,0 +1,284 @@
+use crate::{
+    error::CalfError,
+    format::level::{Level, read_level},
+    reader::OsReader,
+};
+use std::io::{Read, Seek};
+
+/// Allocation status for a range of the guest disk.
+/// Mirrors the records from `qemu-img map --output=json`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapEntry {
+    pub start: u64,
+    pub length: u64,
+    /// Backing files are not supported by calf. Always 0
+    pub depth: u64,
+    /// Range is allocated in the QCOW file
+    pub present: bool,
+    /// Range reads as all zeros
+    pub zero: bool,
+    pub data: bool,
+    pub compressed: bool,
+    /// Offset to the data in the QCOW file. Not set for compressed or unallocated clusters
+    pub offset: Option<u64>,
+}
+
+/// Walk the whole guest disk and get the allocation status of each range.
+/// Adjacent ranges are merged the same way as `qemu-img map`
+pub(crate) fn allocation_map<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+) -> Result<Vec<MapEntry>, CalfError> {
+    let mut entries = Vec::new();
+    for index in 0..reader.qcow.level1_table.len() {
+        for entry in level1_map(reader, index)? {
+            merge_entry(&mut entries, entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Get the allocation status of the guest range covered by one level 1 entry
+pub(crate) fn level1_map<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    index: usize,
+) -> Result<Vec<MapEntry>, CalfError> {
+    let size = 8;
+    let level2_entries = reader.cluster_size / size;
+    let level1_span = reader.cluster_size * level2_entries;
+
+    let start = index as u64 * level1_span;
+    let qcow = reader.qcow;
+    let level1 = match qcow.level1_table.get(index) {
+        Some(result) if start < reader.os_size => result,
+        _ => return Ok(Vec::new()),
+    };
+    let end = u64::min(start + level1_span, reader.os_size);
+
+    if level1.offset == 0 {
+        return Ok(vec![unallocated(start, end - start)]);
+    }
+
+    let level2_table = read_level(reader.reader, &reader.cluster_bits, &level1.offset)?;
+    let mut entries = Vec::new();
+    let mut cluster_start = start;
+    for level2 in &level2_table {
+        if cluster_start >= end {
+            break;
+        }
+        let length = u64::min(reader.cluster_size, end - cluster_start);
+        merge_entry(&mut entries, cluster_entry(level2, cluster_start, length));
+        cluster_start += length;
+    }
+
+    // A short level 2 table means the rest of the range has nothing allocated
+    if cluster_start < end {
+        merge_entry(
+            &mut entries,
+            unallocated(cluster_start, end - cluster_start),
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Format map entries exactly like `qemu-img map --output=json`
+pub fn map_json(entries: &[MapEntry]) -> String {
+    let mut output = String::from("[");
+    for (index, entry) in entries.iter().enumerate() {
+        if index != 0 {
+            output.push_str(",\n");
+        }
+        output.push_str(&format!(
+            "{{ \"start\": {}, \"length\": {}, \"depth\": {}, \"present\": {}, \"zero\": {}, \"data\": {}, \"compressed\": {}",
+            entry.start,
+            entry.length,
+            entry.depth,
+            entry.present,
+            entry.zero,
+            entry.data,
+            entry.compressed
+        ));
+        if let Some(offset) = entry.offset {
+            output.push_str(&format!(", \"offset\": {offset}"));
+        }
+        output.push('}');
+    }
+    output.push_str("]\n");
+
+    output
+}
+
+/// Determine the allocation status of a single level 2 entry
+fn cluster_entry(level: &Level, start: u64, length: u64) -> MapEntry {
+    if level.is_compressed {
+        return MapEntry {
+            start,
+            length,
+            depth: 0,
+            present: true,
+            zero: false,
+            data: true,
+            compressed: true,
+            offset: None,
+        };
+    }
+
+    if level.is_zero {
+        // Zero clusters may still have space preallocated in the QCOW file
+        return MapEntry {
+            start,
+            length,
+            depth: 0,
+            present: true,
+            zero: true,
+            data: false,
+            compressed: false,
+            offset: (level.offset != 0).then_some(level.offset),
+        };
+    }
+
+    if level.offset == 0 {
+        return unallocated(start, length);
+    }
+
+    MapEntry {
+        start,
+        length,
+        depth: 0,
+        present: true,
+        zero: false,
+        data: true,
+        compressed: false,
+        offset: Some(level.offset),
+    }
+}
+
+/// Unallocated ranges without a backing file read as zeros
+fn unallocated(start: u64, length: u64) -> MapEntry {
+    MapEntry {
+        start,
+        length,
+        depth: 0,
+        present: false,
+        zero: true,
+        data: false,
+        compressed: false,
+        offset: None,
+    }
+}
+
+/// Merge the entry into the previous one if they are contiguous and have the same status
+fn merge_entry(entries: &mut Vec<MapEntry>, entry: MapEntry) {
+    if let Some(last) = entries.last_mut()
+        && can_merge(last, &entry)
+    {
+        last.length += entry.length;
+        return;
+    }
+
+    entries.push(entry);
+}
+
+/// Same merge rules that `qemu-img map` uses
+fn can_merge(current: &MapEntry, next: &MapEntry) -> bool {
+    if current.depth != next.depth
+        || current.present != next.present
+        || current.zero != next.zero
+        || current.data != next.data
+        || current.compressed != next.compressed
+    {
+        return false;
+    }
+
+    match (current.offset, next.offset) {
+        (Some(offset), Some(next_offset)) => offset + current.length == next_offset,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MapEntry, can_merge, map_json, merge_entry, unallocated};
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn test_allocation_map() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let results = os_reader.allocation_map().unwrap();
+
+        assert_eq!(results.len(), 9);
+        assert_eq!(results[0].length, 1024);
+        assert_eq!(results[0].offset, Some(1536));
+        assert!(results[1].zero && results[1].present);
+        assert_eq!(results[1].offset, None);
+        assert_eq!(results[2].offset, Some(2560));
+        assert_eq!(results[4].start, 2560);
+        assert!(!results[4].present);
+        assert!(results[5].compressed);
+        assert_eq!(results[6].length, 59904);
+        assert_eq!(results[7].offset, Some(4608));
+        assert_eq!(results[8].start + results[8].length, 131072);
+    }
+
+    #[test]
+    fn test_map_json() {
+        let data = MapEntry {
+            start: 0,
+            length: 65536,
+            depth: 0,
+            present: true,
+            zero: false,
+            data: true,
+            compressed: false,
+            offset: Some(327680),
+        };
+        let results = map_json(&[data, unallocated(65536, 65536)]);
+        assert_eq!(
+            results,
+            "[{ \"start\": 0, \"length\": 65536, \"depth\": 0, \"present\": true, \"zero\": false, \"data\": true, \"compressed\": false, \"offset\": 327680},\n{ \"start\": 65536, \"length\": 65536, \"depth\": 0, \"present\": false, \"zero\": true, \"data\": false, \"compressed\": false}]\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_entry() {
+        let mut entries = vec![unallocated(0, 512)];
+        merge_entry(&mut entries, unallocated(512, 512));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].length, 1024);
+
+        let data = MapEntry {
+            start: 1024,
+            length: 512,
+            depth: 0,
+            present: true,
+            zero: false,
+            data: true,
+            compressed: false,
+            offset: Some(4096),
+        };
+        let mut next = data.clone();
+        next.start = 1536;
+        next.offset = Some(8192);
+        assert!(!can_merge(&data, &next));
+        next.offset = Some(4608);
+        assert!(can_merge(&data, &next));
+    }
+}
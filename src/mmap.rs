@@ -0,0 +1,435 @@
+use crate::{
+    calf::{CalfReader, CalfReaderAction, QcowInfo},
+    error::{CalfError, Offset, Structure},
+    format::{
+        cluster::{ClusterLocation, cluster_location, decode_cluster},
+        header::{CalfHeader, Header},
+        level::Level,
+    },
+    reader::{OsReader, missing_level1, seek_position},
+};
+use log::{error, warn};
+use memmap2::Mmap;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// QCOW file mapped into memory. Requires the `mmap` feature
+pub struct MmapImage {
+    map: Mmap,
+}
+
+impl MmapImage {
+    /// Map a QCOW file into memory. Only regular files can be mapped
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapImage, CalfError> {
+        let file = match File::open(path.as_ref()) {
+            Ok(result) => result,
+            Err(err) => {
+                error!("[calf] Could not open QCOW file for mapping: {err:?}");
+                return Err(CalfError::ReadFile {
+                    offset: Offset::Host(0),
+                    source: err,
+                });
+            }
+        };
+        MmapImage::from_file(&file)
+    }
+
+    /// Map an already opened QCOW file into memory
+    pub fn from_file(file: &File) -> Result<MmapImage, CalfError> {
+        let is_file = file.metadata().is_ok_and(|meta| meta.is_file());
+        if !is_file {
+            error!("[calf] Only regular files can be memory mapped");
+            return Err(CalfError::ReadFile {
+                offset: Offset::Host(0),
+                source: io::Error::new(io::ErrorKind::Unsupported, "not a regular file"),
+            });
+        }
+
+        // SAFETY: The map is read only. Like every mmap reader, the QCOW file must not be truncated while mapped
+        #[allow(unsafe_code)]
+        let map = unsafe { Mmap::map(file) };
+        match map {
+            Ok(map) => Ok(MmapImage { map }),
+            Err(err) => {
+                error!("[calf] Could not memory map QCOW file: {err:?}");
+                Err(CalfError::ReadFile {
+                    offset: Offset::Host(0),
+                    source: err,
+                })
+            }
+        }
+    }
+
+    /// Raw bytes of the QCOW file
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Grab QCOW header info
+    pub fn header(&self) -> Result<Header, CalfError> {
+        let size = 112;
+        let Some(bytes) = self.map.get(..size) else {
+            error!("[calf] QCOW file is too small for a header");
+            return Err(CalfError::Parse {
+                structure: Structure::Header,
+                offset: Offset::Host(0),
+                detail: format!("file is only {} bytes", self.map.len()),
+            });
+        };
+        Header::grab_header(bytes)
+    }
+
+    /// Parse the header and level 1 table
+    pub fn info(&self) -> Result<QcowInfo, CalfError> {
+        let header = self.header()?;
+        let start = header.level_one_table_offset as usize;
+        let end = start.saturating_add(header.level_one_table_ref as usize);
+        let Some(bytes) = self.map.get(start..end) else {
+            error!("[calf] Level 1 table at {start} is past the end of the QCOW file");
+            return Err(CalfError::Parse {
+                structure: Structure::LevelTable,
+                offset: Offset::Host(start as u64),
+                detail: String::from("table is past the end of the QCOW file"),
+            });
+        };
+        let level1_table = Level::grab_levels(bytes, header.level_one_table_offset)?;
+
+        Ok(QcowInfo {
+            header,
+            level1_table,
+        })
+    }
+
+    /// Create a reader that can read bytes from the guest OS. Level lookups and cluster reads borrow from the map
+    pub fn os_reader<'a>(&'a self, info: &'a QcowInfo) -> MmapOsReader<'a> {
+        let cluster_bits = info.header.cluster_block_bits_count;
+        MmapOsReader {
+            qcow: info,
+            bytes: &self.map,
+            position: 0,
+            cluster_bits,
+            cluster_size: 1 << cluster_bits,
+            os_size: info.header.size,
+        }
+    }
+}
+
+/// Reader for the guest OS inside a memory mapped QCOW file. Implements `Read` and `Seek`
+pub struct MmapOsReader<'a> {
+    qcow: &'a QcowInfo,
+    bytes: &'a [u8],
+    position: u64,
+    cluster_bits: u32,
+    cluster_size: u64,
+    os_size: u64,
+}
+
+impl<'a> MmapOsReader<'a> {
+    /// Get the guest cluster that contains the provided guest offset.
+    /// Uncompressed clusters are borrowed straight from the map. Zero and unallocated clusters return None
+    pub fn cluster(&self, offset: u64) -> io::Result<Option<Cow<'a, [u8]>>> {
+        let (location, is_compressed) = self.location(offset >> self.cluster_bits)?;
+        let ClusterLocation::Host(host_offset) = location else {
+            return Ok(None);
+        };
+
+        let start = host_offset as usize;
+        let end = start.saturating_add(self.cluster_size as usize);
+        let data = match self.bytes.get(start..end) {
+            Some(result) => Cow::Borrowed(result),
+            None => {
+                // Same as the BufReader path. A short cluster at the end of the QCOW file is an error
+                return Err(io::Error::from(CalfError::ReadFile {
+                    offset: Offset::Host(host_offset),
+                    source: io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Cluster is past the end of the qcow file",
+                    ),
+                }));
+            }
+        };
+
+        if is_compressed {
+            let decoded = decode_cluster(
+                data.into_owned(),
+                &self.qcow.header.compression_method,
+                &is_compressed,
+            )?;
+            return Ok(Some(Cow::Owned(decoded)));
+        }
+        Ok(Some(data))
+    }
+
+    /// Lookup the host location of a guest cluster. Also returns if the cluster is compressed
+    fn location(&self, cluster_key: u64) -> io::Result<(ClusterLocation, bool)> {
+        let size = 8;
+        let level2_entries = self.cluster_size / size;
+        let level1_key = cluster_key / level2_entries;
+        let level2_index = cluster_key % level2_entries;
+
+        let level1 = self
+            .qcow
+            .level1_table
+            .get(level1_key as usize)
+            .ok_or_else(|| missing_level1(cluster_key << self.cluster_bits))?;
+        if level1.offset == 0 {
+            return Ok((ClusterLocation::Zeros, false));
+        }
+
+        // Same as the BufReader path. The whole level 2 table must be in the file, otherwise missing entries would read as zeros
+        let table_end = level1.offset.saturating_add(self.cluster_size);
+        if table_end > self.bytes.len() as u64 {
+            return Err(io::Error::from(CalfError::ReadFile {
+                offset: Offset::Host(level1.offset),
+                source: io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Level 2 table is past the end of the qcow file",
+                ),
+            }));
+        }
+        let start = (level1.offset + level2_index * size) as usize;
+        let Some(entry) = self
+            .bytes
+            .get(start..start + size as usize)
+            .and_then(|value| value.try_into().ok())
+        else {
+            return Err(io::Error::from(CalfError::ReadFile {
+                offset: Offset::Host(start as u64),
+                source: io::Error::from(io::ErrorKind::UnexpectedEof),
+            }));
+        };
+
+        let level2 = Level::from_value(u64::from_be_bytes(entry));
+        Ok((
+            cluster_location(level1, Some(&level2)),
+            level2.is_compressed,
+        ))
+    }
+}
+
+impl Read for MmapOsReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.os_size {
+            return Ok(0);
+        }
+
+        let position_in_cluster = self.position % self.cluster_size;
+        let cluster_bytes_remaining = u64::min(
+            self.cluster_size - position_in_cluster,
+            self.os_size - self.position,
+        );
+        let read_len = u64::min(cluster_bytes_remaining, buf.len() as u64) as usize;
+        let read_start = position_in_cluster as usize;
+
+        match self.cluster(self.position)? {
+            Some(cluster) => {
+                buf[..read_len].copy_from_slice(&cluster[read_start..read_start + read_len]);
+            }
+            None => buf[..read_len].fill(0),
+        }
+
+        self.position += read_len as u64;
+        Ok(read_len)
+    }
+}
+
+impl Seek for MmapOsReader<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(self.position, self.os_size, position)?;
+        Ok(self.position)
+    }
+}
+
+/// A QCOW file that is memory mapped when possible. Falls back to `CalfReader` for anything that cannot be mapped
+pub enum QcowSource<T: Seek + Read> {
+    Mapped(MmapImage),
+    Reader(CalfReader<T>),
+}
+
+impl QcowSource<File> {
+    /// Open a QCOW file. Uses a memory map if the file can be mapped
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<QcowSource<File>, CalfError> {
+        let file = match File::open(path.as_ref()) {
+            Ok(result) => result,
+            Err(err) => {
+                error!("[calf] Could not open QCOW file: {err:?}");
+                return Err(CalfError::ReadFile {
+                    offset: Offset::Host(0),
+                    source: err,
+                });
+            }
+        };
+
+        if let Ok(image) = MmapImage::from_file(&file) {
+            return Ok(QcowSource::Mapped(image));
+        }
+        warn!("[calf] Could not memory map QCOW file. Falling back to reading the file");
+        Ok(QcowSource::from_reader(file))
+    }
+}
+
+impl<T: Seek + Read> QcowSource<T> {
+    /// Use the `Read` + `Seek` path for sources that cannot be memory mapped
+    pub fn from_reader(reader: T) -> QcowSource<T> {
+        QcowSource::Reader(CalfReader {
+            fs: BufReader::new(reader),
+        })
+    }
+
+    /// Check if the QCOW file is memory mapped
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, QcowSource::Mapped(_))
+    }
+
+    /// Parse the header and level 1 table
+    pub fn info(&mut self) -> Result<QcowInfo, CalfError> {
+        match self {
+            QcowSource::Mapped(image) => image.info(),
+            QcowSource::Reader(calf) => Ok(QcowInfo {
+                header: calf.header()?,
+                level1_table: calf.level1_entries()?,
+            }),
+        }
+    }
+
+    /// Create a reader that can read bytes from the guest OS
+    pub fn os_reader<'a>(
+        &'a mut self,
+        info: &'a QcowInfo,
+    ) -> Result<GuestReader<'a, T>, CalfError> {
+        match self {
+            QcowSource::Mapped(image) => Ok(GuestReader::Mapped(image.os_reader(info))),
+            QcowSource::Reader(calf) => Ok(GuestReader::Reader(calf.os_reader(info)?)),
+        }
+    }
+}
+
+/// Guest OS reader for a `QcowSource`. Implements `Read` and `Seek`
+pub enum GuestReader<'a, T: Seek + Read> {
+    Mapped(MmapOsReader<'a>),
+    Reader(OsReader<'a, 'a, T>),
+}
+
+impl<T: Seek + Read> Read for GuestReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            GuestReader::Mapped(reader) => reader.read(buf),
+            GuestReader::Reader(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<T: Seek + Read> Seek for GuestReader<'_, T> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            GuestReader::Mapped(reader) => reader.seek(position),
+            GuestReader::Reader(reader) => reader.seek(position),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MmapImage, QcowSource};
+    use crate::error::{CalfError, Offset};
+    use std::{
+        borrow::Cow,
+        fs::File,
+        io::{Cursor, Read, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    fn sparse_path() -> PathBuf {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+        test_location
+    }
+
+    #[test]
+    fn test_mmap_cluster() {
+        let image = MmapImage::open(sparse_path()).unwrap();
+        let info = image.info().unwrap();
+        assert_eq!(info.header.version, 3);
+        assert_eq!(info.level1_table.len(), 4);
+
+        let reader = image.os_reader(&info);
+        let cluster = reader.cluster(0).unwrap().unwrap();
+        assert!(matches!(cluster, Cow::Borrowed(_)));
+        assert_eq!(cluster.as_ref(), [1; 512]);
+        assert_eq!(reader.cluster(65536).unwrap().unwrap().as_ref(), [4; 512]);
+        assert!(reader.cluster(1024).unwrap().is_none());
+        assert!(reader.cluster(70000).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_mmap_read() {
+        let mut source = QcowSource::open(sparse_path()).unwrap();
+        assert!(source.is_mapped());
+        let info = source.info().unwrap();
+        let mut reader = source.os_reader(&info).unwrap();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+
+        let data = std::fs::read(sparse_path()).unwrap();
+        let mut fallback = QcowSource::from_reader(Cursor::new(data));
+        assert!(!fallback.is_mapped());
+        let fallback_info = fallback.info().unwrap();
+        let mut fallback_reader = fallback.os_reader(&fallback_info).unwrap();
+        let mut expected = Vec::new();
+        fallback_reader.read_to_end(&mut expected).unwrap();
+
+        assert_eq!(bytes.len(), 131072);
+        assert_eq!(bytes, expected);
+
+        let status = reader.seek(SeekFrom::Start(65536 + 500)).unwrap();
+        assert_eq!(status, 66036);
+        let mut bytes = [0; 20];
+        reader.read_exact(&mut bytes).unwrap();
+        assert_eq!(bytes[..12], [4; 12]);
+        assert_eq!(bytes[12..], [0; 8]);
+    }
+
+    #[test]
+    fn test_mmap_truncated() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/truncated.qcow");
+
+        let image = MmapImage::open(&test_location).unwrap();
+        let info = image.info().unwrap();
+        let reader = image.os_reader(&info);
+        assert_eq!(reader.cluster(512).unwrap().unwrap().as_ref(), [2; 512]);
+
+        // Level 2 entry points past the end of the file
+        let err = reader.cluster(1024).unwrap_err();
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(1048576));
+
+        // Level 2 table was cut off. Entries that are still in the file fail too
+        for offset in [64 * 512, 66 * 512] {
+            let err = reader.cluster(offset).unwrap_err();
+            let err = err.downcast::<CalfError>().unwrap();
+            assert_eq!(err.offset(), Offset::Host(2560));
+        }
+
+        // BufReader fallback fails the same way
+        let data = std::fs::read(&test_location).unwrap();
+        let mut fallback = QcowSource::from_reader(Cursor::new(data));
+        let fallback_info = fallback.info().unwrap();
+        let mut fallback_reader = fallback.os_reader(&fallback_info).unwrap();
+        fallback_reader.seek(SeekFrom::Start(64 * 512)).unwrap();
+        let mut bytes = [0; 512];
+        let err = fallback_reader.read_exact(&mut bytes).unwrap_err();
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(2560));
+    }
+
+    #[test]
+    fn test_mmap_not_file() {
+        let dir = File::open(env!("CARGO_MANIFEST_DIR")).unwrap();
+        assert!(MmapImage::from_file(&dir).is_err());
+    }
+}
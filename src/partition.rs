@@ -0,0 +1,124 @@
+use crate::{bootsector::boot::Partition, reader::seek_position};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reader confined to a single partition. Offset 0 is the start of the partition and reads stop at the partition size.
+/// Works with an `OsReader` or any other guest disk reader
+pub struct PartitionReader<R: Read + Seek> {
+    reader: R,
+    start: u64,
+    size: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> PartitionReader<R> {
+    /// Create a reader for the provided partition
+    pub fn new(reader: R, partition: &Partition) -> PartitionReader<R> {
+        PartitionReader {
+            reader,
+            start: partition.offset_start,
+            size: partition.partition_size,
+            position: 0,
+        }
+    }
+
+    /// Partition size in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Guest disk offset of the partition
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Get the guest disk reader back
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> Read for PartitionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+
+        let read_len = u64::min(self.size - self.position, buf.len() as u64) as usize;
+        self.reader
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        let bytes_read = self.reader.read(&mut buf[..read_len])?;
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read + Seek> Seek for PartitionReader<R> {
+    /// Seeking past the end of the partition stops at the partition size
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let new_position = seek_position(self.position, self.size, position)?;
+        self.position = new_position.min(self.size);
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartitionReader;
+    use crate::{
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_partition_reader() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/mbr/bsd.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot_info = os_reader.get_boot_info().unwrap();
+
+        // The FreeBSD slice has the disklabel in its second sector
+        let slice = &boot_info.partitions[0];
+        let mut part_reader = os_reader.partition_reader(slice);
+        assert_eq!(part_reader.start(), 63 * 512);
+        assert_eq!(part_reader.size(), 1000 * 512);
+
+        let mut magic = [0; 4];
+        part_reader.seek(SeekFrom::Start(512)).unwrap();
+        part_reader.read_exact(&mut magic).unwrap();
+        assert_eq!(magic, [0x57, 0x45, 0x56, 0x82]);
+
+        // Reads stop at the end of the partition
+        assert_eq!(
+            part_reader.seek(SeekFrom::End(-10)).unwrap(),
+            1000 * 512 - 10
+        );
+        let mut data = Vec::new();
+        part_reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 10);
+        assert_eq!(
+            part_reader.seek(SeekFrom::Current(100)).unwrap(),
+            1000 * 512
+        );
+        assert_eq!(part_reader.read(&mut magic).unwrap(), 0);
+
+        let mut os_reader = part_reader.into_inner();
+        let mut part_reader = PartitionReader::new(&mut os_reader, &slice.children[0]);
+        assert_eq!(part_reader.size(), 600 * 512);
+        assert_eq!(part_reader.read(&mut magic).unwrap(), 4);
+    }
+}
@@ -1,26 +1,36 @@
 /// Heavily inspired by <https://github.com/panda-re/qcow-rs/blob/master/src/reader.rs> (MIT)
 use crate::{
-    bootsector::boot::{BootInfo, boot_info},
+    bootsector::boot::{BootInfo, Partition, boot_info},
     calf::QcowInfo,
-    error::CalfError,
+    error::{CalfError, Offset, Structure},
+    export::{ExportHash, ExportInfo, export_range},
+    extents::{Extents, next_data, next_hole},
     format::{
-        cluster::read_cluster,
+        cluster::{ClusterLocation, cluster_location, read_cluster},
         level::{Level, read_level},
     },
+    luks::header::{LuksHeader, read_luks_header},
+    lvm::{
+        reader::LogicalVolumeReader,
+        volume::{LogicalVolume, VolumeGroup, volume_groups},
+    },
+    map::{MapEntry, allocation_map},
+    partition::PartitionReader,
+    swap::{SwapHeader, SwapPages, read_swap_header},
 };
 use log::{debug, error};
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 
 pub struct OsReader<'qcow, 'reader, T>
 where
     T: std::io::Seek + std::io::Read,
 {
-    qcow: &'qcow QcowInfo,
+    pub(crate) qcow: &'qcow QcowInfo,
     pub(crate) reader: &'reader mut BufReader<T>,
     position: u64,
-    cluster_bits: u32,
-    cluster_size: u64,
-    os_size: u64,
+    pub(crate) cluster_bits: u32,
+    pub(crate) cluster_size: u64,
+    pub(crate) os_size: u64,
     level1_key: u64,
     level1_cache: &'qcow Level,
     level2_table_cache: Vec<Level>,
@@ -30,6 +40,7 @@ where
 }
 
 impl QcowInfo {
+    #[allow(clippy::new_ret_no_self)]
     /// Create a reader that can read bytes from OS guest inside the QCOW file
     pub fn new<'qcow, 'reader, T: io::Seek + io::Read>(
         &'qcow self,
@@ -64,14 +75,141 @@ impl QcowInfo {
         }
 
         error!("[calf] Could not get level one table for key {level1_key}");
-        Err(CalfError::Level)
+        Err(CalfError::Parse {
+            structure: Structure::LevelTable,
+            offset: Offset::Host(qcow.header.level_one_table_offset),
+            detail: String::from("level 1 or level 2 table is empty"),
+        })
     }
 }
 
 impl<'a, 'qcow, T: std::io::Seek + std::io::Read> OsReader<'a, 'qcow, T> {
     /// Determine OS boot information
     pub fn get_boot_info(&mut self) -> Result<BootInfo, CalfError> {
-        boot_info(self)
+        boot_info(self, None)
+    }
+
+    /// Determine OS boot information using the provided logical sector size. Use for 4K native disks without a GPT
+    pub fn get_boot_info_sector_size(&mut self, sector_size: u64) -> Result<BootInfo, CalfError> {
+        boot_info(self, Some(sector_size))
+    }
+
+    /// Walk the guest disk and get the allocation status of each range. Same records as `qemu-img map --output=json`
+    pub fn allocation_map(&mut self) -> Result<Vec<MapEntry>, CalfError> {
+        allocation_map(self)
+    }
+
+    /// Iterate through the guest disk extents. Each extent is labeled as data, zero, or unallocated
+    pub fn extents(&mut self) -> Extents<'_, 'a, 'qcow, T> {
+        Extents::new(self, 0)
+    }
+
+    /// Get the next guest offset at or after `offset` that contains data. Returns None if there is no more data
+    pub fn next_data(&mut self, offset: u64) -> Result<Option<u64>, CalfError> {
+        next_data(self, offset)
+    }
+
+    /// Get the next guest offset at or after `offset` that reads as zeros. The end of the guest disk counts as a hole.
+    /// Returns None if `offset` is past the end of the guest disk
+    pub fn next_hole(&mut self, offset: u64) -> Result<Option<u64>, CalfError> {
+        next_hole(self, offset)
+    }
+
+    /// Export the whole guest disk as a sparse raw image. Holes are seeked over instead of written.
+    /// `output` must be empty. `progress` gets the bytes exported so far and the total bytes
+    pub fn export<W: Write + Seek, F: FnMut(u64, u64)>(
+        &mut self,
+        output: &mut W,
+        hashes: &ExportHash,
+        progress: F,
+    ) -> Result<ExportInfo, CalfError> {
+        let size = self.os_size;
+        export_range(self, output, 0, size, hashes, progress)
+    }
+
+    /// Export a single partition as a sparse raw image. `output` must be empty
+    pub fn export_partition<W: Write + Seek, F: FnMut(u64, u64)>(
+        &mut self,
+        partition: &Partition,
+        output: &mut W,
+        hashes: &ExportHash,
+        progress: F,
+    ) -> Result<ExportInfo, CalfError> {
+        export_range(
+            self,
+            output,
+            partition.offset_start,
+            partition.partition_size,
+            hashes,
+            progress,
+        )
+    }
+
+    /// Create a reader confined to the provided partition. Offset 0 is the start of the partition
+    pub fn partition_reader(self, partition: &Partition) -> PartitionReader<Self> {
+        PartitionReader::new(self, partition)
+    }
+
+    /// Find the LVM2 physical volumes in the partitions and read the volume groups
+    pub fn volume_groups(&mut self, boot: &BootInfo) -> Result<Vec<VolumeGroup>, CalfError> {
+        volume_groups(self, boot)
+    }
+
+    /// Create a reader for an LVM2 logical volume. Offset 0 is the start of the logical volume
+    pub fn logical_volume_reader(
+        self,
+        group: &VolumeGroup,
+        volume: &LogicalVolume,
+    ) -> Result<LogicalVolumeReader<Self>, CalfError> {
+        LogicalVolumeReader::new(self, group, volume)
+    }
+
+    /// Read the header of a Linux swap partition. Returns None if the partition is not swap
+    pub fn swap_header(&mut self, partition: &Partition) -> Result<Option<SwapHeader>, CalfError> {
+        read_swap_header(self, partition.offset_start, partition.partition_size)
+    }
+
+    /// Iterate over the swap pages in the partition that have data in them
+    pub fn swap_pages(self, partition: &Partition, header: &SwapHeader) -> SwapPages<Self> {
+        SwapPages::new(
+            self,
+            partition.offset_start,
+            partition.partition_size,
+            header,
+        )
+    }
+
+    /// Read the LUKS1 or LUKS2 header of an encrypted partition. Returns None if the partition is not LUKS
+    pub fn luks_header(&mut self, partition: &Partition) -> Result<Option<LuksHeader>, CalfError> {
+        read_luks_header(self, partition.offset_start)
+    }
+
+    /// Try the passphrase on the LUKS key slots of the partition. Returns the master key if a key slot unlocks
+    #[cfg(feature = "luks")]
+    pub fn luks_unlock(
+        &mut self,
+        partition: &Partition,
+        header: &LuksHeader,
+        passphrase: &[u8],
+    ) -> Result<Option<Vec<u8>>, CalfError> {
+        crate::luks::unlock::unlock_passphrase(self, partition.offset_start, header, passphrase)
+    }
+
+    /// Create a decrypted reader for a LUKS partition with the master key
+    #[cfg(feature = "luks")]
+    pub fn luks_reader(
+        self,
+        partition: &Partition,
+        header: &LuksHeader,
+        master_key: &[u8],
+    ) -> Result<crate::luks::reader::LuksReader<Self>, CalfError> {
+        crate::luks::reader::LuksReader::new(
+            self,
+            partition.offset_start,
+            partition.partition_size,
+            header,
+            master_key,
+        )
     }
 
     fn refresh_level1_cache(&mut self) -> io::Result<()> {
@@ -80,21 +218,16 @@ impl<'a, 'qcow, T: std::io::Seek + std::io::Read> OsReader<'a, 'qcow, T> {
 
         let level1_key = (self.position / self.cluster_size) / level2_entries;
         if self.level1_key != level1_key {
-            self.level1_key = level1_key;
-            self.level1_cache =
-                self.qcow
-                    .level1_table
-                    .get(level1_key as usize)
-                    .ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::UnexpectedEof,
-                            "Read position past end of qcow file",
-                        )
-                    })?;
+            self.level1_cache = self
+                .qcow
+                .level1_table
+                .get(level1_key as usize)
+                .ok_or_else(|| missing_level1(self.position))?;
 
+            // Level 2 table past the end of the QCOW file is an error. It must not read as zeros
             self.level2_table_cache =
-                read_level(self.reader, &self.cluster_bits, &self.level1_cache.offset)
-                    .unwrap_or_default();
+                read_level(self.reader, &self.cluster_bits, &self.level1_cache.offset)?;
+            self.level1_key = level1_key;
         }
 
         Ok(())
@@ -107,35 +240,41 @@ impl<'a, 'qcow, T: std::io::Seek + std::io::Read> OsReader<'a, 'qcow, T> {
         let level2_index = level2_key % level2_entries;
 
         if self.level2_key != level2_key {
-            self.level2_key = level2_key;
             self.refresh_level1_cache()?;
 
             if self.level1_cache.offset != 0
                 && let Some(value) = self.level2_table_cache.get(level2_index as usize)
             {
                 self.level2_cache = value.clone();
-                self.level2_key = level2_key;
             }
-        }
-
-        if self.level1_cache.offset == 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "offset to level 2 is 0",
-            ));
+            self.level2_key = level2_key;
         }
 
         debug!(
             "[calf] level 2 cache: {:?}. Level 1 cache: {:?}",
             self.level2_cache, self.level1_cache
         );
-        self.cluster_bytes = read_cluster(
-            self.reader,
-            self.level2_cache.offset,
-            self.cluster_size,
-            &self.qcow.header.compression_method,
-            &self.level2_cache.is_compressed,
-        )?;
+        match cluster_location(self.level1_cache, Some(&self.level2_cache)) {
+            ClusterLocation::Zeros => {
+                self.cluster_bytes.clear();
+                self.cluster_bytes.resize(self.cluster_size as usize, 0);
+            }
+            ClusterLocation::Host(offset) => {
+                self.cluster_bytes = read_cluster(
+                    self.reader,
+                    offset,
+                    self.cluster_size,
+                    &self.qcow.header.compression_method,
+                    &self.level2_cache.is_compressed,
+                )
+                .map_err(|err| {
+                    io::Error::from(CalfError::ReadFile {
+                        offset: Offset::Host(offset),
+                        source: err,
+                    })
+                })?;
+            }
+        }
 
         Ok(())
     }
@@ -146,10 +285,17 @@ where
     T: std::io::Seek + std::io::Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.os_size {
+            return Ok(0);
+        }
+
         match self.refresh_level2_cache() {
             Ok(()) => {
                 let position_in_cluster = self.position % self.cluster_size;
-                let cluster_bytes_remaining = self.cluster_size - position_in_cluster;
+                let cluster_bytes_remaining = u64::min(
+                    self.cluster_size - position_in_cluster,
+                    self.os_size - self.position,
+                );
 
                 let read_len = u64::min(cluster_bytes_remaining, buf.len() as u64);
                 let read_end = position_in_cluster + read_len;
@@ -163,15 +309,7 @@ where
 
                 Ok(read_len as usize)
             }
-            Err(err) => (move || {
-                self.reader.seek(SeekFrom::Start(self.position)).ok()?;
-                let bytes_read = self.reader.read(buf).ok()?;
-
-                self.position += bytes_read as u64;
-
-                Some(bytes_read)
-            })()
-            .ok_or(err),
+            Err(err) => Err(err),
         }
     }
 }
@@ -181,51 +319,92 @@ where
     T: std::io::Seek + std::io::Read,
 {
     fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
-        match position {
-            std::io::SeekFrom::Start(start_position) => self.position = start_position,
-            std::io::SeekFrom::End(end_position) => {
-                self.position =
-                    (end_position + self.os_size as i64)
-                        .try_into()
-                        .map_err(|_err| {
-                            io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                "seek is out of range of 64-bit position",
-                            )
-                        })?;
-            }
-            std::io::SeekFrom::Current(relative_position) => {
-                self.position = self
-                    .position
-                    .try_into()
-                    .map_or_else(
-                        |_| self.position as i64 + relative_position,
-                        |pos: i64| pos + relative_position,
-                    )
-                    .try_into()
-                    .map_err(|_err| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "seek is out of range of 64-bit position",
-                        )
-                    })?;
-            }
-        }
-
+        self.position = seek_position(self.position, self.os_size, position)?;
         Ok(self.position)
     }
 }
 
+/// The level 1 table has no entry for the guest offset. The `CalfError` can be grabbed with `downcast`
+pub(crate) fn missing_level1(position: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        CalfError::Parse {
+            structure: Structure::LevelTable,
+            offset: Offset::Guest(position),
+            detail: String::from("Read position past end of qcow file"),
+        },
+    )
+}
+
+/// Read bytes from the guest disk at the provided offset
+pub(crate) fn read_guest<T: Seek + Read>(
+    reader: &mut OsReader<'_, '_, T>,
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>, CalfError> {
+    if let Err(err) = reader.seek(SeekFrom::Start(offset)) {
+        error!("[calf] Could not seek to guest offset {offset}: {err:?}");
+        return Err(CalfError::SeekFile {
+            offset: Offset::Guest(offset),
+            source: err,
+        });
+    }
+    let mut buf = vec![0; size as usize];
+    if let Err(err) = reader.read_exact(&mut buf) {
+        error!("[calf] Could not read guest bytes at {offset}: {err:?}");
+        return Err(CalfError::ReadFile {
+            offset: Offset::Guest(offset),
+            source: err,
+        });
+    }
+    Ok(buf)
+}
+
+/// Seek ended up before the start or past the 64-bit range
+fn bad_seek(current: u64) -> io::Error {
+    io::Error::from(CalfError::SeekFile {
+        offset: Offset::Guest(current),
+        source: io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seek is out of range of 64-bit position",
+        ),
+    })
+}
+
+/// Calculate the new guest position after a seek. Shared by the sync and async readers
+pub(crate) fn seek_position(
+    current: u64,
+    os_size: u64,
+    position: std::io::SeekFrom,
+) -> std::io::Result<u64> {
+    let new_position = match position {
+        std::io::SeekFrom::Start(start_position) => start_position,
+        std::io::SeekFrom::End(end_position) => (end_position + os_size as i64)
+            .try_into()
+            .map_err(|_err| bad_seek(current))?,
+        std::io::SeekFrom::Current(relative_position) => current
+            .try_into()
+            .map_or_else(
+                |_| current as i64 + relative_position,
+                |pos: i64| pos + relative_position,
+            )
+            .try_into()
+            .map_err(|_err| bad_seek(current))?,
+    };
+
+    Ok(new_position)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         calf::{CalfReader, CalfReaderAction, QcowInfo},
+        error::{CalfError, Offset},
         format::header::CalfHeader,
     };
     use std::{
         fs::File,
-        i64,
-        io::{BufReader, Seek, SeekFrom},
+        io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
         path::PathBuf,
     };
 
@@ -297,4 +476,57 @@ mod tests {
         let status = os_reader.seek(SeekFrom::Current(-i64::MAX)).unwrap();
         assert_eq!(status, 1);
     }
+
+    #[test]
+    fn test_seek_error_context() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/sparse.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        os_reader.seek(SeekFrom::Start(300)).unwrap();
+
+        let err = os_reader.seek(SeekFrom::Current(-301)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Guest(300));
+    }
+
+    #[test]
+    fn test_read_bad_level2_entry() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/sparse/truncated.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let mut data = [0; 512];
+        os_reader.seek(SeekFrom::Start(512)).unwrap();
+        os_reader.read_exact(&mut data).unwrap();
+        assert_eq!(data, [2; 512]);
+
+        // Level 2 entry for the third cluster points past the end of the QCOW file
+        let err = os_reader.read_exact(&mut data).unwrap_err();
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(1048576));
+
+        // Level 2 table at 2560 is cut off after two entries
+        os_reader.seek(SeekFrom::Start(64 * 512)).unwrap();
+        let err = os_reader.read_exact(&mut data).unwrap_err();
+        let err = err.downcast::<CalfError>().unwrap();
+        assert_eq!(err.offset(), Offset::Host(2560));
+    }
 }
@@ -0,0 +1,350 @@
+use crate::{
+    error::{CalfError, Offset, Structure},
+    utils::{guid::format_uuid, strings::extract_utf8_string},
+};
+use log::warn;
+use nom::{
+    bytes::complete::take,
+    number::{Endianness, complete::u32},
+};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Linux swap area header: <https://github.com/torvalds/linux/blob/master/include/linux/swap.h>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwapHeader {
+    /// `SWAPSPACE2` or the old `SWAP-SPACE`
+    pub magic: String,
+    /// Page size the swap area was created with. Found from the location of the magic
+    pub page_size: u64,
+    /// 1 for `SWAPSPACE2`. Old `SWAP-SPACE` areas have no header and use 0
+    pub version: u32,
+    /// Last page slot in the swap area
+    pub last_page: u32,
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+    /// Page slots mkswap marked as bad
+    pub bad_pages: Vec<u32>,
+    /// Swap uses the byte order of the system that created it
+    pub big_endian: bool,
+}
+
+/// Swap page with data in it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwapPage {
+    /// Page slot in the swap area. Slot 0 is the header
+    pub slot: u64,
+    /// Offset of the page in the guest disk
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Read the swap header at the start of a swap area. Returns None if there is no swap signature.
+/// `start` and `size` are the location of the swap area in the reader. Ex: a swap partition in an `OsReader` or 0 for a `LogicalVolumeReader`
+pub fn read_swap_header<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    size: u64,
+) -> Result<Option<SwapHeader>, CalfError> {
+    let max_page_size = 65536;
+    let mut data = vec![0; u64::min(max_page_size, size) as usize];
+    if let Err(err) = reader.seek(SeekFrom::Start(start)) {
+        return Err(CalfError::SeekFile {
+            offset: Offset::Guest(start),
+            source: err,
+        });
+    }
+    if let Err(err) = reader.read_exact(&mut data) {
+        return Err(CalfError::ReadFile {
+            offset: Offset::Guest(start),
+            source: err,
+        });
+    }
+
+    let page_sizes = [4096, 8192, 16384, 65536];
+    let magic_size = 10;
+    for page_size in page_sizes {
+        let Some(magic) = data.get(page_size - magic_size..page_size) else {
+            break;
+        };
+        if magic == b"SWAP-SPACE" {
+            // Old version 0 swap only has a bitmap of usable pages. The last page comes from the area size
+            let last_page = (size / page_size as u64).saturating_sub(1);
+            return Ok(Some(SwapHeader {
+                magic: String::from("SWAP-SPACE"),
+                page_size: page_size as u64,
+                version: 0,
+                last_page: last_page.min(u32::MAX as u64) as u32,
+                uuid: None,
+                label: None,
+                bad_pages: Vec::new(),
+                big_endian: false,
+            }));
+        }
+        if magic != b"SWAPSPACE2" {
+            continue;
+        }
+
+        let mut header = match parse_swap_header(&data[..page_size - magic_size]) {
+            Ok((_, result)) => result,
+            Err(err) => {
+                return Err(CalfError::parse(
+                    Structure::SwapHeader,
+                    Offset::Guest(start),
+                    &err,
+                ));
+            }
+        };
+        header.page_size = page_size as u64;
+        if (header.last_page as u64 + 1) * header.page_size > size {
+            warn!(
+                "[calf] Swap header at {start} says the last page is {} but the area only has {} pages",
+                header.last_page,
+                size / header.page_size
+            );
+        }
+        return Ok(Some(header));
+    }
+    Ok(None)
+}
+
+/// Parse the `SWAPSPACE2` header fields. The data ends before the magic
+fn parse_swap_header(data: &[u8]) -> nom::IResult<&[u8], SwapHeader> {
+    let boot_size: u16 = 1024;
+    let (input, _boot) = take(boot_size)(data)?;
+
+    // Version is always 1. Use it to find the byte order
+    let (_, version) = u32(Endianness::Little)(input)?;
+    let endian = if version == 1 || version.swap_bytes() != 1 {
+        Endianness::Little
+    } else {
+        Endianness::Big
+    };
+
+    let (input, version) = u32(endian)(input)?;
+    let (input, last_page) = u32(endian)(input)?;
+    let (input, bad_count) = u32(endian)(input)?;
+    let uuid_size: u8 = 16;
+    let (input, uuid) = take(uuid_size)(input)?;
+    let label_size: u8 = 16;
+    let (input, label) = take(label_size)(input)?;
+    let padding_size: u16 = 117 * 4;
+    let (mut input, _padding) = take(padding_size)(input)?;
+
+    // Same as the kernel's MAX_SWAP_BADPAGES: the list ends at the magic
+    let max_bad = (input.len() / 4) as u32;
+    if bad_count > max_bad {
+        warn!("[calf] Swap header has {bad_count} bad pages but only {max_bad} fit in the page");
+    }
+    let mut bad_pages = Vec::new();
+    for _ in 0..bad_count.min(max_bad) {
+        let (remaining, page) = u32(endian)(input)?;
+        bad_pages.push(page);
+        input = remaining;
+    }
+
+    let mut uuid_bytes = [0; 16];
+    uuid_bytes.copy_from_slice(uuid);
+    let label_end = label
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(label.len());
+    let label = extract_utf8_string(&label[..label_end]);
+
+    let header = SwapHeader {
+        magic: String::from("SWAPSPACE2"),
+        page_size: 0,
+        version,
+        last_page,
+        uuid: (uuid_bytes != [0; 16]).then(|| format_uuid(&uuid_bytes)),
+        label: (!label.is_empty()).then_some(label),
+        bad_pages,
+        big_endian: endian == Endianness::Big,
+    };
+    Ok((input, header))
+}
+
+/// Iterator over the swap pages that have data in them. Pages that are all zeros and bad pages are skipped
+pub struct SwapPages<R: Read + Seek> {
+    reader: R,
+    start: u64,
+    page_size: u64,
+    slot: u64,
+    last_slot: u64,
+    bad_pages: Vec<u32>,
+}
+
+impl<R: Read + Seek> SwapPages<R> {
+    /// Create an iterator for the swap area at `start` in the reader. Page offsets are `start` plus the slot times the page size
+    pub fn new(reader: R, start: u64, size: u64, header: &SwapHeader) -> SwapPages<R> {
+        // Do not read past the swap area if the header says it is larger
+        let last_slot = if let Some(pages) = size.checked_div(header.page_size) {
+            u64::min(header.last_page as u64, pages.saturating_sub(1))
+        } else {
+            warn!("[calf] Swap header has a page size of 0. No pages to read");
+            0
+        };
+        SwapPages {
+            reader,
+            start,
+            page_size: header.page_size,
+            slot: 0,
+            last_slot,
+            bad_pages: header.bad_pages.clone(),
+        }
+    }
+
+    /// Get the guest disk reader back
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_page(&mut self, offset: u64) -> Result<Vec<u8>, CalfError> {
+        let mut data = vec![0; self.page_size as usize];
+        if let Err(err) = self.reader.seek(SeekFrom::Start(offset)) {
+            return Err(CalfError::SeekFile {
+                offset: Offset::Guest(offset),
+                source: err,
+            });
+        }
+        if let Err(err) = self.reader.read_exact(&mut data) {
+            return Err(CalfError::ReadFile {
+                offset: Offset::Guest(offset),
+                source: err,
+            });
+        }
+        Ok(data)
+    }
+}
+
+impl<R: Read + Seek> Iterator for SwapPages<R> {
+    type Item = Result<SwapPage, CalfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.slot < self.last_slot {
+            self.slot += 1;
+            if self.bad_pages.contains(&(self.slot as u32)) {
+                continue;
+            }
+
+            let offset = self.start + self.slot * self.page_size;
+            let data = match self.read_page(offset) {
+                Ok(result) => result,
+                Err(err) => {
+                    // Stop after an error so callers do not loop forever
+                    self.slot = self.last_slot;
+                    return Some(Err(err));
+                }
+            };
+            if data.iter().all(|value| *value == 0) {
+                continue;
+            }
+            return Some(Ok(SwapPage {
+                slot: self.slot,
+                offset,
+                data,
+            }));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SwapPages, read_swap_header};
+    use crate::{
+        bootsector::boot::PartitionType,
+        calf::{CalfReader, CalfReaderAction, QcowInfo},
+        format::header::CalfHeader,
+    };
+    use std::{
+        fs::File,
+        io::{BufReader, Cursor},
+        path::PathBuf,
+    };
+
+    #[test]
+    fn test_swap_pages() {
+        let mut test_location = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_location.push("tests/test_data/swap/swap.qcow");
+
+        let reader = File::open(test_location.to_str().unwrap()).unwrap();
+        let buf = BufReader::new(reader);
+
+        let mut calf = CalfReader { fs: buf };
+        let info = QcowInfo {
+            header: calf.header().unwrap(),
+            level1_table: calf.level1_entries().unwrap(),
+        };
+        let mut os_reader = calf.os_reader(&info).unwrap();
+        let boot = os_reader.get_boot_info().unwrap();
+        let swap = &boot.partitions[0];
+        assert_eq!(swap.partition_type, PartitionType::LinuxSwap);
+
+        let header = os_reader.swap_header(swap).unwrap().unwrap();
+        assert_eq!(header.magic, "SWAPSPACE2");
+        assert_eq!(header.page_size, 4096);
+        assert_eq!(header.version, 1);
+        assert_eq!(header.last_page, 63);
+        assert_eq!(header.label.as_deref(), Some("swapspace"));
+        assert_eq!(
+            header.uuid.as_deref(),
+            Some("5a5a5a5a-1111-4222-8333-444444444444")
+        );
+        assert_eq!(header.bad_pages, vec![5]);
+        assert!(!header.big_endian);
+
+        let pages: Vec<_> = os_reader
+            .swap_pages(swap, &header)
+            .map(|page| page.unwrap())
+            .collect();
+        let slots: Vec<u64> = pages.iter().map(|page| page.slot).collect();
+        assert_eq!(slots, vec![3, 10, 63]);
+        assert_eq!(pages[0].offset, 2048 * 512 + 3 * 4096);
+        assert!(pages[1].data.starts_with(b"page 10"));
+    }
+
+    #[test]
+    fn test_swap_header_big_endian() {
+        let mut data = vec![0; 8192 * 4];
+        data[1024..1028].copy_from_slice(&1u32.to_be_bytes());
+        data[1028..1032].copy_from_slice(&3u32.to_be_bytes());
+        data[8182..8192].copy_from_slice(b"SWAPSPACE2");
+        data[8192 * 2] = 1;
+
+        let mut reader = Cursor::new(data);
+        let header = read_swap_header(&mut reader, 0, 8192 * 4).unwrap().unwrap();
+        assert!(header.big_endian);
+        assert_eq!(header.page_size, 8192);
+        assert_eq!(header.last_page, 3);
+        assert!(header.uuid.is_none());
+        assert!(header.label.is_none());
+
+        let pages: Vec<_> = SwapPages::new(reader, 0, 8192 * 4, &header)
+            .map(|page| page.unwrap().slot)
+            .collect();
+        assert_eq!(pages, vec![2]);
+
+        let mut reader = Cursor::new(vec![0; 4096]);
+        assert!(read_swap_header(&mut reader, 0, 4096).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_swap_header_crafted() {
+        let mut data = vec![0; 4096 * 4];
+        data[1024..1028].copy_from_slice(&1u32.to_le_bytes());
+        data[1028..1032].copy_from_slice(&3u32.to_le_bytes());
+        data[1032..1036].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[4086..4096].copy_from_slice(b"SWAPSPACE2");
+        data[4096 * 2] = 1;
+
+        let mut reader = Cursor::new(data);
+        let mut header = read_swap_header(&mut reader, 0, 4096 * 4).unwrap().unwrap();
+        assert_eq!(header.bad_pages.len(), 637);
+
+        header.page_size = 0;
+        let mut pages = SwapPages::new(reader, 0, 4096 * 4, &header);
+        assert!(pages.next().is_none());
+    }
+}
@@ -4,3 +4,8 @@ use base64::{Engine, engine::general_purpose};
 pub(crate) fn base64_encode_standard(data: &[u8]) -> String {
     general_purpose::STANDARD.encode(data)
 }
+
+/// Base64 decode data using the STANDARD engine. Returns None if the data is not valid base64
+pub(crate) fn base64_decode_standard(data: &str) -> Option<Vec<u8>> {
+    general_purpose::STANDARD.decode(data).ok()
+}
@@ -0,0 +1,45 @@
+/// Format 16 bytes as a mixed endian GUID. Ex: `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`
+pub(crate) fn format_guid(data: &[u8; 16]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        u16::from_le_bytes([data[4], data[5]]),
+        u16::from_le_bytes([data[6], data[7]]),
+        data[8],
+        data[9],
+        data[10],
+        data[11],
+        data[12],
+        data[13],
+        data[14],
+        data[15]
+    )
+}
+
+/// Format 16 bytes as a big endian UUID. Ex: `0b43d8e6-e877-460f-a713-ce9d80ec6904`
+pub(crate) fn format_uuid(data: &[u8; 16]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+        u16::from_be_bytes([data[4], data[5]]),
+        u16::from_be_bytes([data[6], data[7]]),
+        u16::from_be_bytes([data[8], data[9]]),
+        u64::from_be_bytes([
+            0, 0, data[10], data[11], data[12], data[13], data[14], data[15]
+        ])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_guid, format_uuid};
+
+    #[test]
+    fn test_format_guid() {
+        let test = [
+            40, 115, 42, 193, 31, 248, 210, 17, 186, 75, 0, 160, 201, 62, 201, 59,
+        ];
+        assert_eq!(format_guid(&test), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(format_uuid(&test), "28732ac1-1ff8-d211-ba4b-00a0c93ec93b");
+    }
+}
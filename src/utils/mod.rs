@@ -1,3 +1,4 @@
 pub(crate) mod encoding;
+pub(crate) mod guid;
 pub(crate) mod read;
 pub(crate) mod strings;
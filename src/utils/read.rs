@@ -1,4 +1,4 @@
-use crate::error::CalfError;
+use crate::error::{CalfError, Offset};
 use log::{error, warn};
 use std::io::{BufReader, Read, Seek, SeekFrom};
 
@@ -8,16 +8,22 @@ pub(crate) fn read_bytes<T: std::io::Read + std::io::Seek>(
     bytes: u64,
     fs: &mut BufReader<T>,
 ) -> Result<Vec<u8>, CalfError> {
-    if fs.seek(SeekFrom::Start(offset)).is_err() {
+    if let Err(err) = fs.seek(SeekFrom::Start(offset)) {
         error!("[calf] Could not seek to offset {offset}");
-        return Err(CalfError::SeekFile);
+        return Err(CalfError::SeekFile {
+            offset: Offset::Host(offset),
+            source: err,
+        });
     }
     let mut buff_size = vec![0u8; bytes as usize];
     let bytes_read = match fs.read(&mut buff_size) {
         Ok(result) => result,
         Err(err) => {
             error!("[calf] Could not read bytes: {err:?}");
-            return Err(CalfError::ReadFile);
+            return Err(CalfError::ReadFile {
+                offset: Offset::Host(offset),
+                source: err,
+            });
         }
     };
 
@@ -27,3 +33,31 @@ pub(crate) fn read_bytes<T: std::io::Read + std::io::Seek>(
 
     Ok(buff_size)
 }
+
+/// Read bytes from the QCOW file using an async reader. Unlike `read_bytes` a short read is an `UnexpectedEof` error
+#[cfg(feature = "tokio")]
+pub(crate) async fn read_bytes_async<T: tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin>(
+    offset: u64,
+    bytes: u64,
+    fs: &mut tokio::io::BufReader<T>,
+) -> Result<Vec<u8>, CalfError> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    if let Err(err) = fs.seek(SeekFrom::Start(offset)).await {
+        error!("[calf] Could not seek to offset {offset}");
+        return Err(CalfError::SeekFile {
+            offset: Offset::Host(offset),
+            source: err,
+        });
+    }
+    let mut buff_size = vec![0; bytes as usize];
+    if let Err(err) = fs.read_exact(&mut buff_size).await {
+        error!("[calf] Could not read {bytes} bytes at offset {offset}: {err:?}");
+        return Err(CalfError::ReadFile {
+            offset: Offset::Host(offset),
+            source: err,
+        });
+    }
+
+    Ok(buff_size)
+}
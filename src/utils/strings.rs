@@ -23,6 +23,23 @@ pub(crate) fn extract_utf8_string(data: &[u8]) -> String {
     }
 }
 
+/// Get a UTF16 little endian string from provided bytes data. Stops at the first NULL character
+pub(crate) fn extract_utf16_string(data: &[u8]) -> String {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|value| *value != 0)
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+/// Each 512 byte sector in the LVM and LUKS test fixtures starts with `<name>:<offset>.`
+#[cfg(test)]
+pub(crate) fn sector_tag(data: &[u8]) -> String {
+    let end = data.iter().position(|value| *value == b'.').unwrap();
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
 /// Get a UTF8 string from provided bytes data
 fn bytes_to_utf8_string(data: &[u8]) -> Result<String, FromUtf8Error> {
     let result = String::from_utf8(data.to_vec())?;
@@ -33,7 +33,7 @@ fn test_debian() {
     os_reader.seek(SeekFrom::Start(1048576 + 1024)).unwrap();
     let mut bytes = vec![0; 1024];
 
-    os_reader.read(&mut bytes).unwrap();
+    os_reader.read_exact(&mut bytes).unwrap();
     assert_eq!(
         bytes,
         [
@@ -84,9 +84,10 @@ fn test_debian() {
             continue;
         }
         let os_reader = calf.os_reader(&info).unwrap();
+        let part_reader = os_reader.partition_reader(&entry);
 
-        let test = BufReader::new(os_reader);
-        let mut ext4_reader = Ext4Reader::new(test, 4096, entry.offset_start).unwrap();
+        let test = BufReader::new(part_reader);
+        let mut ext4_reader = Ext4Reader::new(test, 4096, 0).unwrap();
 
         let block = ext4_reader.superblock().unwrap();
         if block.filesystem_id == "0b43d8e6-e877-460f-a713-ce9d80ec6904" {
@@ -175,7 +176,7 @@ fn walk_dir<T: std::io::Seek + std::io::Read>(
             // Read 15 bytes of every file
             let mut byte_reader = reader.reader(entry.inode).unwrap();
             let mut buf = [0; 15];
-            byte_reader.read(&mut buf).unwrap();
+            byte_reader.read_exact(&mut buf).unwrap();
             assert_ne!(buf, [0; 15]);
         }
     }